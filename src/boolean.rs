@@ -0,0 +1,510 @@
+//! Boolean operations between polygons.
+//!
+//! The operations work by overlaying the borders of both operands: every edge is split at every
+//! point where it crosses another edge, and then each resulting piece is kept only if the
+//! operation's result is "inside" on one of its sides and "outside" on the other. The kept pieces
+//! are finally linked back together into closed borders.
+
+use std::collections::HashMap;
+
+use crate::shapes::{FillRule, Line, LineMethods, Orientation, Point, Polygon, Universal};
+
+/// How far from a piece of border its sides get sampled, in polygon units. Features thinner than
+/// twice this can't be told apart from the border itself.
+const SIDE_PROBE: f64 = 1e-4;
+
+/// A straight piece of a border, stored in f64 so that intersections are computed precisely.
+#[derive(Copy, Clone)]
+struct Edge {
+    a: (f64, f64),
+    b: (f64, f64),
+}
+
+impl Edge {
+    fn min_x(&self) -> f64 {
+        self.a.0.min(self.b.0)
+    }
+    fn max_x(&self) -> f64 {
+        self.a.0.max(self.b.0)
+    }
+    fn min_y(&self) -> f64 {
+        self.a.1.min(self.b.1)
+    }
+    fn max_y(&self) -> f64 {
+        self.a.1.max(self.b.1)
+    }
+}
+
+fn to_f64(point: Point<Universal>) -> (f64, f64) {
+    (point.x() as f64, point.y() as f64)
+}
+
+/// Every border is treated as closed, the same way a fill closes an open path.
+fn edges_of(polygon: &Polygon<Universal>) -> Vec<Edge> {
+    border_edges(polygon.get_borders())
+}
+
+fn border_edges(borders: &[Line<Universal>]) -> Vec<Edge> {
+    borders
+        .iter()
+        .filter(|border| border.len() > 1)
+        .flat_map(|border| {
+            let closing = (border.first() != border.last()).then(|| Edge {
+                a: to_f64(border[border.len() - 1]),
+                b: to_f64(border[0]),
+            });
+            border
+                .windows(2)
+                .map(|w| Edge {
+                    a: to_f64(w[0]),
+                    b: to_f64(w[1]),
+                })
+                .chain(closing)
+                .filter(|edge| edge.a != edge.b)
+                .collect::<Vec<Edge>>()
+        })
+        .collect()
+}
+
+/// How many times the edges go around a point, positive for clockwise turns.
+fn winding(edges: &[Edge], (x, y): (f64, f64)) -> i32 {
+    edges
+        .iter()
+        .filter(|e| (e.a.1 > y) != (e.b.1 > y))
+        .filter(|e| x < e.a.0 + (y - e.a.1) * (e.b.0 - e.a.0) / (e.b.1 - e.a.1))
+        .map(|e| if e.b.1 > e.a.1 { 1 } else { -1 })
+        .sum()
+}
+
+/// Containment test against a set of edges, following the operand's fill rule.
+fn inside(edges: &[Edge], rule: FillRule, point: (f64, f64)) -> bool {
+    rule.is_inside(winding(edges, point))
+}
+
+fn cross(o: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+/// Parameter of `p` along `edge`, assuming `p` lies on its supporting line.
+fn param_along(edge: &Edge, p: (f64, f64)) -> f64 {
+    let (dx, dy) = (edge.b.0 - edge.a.0, edge.b.1 - edge.a.1);
+    ((p.0 - edge.a.0) * dx + (p.1 - edge.a.1) * dy) / (dx * dx + dy * dy)
+}
+
+/// Records in `splits` the points where edges `i` and `j` touch, together with their parameter
+/// along each edge. The point is computed once and shared, so both edges split at exactly the
+/// same coordinates.
+fn split_pair(edges: &[Edge], i: usize, j: usize, splits: &mut [Vec<(f64, (f64, f64))>]) {
+    let (e, f) = (edges[i], edges[j]);
+    if e.max_y() < f.min_y() || f.max_y() < e.min_y() {
+        return;
+    }
+
+    let r = (e.b.0 - e.a.0, e.b.1 - e.a.1);
+    let s = (f.b.0 - f.a.0, f.b.1 - f.a.1);
+    let denominator = r.0 * s.1 - r.1 * s.0;
+    let scale = (r.0 * r.0 + r.1 * r.1).sqrt() * (s.0 * s.0 + s.1 * s.1).sqrt();
+
+    if denominator.abs() <= 1e-12 * scale {
+        // Parallel edges only matter if they're collinear, in which case each one gets split at
+        // the other's endpoints so that the overlapping parts become identical pieces.
+        if cross(e.a, e.b, f.a).abs() > 1e-9 * scale {
+            return;
+        }
+        for (target, other, source) in [(i, e, f), (j, f, e)] {
+            for p in [source.a, source.b] {
+                let t = param_along(&other, p);
+                if t > 0.0 && t < 1.0 {
+                    splits[target].push((t, p));
+                }
+            }
+        }
+        return;
+    }
+
+    let qp = (f.a.0 - e.a.0, f.a.1 - e.a.1);
+    let t = (qp.0 * s.1 - qp.1 * s.0) / denominator;
+    let u = (qp.0 * r.1 - qp.1 * r.0) / denominator;
+    if !(0.0..=1.0).contains(&t) || !(0.0..=1.0).contains(&u) {
+        return;
+    }
+
+    // Touching an endpoint reuses that exact endpoint instead of a recomputed point
+    let point = if t == 0.0 {
+        e.a
+    } else if t == 1.0 {
+        e.b
+    } else if u == 0.0 {
+        f.a
+    } else if u == 1.0 {
+        f.b
+    } else {
+        (e.a.0 + t * r.0, e.a.1 + t * r.1)
+    };
+    if t > 0.0 && t < 1.0 {
+        splits[i].push((t, point));
+    }
+    if u > 0.0 && u < 1.0 {
+        splits[j].push((u, point));
+    }
+}
+
+/// Splits every edge at all the points where it meets any other edge (of either operand).
+fn split_edges(edges: &[Edge]) -> Vec<Edge> {
+    let mut splits: Vec<Vec<(f64, (f64, f64))>> = vec![Vec::new(); edges.len()];
+
+    // Sweep over x so that only edges with overlapping x ranges get compared
+    let mut order: Vec<usize> = (0..edges.len()).collect();
+    order.sort_unstable_by(|i, j| edges[*i].min_x().total_cmp(&edges[*j].min_x()));
+    for (k, i) in order.iter().enumerate() {
+        for j in order[k + 1..]
+            .iter()
+            .take_while(|j| edges[**j].min_x() <= edges[*i].max_x())
+        {
+            split_pair(edges, *i, *j, &mut splits);
+        }
+    }
+
+    edges
+        .iter()
+        .zip(splits.iter_mut())
+        .flat_map(|(edge, split)| {
+            split.sort_unstable_by(|p, q| p.0.total_cmp(&q.0));
+            let mut points: Vec<(f64, f64)> = Vec::with_capacity(split.len() + 2);
+            points.push(edge.a);
+            points.extend(split.iter().map(|(_, p)| *p));
+            points.push(edge.b);
+            points.dedup();
+            points
+                .windows(2)
+                .map(|w| Edge { a: w[0], b: w[1] })
+                .collect::<Vec<Edge>>()
+        })
+        .collect()
+}
+
+type PointKey = (u64, u64);
+
+fn key((x, y): (f64, f64)) -> PointKey {
+    (x.to_bits(), y.to_bits())
+}
+
+/// Links the kept pieces back into closed borders. Every point on the boundary of a region is
+/// touched by an even number of pieces, so walking unused pieces always returns to the start.
+fn link_edges(edges: &[Edge]) -> Vec<Line<Universal>> {
+    let mut incident: HashMap<PointKey, Vec<usize>> = HashMap::new();
+    for (i, edge) in edges.iter().enumerate() {
+        incident.entry(key(edge.a)).or_default().push(i);
+        incident.entry(key(edge.b)).or_default().push(i);
+    }
+
+    let to_point = |(x, y): (f64, f64)| Point::new_unchecked(x as Universal, y as Universal);
+    let mut used = vec![false; edges.len()];
+    let mut borders = Vec::new();
+
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let start = edges[first].a;
+        let mut current = edges[first].b;
+        let mut border: Line<Universal> = vec![to_point(start), to_point(current)];
+
+        while current != start {
+            let next = incident[&key(current)].iter().find(|i| !used[**i]).copied();
+            match next {
+                Some(i) => {
+                    used[i] = true;
+                    current = if edges[i].a == current {
+                        edges[i].b
+                    } else {
+                        edges[i].a
+                    };
+                    border.push(to_point(current));
+                }
+                None => break,
+            }
+        }
+
+        if border.len() > 3 {
+            borders.push(border);
+        }
+    }
+
+    borders
+}
+
+/// Splits `edges` where they cross and links back the pieces that have `in_result` on just one
+/// of their sides, which gives the borders of the region where it holds.
+fn trace(edges: &[Edge], in_result: impl Fn((f64, f64)) -> bool) -> Vec<Line<Universal>> {
+    let mut pieces = split_edges(edges);
+
+    // Pieces shared by several borders only count once
+    pieces.iter_mut().for_each(|edge| {
+        if key(edge.b) < key(edge.a) {
+            std::mem::swap(&mut edge.a, &mut edge.b);
+        }
+    });
+    pieces.sort_unstable_by_key(|edge| (key(edge.a), key(edge.b)));
+    pieces.dedup_by_key(|edge| (key(edge.a), key(edge.b)));
+
+    let kept: Vec<Edge> = pieces
+        .into_iter()
+        .filter(|edge| {
+            let (dx, dy) = (edge.b.0 - edge.a.0, edge.b.1 - edge.a.1);
+            // Sample just to the left and right of the piece's midpoint, at the same distance
+            // however long the piece is
+            let length = dx.hypot(dy);
+            let (nx, ny) = (-dy / length * SIDE_PROBE, dx / length * SIDE_PROBE);
+            let mid = ((edge.a.0 + edge.b.0) / 2.0, (edge.a.1 + edge.b.1) / 2.0);
+            let left = (mid.0 + nx, mid.1 + ny);
+            let right = (mid.0 - nx, mid.1 - ny);
+            in_result(left) != in_result(right)
+        })
+        .collect();
+
+    orient_borders(link_edges(&kept))
+}
+
+/// Resolves borders that cross themselves or each other into the simple borders of the region
+/// they go around clockwise, which is how loops turned inside out by offsetting get dropped.
+#[allow(dead_code)] // Only used by `offset`, which the viewer doesn't call
+pub(crate) fn positive_region(borders: &[Line<Universal>]) -> Vec<Line<Universal>> {
+    let edges = border_edges(borders);
+    trace(&edges, |p| winding(&edges, p) > 0)
+}
+
+/// Makes outer borders go clockwise and holes (borders inside an odd number of others) go
+/// counterclockwise, which is what the nonzero fill rule needs to leave the holes empty.
+pub(crate) fn orient_borders(mut borders: Vec<Line<Universal>>) -> Vec<Line<Universal>> {
+    // The midpoint of a border's first segment can't be on any other border, since no two
+    // borders share a piece
+    let probes: Vec<Point<Universal>> = borders
+        .iter()
+        .map(|b| Point::new_unchecked((b[0].x() + b[1].x()) / 2.0, (b[0].y() + b[1].y()) / 2.0))
+        .collect();
+    let depths: Vec<usize> = probes
+        .iter()
+        .enumerate()
+        .map(|(i, probe)| {
+            borders
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.encloses(*probe))
+                .count()
+        })
+        .collect();
+
+    for (border, depth) in borders.iter_mut().zip(depths) {
+        let wanted = if depth % 2 == 0 {
+            Orientation::Clockwise
+        } else {
+            Orientation::CounterClockwise
+        };
+        if border.orientation() != wanted {
+            border.reverse();
+        }
+    }
+    borders
+}
+
+/// Computes the borders of the region where `keep(inside_a, inside_b)` holds. The borders that
+/// come out never overlap, so the result is the same under either fill rule.
+fn overlay(
+    a: &Polygon<Universal>,
+    b: &Polygon<Universal>,
+    keep: fn(bool, bool) -> bool,
+) -> Vec<Line<Universal>> {
+    let (edges_a, edges_b) = (edges_of(a), edges_of(b));
+    let (rule_a, rule_b) = (a.get_fill_rule(), b.get_fill_rule());
+    trace(&[edges_a.as_slice(), edges_b.as_slice()].concat(), |p| {
+        keep(inside(&edges_a, rule_a, p), inside(&edges_b, rule_b, p))
+    })
+}
+
+/// The borders of the region the polygon fills under its fill rule, which neither cross nor
+/// overlap.
+pub(crate) fn filled_region(polygon: &Polygon<Universal>) -> Vec<Line<Universal>> {
+    let edges = edges_of(polygon);
+    let rule = polygon.get_fill_rule();
+    trace(&edges, |p| inside(&edges, rule, p))
+}
+
+/// The polygon's borders closed and oriented so that outer borders go clockwise and holes the
+/// other way, whatever its fill rule.
+pub(crate) fn oriented_borders(polygon: &Polygon<Universal>) -> Vec<Line<Universal>> {
+    let borders: Vec<Line<Universal>> = polygon
+        .get_borders()
+        .iter()
+        .filter(|b| b.len() > 2)
+        .map(|b| {
+            let mut b = b.clone();
+            if !b.is_closed() {
+                b.push(b[0]);
+            }
+            b
+        })
+        .collect();
+    match polygon.get_fill_rule() {
+        FillRule::EvenOdd => orient_borders(borders),
+        FillRule::NonZero if polygon.orientation() == Orientation::CounterClockwise => borders
+            .into_iter()
+            .map(|mut b| {
+                b.reverse();
+                b
+            })
+            .collect(),
+        FillRule::NonZero => borders,
+    }
+}
+
+impl Polygon<Universal> {
+    /// Returns the part of this polygon that's also covered by `other`, keeping this polygon's
+    /// colors, layer and id.
+    pub fn intersection(&self, other: &Polygon<Universal>) -> Polygon<Universal> {
+        self.new_copy_attributes(overlay(self, other, |a, b| a && b))
+    }
+}
+
+// Only clip paths use the overlay while parsing; the rest are for deriving shapes from the car
+// (panels minus their cutouts, merged parts) and have no caller in the viewer.
+#[allow(dead_code)]
+impl Polygon<Universal> {
+    /// Returns the region covered by either polygon, keeping this polygon's colors, layer and id.
+    pub fn union(&self, other: &Polygon<Universal>) -> Polygon<Universal> {
+        self.new_copy_attributes(overlay(self, other, |a, b| a || b))
+    }
+
+    /// Returns the part of this polygon that isn't covered by `other`, e.g. a panel minus the
+    /// window cut out of it.
+    pub fn difference(&self, other: &Polygon<Universal>) -> Polygon<Universal> {
+        self.new_copy_attributes(overlay(self, other, |a, b| a && !b))
+    }
+
+    /// Returns the region covered by exactly one of the polygons, keeping this polygon's
+    /// attributes.
+    pub fn xor(&self, other: &Polygon<Universal>) -> Polygon<Universal> {
+        self.new_copy_attributes(overlay(self, other, |a, b| a != b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::{polygon, rectangle};
+
+    fn square(x: Universal, y: Universal, size: Universal) -> Polygon<Universal> {
+        polygon(vec![rectangle(x, y, x + size, y + size)], FillRule::NonZero)
+    }
+
+    /// A 10x10 square with a 2x2 hole, made with the even-odd rule.
+    fn donut() -> Polygon<Universal> {
+        polygon(
+            vec![
+                rectangle(0.0, 0.0, 10.0, 10.0),
+                rectangle(2.0, 2.0, 4.0, 4.0),
+            ],
+            FillRule::EvenOdd,
+        )
+    }
+
+    /// Results come out with holes going the other way, so the signed area is the covered area.
+    fn assert_area(polygon: &Polygon<Universal>, expected: Universal) {
+        let area = polygon.signed_area();
+        assert!((area - expected).abs() < 1e-3, "{} != {}", area, expected);
+    }
+
+    #[test]
+    fn overlapping_squares() {
+        let (a, b) = (square(0.0, 0.0, 10.0), square(5.0, 5.0, 10.0));
+        assert_area(&a.union(&b), 175.0);
+        assert_area(&a.intersection(&b), 25.0);
+        assert_area(&a.difference(&b), 75.0);
+        assert_area(&a.xor(&b), 150.0);
+    }
+
+    #[test]
+    fn coincident_borders() {
+        let a = square(0.0, 0.0, 10.0);
+        assert_area(&a.union(&a), 100.0);
+        assert_area(&a.intersection(&a), 100.0);
+        assert!(a.difference(&a).get_borders().is_empty());
+        // Sharing only an edge
+        assert_area(&a.union(&square(10.0, 0.0, 10.0)), 200.0);
+        assert!(a
+            .intersection(&square(10.0, 0.0, 10.0))
+            .get_borders()
+            .is_empty());
+    }
+
+    #[test]
+    fn holed_operands() {
+        let donut = donut();
+        // What the hole leaves uncovered
+        assert_area(&square(0.0, 0.0, 10.0).difference(&donut), 4.0);
+        // Covering the hole fills it
+        assert_area(&donut.union(&square(1.0, 1.0, 4.0)), 100.0);
+        // Half of the hole falls in the square
+        let half = donut.intersection(&square(3.0, 0.0, 7.0));
+        assert_area(&half, 47.0);
+        assert_eq!(half.get_borders().len(), 1);
+        // The hole stays a hole, going counterclockwise
+        let holed = donut.intersection(&square(1.0, 1.0, 8.0));
+        assert_area(&holed, 60.0);
+        assert_eq!(holed.get_borders().len(), 2);
+    }
+
+    #[test]
+    fn nonzero_hole() {
+        let mut hole = rectangle(2.0, 2.0, 4.0, 4.0);
+        hole.reverse();
+        let donut = polygon(
+            vec![rectangle(0.0, 0.0, 10.0, 10.0), hole],
+            FillRule::NonZero,
+        );
+        assert_area(&square(0.0, 0.0, 10.0).difference(&donut), 4.0);
+        // Both borders going the same way leave no hole under nonzero
+        let filled = polygon(
+            vec![
+                rectangle(0.0, 0.0, 10.0, 10.0),
+                rectangle(2.0, 2.0, 4.0, 4.0),
+            ],
+            FillRule::NonZero,
+        );
+        assert!(square(0.0, 0.0, 10.0)
+            .difference(&filled)
+            .get_borders()
+            .is_empty());
+    }
+
+    #[test]
+    fn multi_border_operands() {
+        // Two separate squares crossed by a bar
+        let pair = polygon(
+            vec![
+                rectangle(0.0, 0.0, 4.0, 4.0),
+                rectangle(6.0, 0.0, 10.0, 4.0),
+            ],
+            FillRule::NonZero,
+        );
+        let bar = polygon(vec![rectangle(-1.0, 1.0, 11.0, 3.0)], FillRule::NonZero);
+        let both = pair.intersection(&bar);
+        assert_area(&both, 16.0);
+        assert_eq!(both.get_borders().len(), 2);
+        assert_area(&pair.union(&bar), 32.0 + 24.0 - 16.0);
+        let cut = pair.difference(&bar);
+        assert_area(&cut, 16.0);
+        assert_eq!(cut.get_borders().len(), 4);
+    }
+
+    #[test]
+    fn thin_slivers_on_long_edges() {
+        // A 1000 long and 0.01 thick overlap, far thinner than its pieces are long
+        let a = polygon(vec![rectangle(0.0, 0.0, 1000.0, 1.0)], FillRule::NonZero);
+        let b = polygon(vec![rectangle(0.0, 0.99, 1000.0, 2.0)], FillRule::NonZero);
+        assert_area(&a.intersection(&b), 10.0);
+        assert_area(&a.union(&b), 2000.0);
+        assert_area(&a.difference(&b), 990.0);
+    }
+}
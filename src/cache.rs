@@ -0,0 +1,227 @@
+//! Caches the parsed [`Car`] on disk so that the svg doesn't have to be parsed and its curves
+//! flattened again on every start.
+//!
+//! Each cache file stores the key it was made with: the version of the cache's format, a hash of
+//! the svg's content plus the curve tolerance and scene size used for the flattening. If any of
+//! them changes, the svg is parsed again and the cache overwritten.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::car::{self, Car};
+use crate::shapes::{Mask, Universal};
+
+/// On-disk format of the cache.
+#[derive(Debug, Clone, Copy)]
+pub enum CacheFormat {
+    /// Human readable, handy to inspect what the parser produced
+    Json,
+    /// Compact and fast to load
+    Binary,
+}
+
+impl CacheFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            CacheFormat::Json => "json",
+            CacheFormat::Binary => "bin",
+        }
+    }
+}
+
+/// Version of what gets cached. Bump it whenever the parser or the serialized types change, even
+/// if the cached structs keep their shape, so that caches made by older versions get parsed again
+/// instead of being served stale.
+const FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct CacheKey {
+    format_version: u32,
+    svg_hash: u64,
+    curve_tolerance: Universal,
+    scene_size: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    key: CacheKey,
+    /// The car without its masks
+    car: Car,
+    /// Every distinct mask once, since serde would write (and read back) a copy of a shared mask
+    /// for each polygon using it
+    masks: Vec<Rc<Mask>>,
+    /// Index in `masks` of each polygon's mask
+    mask_indices: Vec<Option<usize>>,
+}
+
+impl CacheEntry {
+    fn new(key: CacheKey, car: Car) -> CacheEntry {
+        let mut masks: Vec<Rc<Mask>> = Vec::new();
+        let mut indices: HashMap<*const Mask, usize> = HashMap::new();
+        let mask_indices = car
+            .iter()
+            .map(|polygon| {
+                polygon.get_mask().map(|mask| {
+                    *indices.entry(Rc::as_ptr(mask)).or_insert_with(|| {
+                        masks.push(Rc::clone(mask));
+                        masks.len() - 1
+                    })
+                })
+            })
+            .collect();
+        CacheEntry {
+            key,
+            car,
+            masks,
+            mask_indices,
+        }
+    }
+
+    /// The car with its masks shared again between the polygons that use them.
+    fn into_car(self) -> Result<Car> {
+        let CacheEntry {
+            mut car,
+            masks,
+            mask_indices,
+            ..
+        } = self;
+        if mask_indices.len() != car.len() {
+            return Err(anyhow!(
+                "Cache con máscaras que no calzan con los polígonos"
+            ));
+        }
+        for (polygon, index) in car.iter_mut().zip(mask_indices) {
+            let mask = index
+                .map(|i| {
+                    masks
+                        .get(i)
+                        .cloned()
+                        .ok_or_else(|| anyhow!("Cache con máscara {} inexistente", i))
+                })
+                .transpose()?;
+            polygon.set_mask(mask);
+        }
+        Ok(car)
+    }
+}
+
+/// 64 bit FNV-1a. Unlike std's `DefaultHasher`, it's guaranteed to stay the same between Rust
+/// versions, which matters for hashes that get stored on disk.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn cache_path(svg_path: &str, cache_dir: &str, format: CacheFormat) -> Result<PathBuf> {
+    let stem = Path::new(svg_path)
+        .file_stem()
+        .ok_or_else(|| anyhow!("'{}' no es una ruta a un archivo", svg_path))?;
+    Ok(Path::new(cache_dir)
+        .join(stem)
+        .with_extension(format.extension()))
+}
+
+fn read_cache(path: &Path, format: CacheFormat) -> Result<CacheEntry> {
+    let bytes = fs::read(path)?;
+    Ok(match format {
+        CacheFormat::Json => serde_json::from_slice(&bytes)?,
+        CacheFormat::Binary => bincode::deserialize(&bytes)?,
+    })
+}
+
+fn write_cache(path: &Path, format: CacheFormat, entry: &CacheEntry) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let bytes = match format {
+        CacheFormat::Json => serde_json::to_vec(entry)?,
+        CacheFormat::Binary => bincode::serialize(entry)?,
+    };
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Same as `car::parse_svg()`, but reuses the car cached in `cache_dir` when it was made by this
+/// version from the same svg content with the same `scene_size` and `tolerance`. A missing or
+/// unreadable cache just means parsing again, and failing to write it only gets reported.
+pub fn load_car(
+    path: &str,
+    scene_size: u32,
+    tolerance: Universal,
+    cache_dir: &str,
+    format: CacheFormat,
+) -> Result<Car> {
+    let content = fs::read(path).with_context(|| format!("No se pudo leer '{}'", path))?;
+    let key = CacheKey {
+        format_version: FORMAT_VERSION,
+        svg_hash: fnv1a(&content),
+        curve_tolerance: tolerance,
+        scene_size,
+    };
+    let cache = cache_path(path, cache_dir, format)?;
+
+    match read_cache(&cache, format) {
+        Ok(entry) if entry.key == key => match entry.into_car() {
+            Ok(car) => {
+                println!("Car loaded from cache '{}'", cache.display());
+                return Ok(car);
+            }
+            Err(err) => println!(
+                "Cache '{}' not usable ({}), parsing svg",
+                cache.display(),
+                err
+            ),
+        },
+        Ok(_) => println!("Cache '{}' is outdated, parsing svg again", cache.display()),
+        Err(err) => println!(
+            "Cache '{}' not usable ({}), parsing svg",
+            cache.display(),
+            err
+        ),
+    }
+
+    let entry = CacheEntry::new(key, car::parse_svg(path, scene_size, tolerance)?);
+    write_cache(&cache, format, &entry)
+        .unwrap_or_else(|err| println!("Couldn't write cache '{}': {}", cache.display(), err));
+    entry.into_car()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG: &str = r##"<svg viewBox="0 0 100 100" xmlns="http://www.w3.org/2000/svg">
+<mask id="m"><rect/><path id="mp" d="m 0,0 50,0 0,50 z" style="fill:#ffffff;stroke:none"/></mask>
+<g id="0">
+<path id="a" mask="url(#m)" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+<path id="b" mask="url(#m)" d="m 20,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+<path id="c" d="m 40,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+</g></svg>"##;
+
+    #[test]
+    fn masks_stay_shared_through_the_cache() {
+        let dir = std::env::temp_dir().join(format!("cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let svg = dir.join("masks.svg");
+        fs::write(&svg, SVG).unwrap();
+        let (svg, dir) = (svg.to_str().unwrap(), dir.to_str().unwrap());
+
+        for format in [CacheFormat::Json, CacheFormat::Binary] {
+            // The first load parses and writes the cache, the second one reads it back
+            for _ in 0..2 {
+                let car = load_car(svg, 1000, 0.8, dir, format).unwrap();
+                assert_eq!(car.len(), 3);
+                let (a, b) = (car[0].get_mask().unwrap(), car[1].get_mask().unwrap());
+                assert!(Rc::ptr_eq(a, b));
+                assert_eq!(a.len(), 1);
+                assert!(car[2].get_mask().is_none());
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+//! Targets that the rasterizing functions can plot pixels onto.
+//!
+//! The [`Canvas`] wraps the sdl_wrapper ScreenContextManager and remembers the color of every
+//! pixel drawn during the current frame, which is what makes blending a semi transparent pixel
+//! over whatever is already on screen possible.
+use anyhow::Result;
+use sdl_wrapper::{EventPollIterator, ScreenContextManager};
+
+use crate::constants::{WINDOW_HEIGHT, WINDOW_WIDTH};
+use crate::shapes::{Color, Framebuffer};
+
+/// Anything the scanline and bresenham functions can draw on.
+pub trait PixelTarget {
+    fn plot_pixel(&mut self, x: Framebuffer, y: Framebuffer);
+}
+
+fn index(x: Framebuffer, y: Framebuffer) -> usize {
+    (y * WINDOW_WIDTH + x) as usize
+}
+
+pub struct Canvas {
+    screen: ScreenContextManager,
+
+    /// Color that pixels get plotted with
+    color: Color,
+
+    /// Color of every pixel in the current frame
+    pixels: Vec<Color>,
+
+    /// If set, every plotted pixel is blended over the frame with this per-pixel alpha
+    alpha: Option<AlphaMask>,
+}
+
+impl Canvas {
+    pub fn new(title: &str) -> Result<Canvas> {
+        let black = Color::new(0.0, 0.0, 0.0)?;
+        Ok(Canvas {
+            screen: ScreenContextManager::new(title, WINDOW_WIDTH, WINDOW_HEIGHT)?,
+            color: black,
+            pixels: vec![black; (WINDOW_WIDTH * WINDOW_HEIGHT) as usize],
+            alpha: None,
+        })
+    }
+
+    pub fn get_events(&mut self) -> EventPollIterator {
+        self.screen.get_events()
+    }
+
+    pub fn clear(&mut self, gray: f32) {
+        self.screen.clear(gray);
+        let gray = Color::new(gray, gray, gray).unwrap_or(self.color);
+        self.pixels.iter_mut().for_each(|p| *p = gray);
+    }
+
+    pub fn clear_with_color(&mut self, color: Color) {
+        self.screen.clear_with_rgb(color.r(), color.g(), color.b());
+        self.pixels.iter_mut().for_each(|p| *p = color);
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+        self.screen.set_color(color.r(), color.g(), color.b());
+    }
+
+    /// Sets (or removes with `None`) the per-pixel alpha used for the following plots.
+    pub fn set_alpha(&mut self, alpha: Option<AlphaMask>) {
+        self.alpha = alpha;
+    }
+
+    pub async fn present(&mut self) {
+        self.screen
+            .present()
+            .await
+            .unwrap_or_else(|err| println!("Error while presenting screen: {}", err));
+    }
+}
+
+impl PixelTarget for Canvas {
+    fn plot_pixel(&mut self, x: Framebuffer, y: Framebuffer) {
+        let i = index(x, y);
+        match self.alpha.as_ref().map(|mask| mask.values[i]) {
+            None => {
+                self.pixels[i] = self.color;
+                self.screen.plot_pixel(x, y);
+            }
+            Some(alpha) if alpha <= 0.0 => (),
+            Some(alpha) => {
+                let blended = self.color.blend(self.pixels[i], alpha.min(1.0));
+                self.pixels[i] = blended;
+                self.screen.set_color(blended.r(), blended.g(), blended.b());
+                self.screen.plot_pixel(x, y);
+                self.screen
+                    .set_color(self.color.r(), self.color.g(), self.color.b());
+            }
+        }
+    }
+}
+
+/// A framebuffer sized grid of alpha values. Plotting on it writes the current `value`.
+pub struct AlphaMask {
+    values: Vec<f32>,
+    value: f32,
+}
+
+impl AlphaMask {
+    /// Creates a fully transparent mask.
+    pub fn transparent() -> AlphaMask {
+        AlphaMask {
+            values: vec![0.0; (WINDOW_WIDTH * WINDOW_HEIGHT) as usize],
+            value: 1.0,
+        }
+    }
+
+    pub fn set_value(&mut self, value: f32) {
+        self.value = value;
+    }
+}
+
+impl PixelTarget for AlphaMask {
+    fn plot_pixel(&mut self, x: Framebuffer, y: Framebuffer) {
+        self.values[index(x, y)] = self.value;
+    }
+}
@@ -7,7 +7,8 @@ use anyhow::{anyhow, Context, Result};
 use impls::impls;
 use itertools::Itertools;
 use std::collections::HashMap;
-use std::f32::consts::PI;
+use std::fs;
+use std::rc::Rc;
 use svg::node::element::{
     path::{Command, Data, Parameters, Position},
     tag::{self, Type},
@@ -15,60 +16,111 @@ use svg::node::element::{
 use svg::node::Attributes;
 use svg::parser::{Event, Parser};
 
-use crate::constants::{COS15, POLYLINE_N, SCENE_CENTER, SEN15};
-use crate::shapes::{Color, Line, LineMethods, Point, Polygon, Universal};
+use crate::collision;
+use crate::curves::{Curve, Path};
+use crate::shapes::{
+    BoundingBox, Color, FillRule, Line, LineMethods, Mask, Point, Polygon, Transform2D, Universal,
+};
 
 pub type Car = Vec<Polygon<Universal>>;
 
 /// Function made to specifically parse the "car.svg" file and return a "Car" object (which is just
-/// Vec<Polygon>.
-pub fn parse_svg(path: &str, scene_size: u32, distance: Universal) -> Result<Car> {
+/// Vec<Polygon>. The curves are kept in the polygons' paths, and their borders are flattened
+/// from them with `tolerance`.
+pub fn parse_svg(path: &str, scene_size: u32, tolerance: Universal) -> Result<Car> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("No se pudo leer '{}'", path))?;
+    parse_svg_content(&content, scene_size, tolerance)
+}
+
+/// Like [`parse_svg`], for an svg that's already been read into `content`.
+pub fn parse_svg_content(content: &str, scene_size: u32, tolerance: Universal) -> Result<Car> {
     if !impls!(Universal: From<f32>) {
         return Err(anyhow!("tipo Universal no es convertible a f32"));
     }
 
-    let mut content = String::new();
-    let (parser, mut car, scaling) = init_svg::<f32>(path, scene_size, &mut content)?;
+    let (parser, mut car, placement) = init_svg(content, scene_size)?;
 
     let mut layer: i32 = 0;
+    let mut definitions = Definitions::default();
+    let mut groups: Vec<Group> = Vec::new();
 
     for event in parser {
         match event {
+            // <clipPath> y <mask> guardan sus figuras en vez de agregarlas al carro
+            Event::Tag(tag::ClipPath, Type::Start, attributes) => {
+                definitions.open(Definition::ClipPath, &attributes)?;
+            }
+            Event::Tag(tag::Mask, Type::Start, attributes) => {
+                definitions.open(Definition::Mask, &attributes)?;
+            }
+            Event::Tag(tag::ClipPath | tag::Mask, Type::End, _) => {
+                definitions.close();
+            }
+            // Los grupos dentro de una definición no son layers
+            Event::Tag(tag::Group, _, _) if definitions.is_open() => (),
+
             // Group = layers
-            Event::Tag(tag::Group, Type::Start, attributes) => {
+            Event::Tag(tag::Group, Type::Start, mut attributes) => {
                 let id = attributes
                     .get("id")
                     .ok_or_else(|| anyhow!("group no trae id (layer sin número)"))?;
                 println!("Layer: '{}'", id);
+                let previous_layer = layer;
                 layer = id
                     .parse()
                     .context("id de 'group' (layer) no se pudo parsear a i32")?;
+                groups.push(Group {
+                    visibility: inherited_visibility(&groups).child(&attributes)?,
+                    references: References::new(&mut attributes, &definitions)?,
+                    previous_layer,
+                });
+            }
+            Event::Tag(tag::Group, Type::End, _) => {
+                if let Some(group) = groups.pop() {
+                    layer = group.previous_layer;
+                }
             }
 
             // Path = líneas/curvas
-            Event::Tag(tag::Path, Type::Empty | Type::Start, attributes) => {
+            Event::Tag(tag::Path, Type::Empty | Type::Start, mut attributes) => {
                 let id = attributes
                     .get("id")
                     .ok_or_else(|| anyhow!("path no trae id"))?;
                 println!("Path id: {}", id);
-                let poly_path = approximate_path(attributes, layer, scaling, distance)?;
-                car.push(poly_path);
+                let references = References::new(&mut attributes, &definitions)?;
+                let hidden = inherited_visibility(&groups)
+                    .child(&attributes)?
+                    .is_hidden();
+                let mut poly_path = parse_path(attributes, layer, &placement, tolerance)?;
+                poly_path.set_hidden(hidden);
+                definitions.place(poly_path, &groups, references, &mut car)?;
             }
-            Event::Tag(tag::Circle, Type::Empty | Type::Start, attributes) => {
+            Event::Tag(tag::Circle, Type::Empty | Type::Start, mut attributes) => {
                 let id = attributes
                     .get("id")
                     .ok_or_else(|| anyhow!("circle no trae id"))?;
                 println!("Circle id: {}", id);
-                let poly_circle = approximate_circle(attributes, layer, scaling, distance)?;
-                car.push(poly_circle);
+                let references = References::new(&mut attributes, &definitions)?;
+                let hidden = inherited_visibility(&groups)
+                    .child(&attributes)?
+                    .is_hidden();
+                let mut poly_circle = parse_circle(attributes, layer, &placement, tolerance)?;
+                poly_circle.set_hidden(hidden);
+                definitions.place(poly_circle, &groups, references, &mut car)?;
             }
-            Event::Tag(tag::Ellipse, Type::Empty | Type::Start, attributes) => {
+            Event::Tag(tag::Ellipse, Type::Empty | Type::Start, mut attributes) => {
                 let id = attributes
                     .get("id")
                     .ok_or_else(|| anyhow!("ellipse no trae id"))?;
                 println!("Ellipse id: {}", id);
-                let poly_ellipse = approximate_ellipse(attributes, layer, scaling, distance)?;
-                car.push(poly_ellipse);
+                let references = References::new(&mut attributes, &definitions)?;
+                let hidden = inherited_visibility(&groups)
+                    .child(&attributes)?
+                    .is_hidden();
+                let mut poly_ellipse = parse_ellipse(attributes, layer, &placement, tolerance)?;
+                poly_ellipse.set_hidden(hidden);
+                definitions.place(poly_ellipse, &groups, references, &mut car)?;
             }
             // unhandled
             Event::Tag(tag, Type::Start | Type::Empty, _) => {
@@ -81,7 +133,7 @@ pub fn parse_svg(path: &str, scene_size: u32, distance: Universal) -> Result<Car
     }
 
     println!(
-        "Car was approximated using {} vertices! ({} polygons)",
+        "Car was flattened into {} vertices! ({} polygons)",
         count_vertices(&car),
         car.len(),
     );
@@ -89,31 +141,61 @@ pub fn parse_svg(path: &str, scene_size: u32, distance: Universal) -> Result<Car
     Ok(car)
 }
 
-pub fn rotate_car(car: &mut Car, amount: i32) {
-    let (cos_theta, sen_theta) = if amount == 1 || amount == -1 {
-        (amount.abs() as f32 * COS15, amount as f32 * SEN15)
-    } else {
-        (
-            (amount as f32 * PI / 12.0).cos(),
-            (amount as f32 * PI / 12.0).sin(),
-        )
-    };
+pub fn transform_car(car: &mut Car, transform: &Transform2D) {
     for polygon in car.iter_mut() {
-        for border in polygon.get_borders_mut().iter_mut() {
-            for point in border.iter_mut() {
-                *point = Point::new_unchecked(
-                    point.x() * cos_theta - SCENE_CENTER * cos_theta + SCENE_CENTER
-                        - point.y() * sen_theta
-                        + SCENE_CENTER * sen_theta,
-                    point.x() * sen_theta - SCENE_CENTER * sen_theta + point.y() * cos_theta
-                        - SCENE_CENTER * cos_theta
-                        + SCENE_CENTER,
-                )
-            }
-        }
+        polygon.transform(transform);
     }
 }
 
+/// Every visible polygon that contains `point`, in the order they're painted (so the last one
+/// is the one on top).
+pub fn polygons_at<'a>(
+    car: impl IntoIterator<Item = &'a Polygon<Universal>>,
+    point: Point<Universal>,
+) -> Vec<&'a Polygon<Universal>> {
+    let mut hits: Vec<&Polygon<Universal>> = car
+        .into_iter()
+        .filter(|p| !p.is_hidden() && p.contains(point))
+        .collect();
+    // Layers are painted from the lowest up, and within a layer in the car's order
+    hits.sort_by_key(|p| p.get_layer());
+    hits
+}
+
+/// Box around every polygon of the car, `None` if it has no points.
+pub fn bounding_box(car: &Car) -> Option<BoundingBox> {
+    car.iter()
+        .filter_map(|p| p.bounding_box())
+        .reduce(|a, b| a.union(&b))
+}
+
+/// Smallest convex border around every polygon of the car, going clockwise.
+#[allow(dead_code)] // For collision checks, which the viewer doesn't do
+pub fn convex_hull(car: &Car) -> Line<Universal> {
+    collision::convex_hull(car.iter().flat_map(|p| p.get_borders()).flatten().copied())
+}
+
+/// Center of mass of the car, with every polygon weighing as much as its area. If the car
+/// covers no area at all, it's the average of its vertices.
+pub fn centroid(car: &Car) -> Point<Universal> {
+    let (area, x, y) = car
+        .iter()
+        .filter_map(|p| Some((p.area(), p.centroid()?)))
+        .fold((0.0, 0.0, 0.0), |(area, x, y), (a, c)| {
+            (area + a, x + a * c.x(), y + a * c.y())
+        });
+    if area > 0.0 {
+        return Point::new_unchecked(x / area, y / area);
+    }
+
+    let (sum_x, sum_y, n) = car
+        .iter()
+        .flat_map(|p| p.get_borders())
+        .flatten()
+        .fold((0.0, 0.0, 0), |(x, y, n), p| (x + p.x(), y + p.y(), n + 1));
+    Point::new_unchecked(sum_x / n.max(1) as Universal, sum_y / n.max(1) as Universal)
+}
+
 pub fn get_lowest_layer(car: &Car) -> i32 {
     car.iter()
         .map(|p| p.get_layer())
@@ -128,13 +210,243 @@ pub fn get_top_layer(car: &Car) -> i32 {
         .expect("car is empty in get_top_layer() function")
 }
 
+/// Prints the measurements of every polygon, one per line, for checking the parsed car.
+pub fn print_metrics(car: &Car) {
+    println!(
+        "id\tlayer\tarea\tsigned_area\tcentroid\torientation\tperimeter\tself_intersecting\t\
+         self_crossing_borders\topen_borders"
+    );
+    for polygon in car.iter() {
+        let borders = polygon.get_borders();
+        println!(
+            "{}\t{}\t{}\t{}\t{:?}\t{:?}\t{}\t{}\t{}\t{}",
+            polygon.id(),
+            polygon.get_layer(),
+            polygon.area(),
+            polygon.signed_area(),
+            polygon.centroid().map(|c| (c.x(), c.y())),
+            polygon.orientation(),
+            polygon.perimeter(),
+            polygon.is_self_intersecting(),
+            borders.iter().filter(|b| b.is_self_intersecting()).count(),
+            borders.iter().filter(|b| !b.is_closed()).count(),
+        );
+    }
+}
+
 pub fn count_vertices(car: &Car) -> usize {
     car.iter().flat_map(|p| p.get_borders()).flatten().count()
 }
 
+#[derive(Clone, Copy)]
+enum Definition {
+    ClipPath,
+    Mask,
+}
+
+/// Keeps the `<clipPath>` and `<mask>` definitions found so far, as well as the one currently
+/// being parsed (if any).
+#[derive(Default)]
+struct Definitions {
+    clip_paths: HashMap<String, Polygon<Universal>>,
+    masks: HashMap<String, Rc<Mask>>,
+
+    /// Definition whose figures are being parsed right now, and its id
+    open: Option<(Definition, String)>,
+    open_figures: Vec<Polygon<Universal>>,
+}
+
+impl Definitions {
+    fn open(&mut self, definition: Definition, attributes: &Attributes) -> Result<()> {
+        let id = attributes
+            .get("id")
+            .ok_or_else(|| anyhow!("clipPath/mask no trae id"))?;
+        println!("Definition id: {}", id);
+        self.open = Some((definition, id.to_string()));
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        let figures = std::mem::take(&mut self.open_figures);
+        match self.open.take() {
+            // Las figuras de un clipPath se toman como una sola región
+            Some((Definition::ClipPath, id)) => {
+                let mut clip = Polygon::new(0, id.clone());
+                clip.set_borders(
+                    figures
+                        .into_iter()
+                        .flat_map(|f| f.get_borders().clone())
+                        .collect(),
+                );
+                self.clip_paths.insert(id, clip);
+            }
+            Some((Definition::Mask, id)) => {
+                self.masks.insert(id, Rc::new(figures));
+            }
+            None => (),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    /// Adds a parsed figure to the definition being parsed, or to the car after applying its
+    /// clip paths and mask: its own and those of the `groups` it's in. A mask on a group gets
+    /// applied to each figure of the group, so only one mask can reach a figure.
+    fn place(
+        &mut self,
+        mut polygon: Polygon<Universal>,
+        groups: &[Group],
+        references: References,
+        car: &mut Car,
+    ) -> Result<()> {
+        if self.is_open() {
+            self.open_figures.push(polygon);
+            return Ok(());
+        }
+
+        let chain = groups
+            .iter()
+            .map(|g| &g.references)
+            .chain(std::iter::once(&references));
+        for clip_id in chain.clone().filter_map(|r| r.clip_path.as_ref()) {
+            let clip = self.clip_paths.get(clip_id).ok_or_else(|| {
+                anyhow!("'{}' usa clip-path '{}' no definido", polygon.id(), clip_id)
+            })?;
+            polygon = polygon.intersection(clip);
+        }
+        let mut masks = chain.filter_map(|r| r.mask.as_ref());
+        if let Some(mask_id) = masks.next() {
+            if masks.next().is_some() {
+                return Err(anyhow!(
+                    "'{}' queda bajo más de una mask, lo cual no está soportado",
+                    polygon.id()
+                ));
+            }
+            let mask = self
+                .masks
+                .get(mask_id)
+                .ok_or_else(|| anyhow!("'{}' usa mask '{}' no definido", polygon.id(), mask_id))?;
+            polygon.set_mask(Some(Rc::clone(mask)));
+        }
+
+        // Clipping can leave nothing to draw
+        if !polygon.get_borders().is_empty() {
+            car.push(polygon);
+        }
+        Ok(())
+    }
+}
+
+/// A `<g>` whose children are being parsed.
+struct Group {
+    /// Visibility its children inherit
+    visibility: Visibility,
+    /// Clip path and mask that apply to all of its children
+    references: References,
+    /// Layer there was before it was opened
+    previous_layer: i32,
+}
+
+/// The clip path and mask that an element references with `url(#id)`.
+struct References {
+    clip_path: Option<String>,
+    mask: Option<String>,
+}
+
+impl References {
+    /// Reads the references of an element. Figures inside a definition don't need a style, so
+    /// they get a default one (svg's default fill is black) when they don't bring one.
+    fn new(attributes: &mut Attributes, definitions: &Definitions) -> Result<References> {
+        if definitions.is_open() {
+            attributes
+                .entry("style".to_string())
+                .or_insert_with(|| "fill:#000000;stroke:none".into());
+        }
+        Ok(References {
+            clip_path: parse_url_reference(attributes.get("clip-path").map(|v| v.as_ref()))?,
+            mask: parse_url_reference(attributes.get("mask").map(|v| v.as_ref()))?,
+        })
+    }
+}
+
+/// Parses attributes in the form `url(#id)`, returning the id.
+fn parse_url_reference(value: Option<&str>) -> Result<Option<String>> {
+    match value.map(str::trim) {
+        None | Some("none") => Ok(None),
+        Some(reference) => reference
+            .strip_prefix("url(#")
+            .and_then(|r| r.strip_suffix(')'))
+            .map(|id| Some(id.to_string()))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Referencia mal formateada, se esperaba 'url(#id)': {}",
+                    reference
+                )
+            }),
+    }
+}
+
+/// Whether an element gets rendered, according to its own `display`/`visibility` and those of the
+/// groups it's inside of.
+#[derive(Clone, Copy)]
+struct Visibility {
+    /// `display:none` hides an element along with all of its children
+    displayed: bool,
+    /// `visibility` is inherited, but children can override it
+    visible: bool,
+}
+
+impl Visibility {
+    /// Visibility of an element with `attributes` that's inside of `self`. The properties can be
+    /// given either as attributes or inside `style`, the latter taking precedence.
+    fn child(&self, attributes: &Attributes) -> Result<Visibility> {
+        let style = match attributes.get("style") {
+            Some(style) => parse_style_properties(style)?,
+            None => HashMap::new(),
+        };
+        let property = |name: &str| {
+            style
+                .get(name)
+                .map(|v| v.trim().to_string())
+                .or_else(|| attributes.get(name).map(|v| v.trim().to_string()))
+        };
+
+        Ok(Visibility {
+            displayed: self.displayed && property("display").as_deref() != Some("none"),
+            visible: match property("visibility").as_deref() {
+                Some("hidden" | "collapse") => false,
+                Some("visible") => true,
+                _ => self.visible,
+            },
+        })
+    }
+
+    fn is_hidden(&self) -> bool {
+        !self.displayed || !self.visible
+    }
+}
+
+fn inherited_visibility(groups: &[Group]) -> Visibility {
+    groups.last().map(|g| g.visibility).unwrap_or(Visibility {
+        displayed: true,
+        visible: true,
+    })
+}
+
 struct Style {
     stroke: Option<Color>,
     fill: Option<Color>,
+    fill_rule: Option<FillRule>,
+}
+
+fn parse_fill_rule(rule: &str) -> Result<FillRule> {
+    match rule.trim() {
+        "nonzero" => Ok(FillRule::NonZero),
+        "evenodd" => Ok(FillRule::EvenOdd),
+        other => Err(anyhow!("fill-rule desconocido: '{}'", other)),
+    }
 }
 
 /// Parses color from a style attribute in the svg, which can either be in the form
@@ -145,8 +457,8 @@ fn parse_color(color: &str) -> Result<Option<Color>> {
     }
 }
 
-fn parse_style(style: &str) -> Result<Style> {
-    let style: HashMap<String, String> = style
+fn parse_style_properties(style: &str) -> Result<HashMap<String, String>> {
+    style
         .split(';')
         .map(|s| {
             s.split(':')
@@ -160,7 +472,11 @@ fn parse_style(style: &str) -> Result<Style> {
                 "No se pudo separar algún key:value pair dentro de atributo de style: {}",
                 style
             )
-        })?;
+        })
+}
+
+fn parse_style(style: &str) -> Result<Style> {
+    let style = parse_style_properties(style)?;
 
     Ok(Style {
         stroke: parse_color(
@@ -173,6 +489,10 @@ fn parse_style(style: &str) -> Result<Style> {
                 .get("fill")
                 .ok_or_else(|| anyhow!("atributo 'style' no trae 'fill': {:?}", style))?,
         )?,
+        fill_rule: style
+            .get("fill-rule")
+            .map(|rule| parse_fill_rule(rule))
+            .transpose()?,
     })
 }
 
@@ -190,348 +510,237 @@ fn init_polygon(attributes: &Attributes, layer: i32) -> Result<Polygon<Universal
 
     poly.set_stroke_color(style.stroke);
     poly.set_fill_color(style.fill);
-    Ok(poly)
-}
 
-fn approx_cubic_bezier_aux(
-    segment: &[Universal],
-    anchor: Point<Universal>,
-    n: u32,
-) -> Result<Line<Universal>> {
-    let p0 = anchor;
-    let p1 = Point::new_unchecked(segment[0] + p0.x(), segment[1] + p0.y());
-    let p2 = Point::new_unchecked(segment[2] + p0.x(), segment[3] + p0.y());
-    let p3 = Point::new_unchecked(segment[4] + p0.x(), segment[5] + p0.y());
-    //println!(
-    //    "Approximating curve:\n\tp0: {:?}\tp1: {:?}\n\tp2: {:?}\tp3: {:?}",
-    //    p0, p1, p2, p3
-    //);
-    let b = |t: Universal| {
-        Point::<Universal>::new(
-            (1.0 - t).powi(3) * p0.x()
-                + 3.0 * (1.0 - t).powi(2) * t * p1.x()
-                + 3.0 * (1.0 - t) * t * t * p2.x()
-                + t.powi(3) * p3.x(),
-            (1.0 - t).powi(3) * p0.y()
-                + 3.0 * (1.0 - t).powi(2) * t * p1.y()
-                + 3.0 * (1.0 - t) * t * t * p2.y()
-                + t.powi(3) * p3.y(),
-        )
+    // El fill-rule puede venir dentro de style o como atributo propio
+    let fill_rule = match (style.fill_rule, attributes.get("fill-rule")) {
+        (Some(rule), _) => rule,
+        (None, Some(rule)) => parse_fill_rule(rule)?,
+        (None, None) => FillRule::default(),
     };
-
-    (0..n)
-        .map(|t| b((t as Universal) / n as Universal))
-        .collect::<Result<Line<Universal>>>()
-}
-
-fn approximate_cubic_beziers(
-    points: &Parameters,
-    anchor: Point<Universal>,
-    distance: Universal,
-) -> Result<Line<Universal>> {
-    let mut beziers: Vec<Line<Universal>> = Vec::new();
-    let mut p0 = anchor;
-    for segment in points.chunks(6) {
-        let length = approx_cubic_bezier_aux(segment, p0, POLYLINE_N)?.euclidean_length();
-        //println!("Length: {}", length);
-        let n = length / distance;
-        let approximation = approx_cubic_bezier_aux(segment, p0, n.round() as u32)?;
-        //println!("Approximation: {:?}", approximation);
-        beziers.push(approximation);
-        p0 = Point::<Universal>::new(p0.x() + segment[4], p0.y() + segment[5])?;
-    }
-
-    Ok(beziers.concat())
+    poly.set_fill_rule(fill_rule);
+    Ok(poly)
 }
 
-fn get_anchor(borders: &[Line<Universal>], command: &Command) -> Result<Point<Universal>> {
-    Ok(*(borders.last().ok_or_else(|| { anyhow!( "Llamado comando '{:?}' sin haber inicializado algún Line dentro de borders", command) })?
-            .last().ok_or_else(|| { anyhow!("Llamado comando '{:?}' sin haber agregado ningún punto previo a último borde (osea sin comando 'm')", command)})?))
+/// Point reached by moving (dx, dy) from `anchor`.
+fn relative(anchor: Point<Universal>, dx: Universal, dy: Universal) -> Result<Point<Universal>> {
+    Point::<Universal>::new(anchor.x() + dx, anchor.y() + dy)
 }
 
-fn get_last_border_mut<'a>(
-    borders: &'a mut Vec<Line<Universal>>,
-    command: &Command,
-) -> Result<&'a mut Line<Universal>> {
-    borders.last_mut().ok_or_else(|| {
+/// The path that the commands after an 'm' extend.
+fn last_path_mut<'a>(paths: &'a mut [Path], command: &Command) -> Result<&'a mut Path> {
+    paths.last_mut().ok_or_else(|| {
         anyhow!(
-            "Llamado comando '{:?}' sin haber inicializado algún Line dentro de borders",
+            "Llamado comando '{:?}' sin haber agregado ningún punto previo (osea sin comando 'm')",
             command
         )
     })
 }
 
-fn approximate_straight_lines(
-    command: &Command,
-    borders: &[Line<Universal>],
-) -> Result<Line<Universal>> {
-    match command {
-        l @ Command::Line(Position::Relative, params) => {
-            //println!("l command");
-            (params.len() % 2 == 0)
-                .then(|| ())
-                .ok_or_else(|| anyhow!("Parámetros de comando 'l' no son múltiplos de 2"))?;
-            let mut anchor = get_anchor(borders, l)?;
-            params
-                .chunks_exact(2)
-                .map(|p| {
-                    anchor = Point::<Universal>::new(anchor.x() + p[0], anchor.y() + p[1])?;
-                    Ok(anchor)
-                })
-                .collect::<Result<Line<Universal>>>()
-        }
-        h @ Command::HorizontalLine(Position::Relative, params) => {
-            //println!("h command");
-            let mut anchor = get_anchor(borders, h)?;
-            params
-                .iter()
-                .map(|p| {
-                    anchor = Point::<Universal>::new(anchor.x() + p, anchor.y())?;
-                    Ok(anchor)
-                })
-                .collect::<Result<Line<Universal>>>()
-        }
-        v @ Command::VerticalLine(Position::Relative, params) => {
-            //println!("v command");
-            let mut anchor = get_anchor(borders, v)?;
-            params
-                .iter()
-                .map(|p| {
-                    anchor = Point::<Universal>::new(anchor.x(), anchor.y() + p)?;
-                    Ok(anchor)
-                })
-                .collect::<Result<Line<Universal>>>()
-        }
-        c => {
-            return Err(anyhow!(
-                "?!?! LINE ERROR: Unhandled command: {:?} (this shouldn't be possible)",
-                c
-            ))
-        }
+/// Makes sure a command brings its parameters in groups of `size`.
+fn check_parameters(command: &Command, params: &Parameters, size: usize) -> Result<()> {
+    if !params.chunks_exact(size).remainder().is_empty() {
+        return Err(anyhow!(
+            "Parámetros de comando '{:?}' no son múltiplos de {}",
+            command,
+            size
+        ));
     }
+    Ok(())
 }
 
-fn approximate_path<T: Into<Universal>>(
-    attributes: Attributes,
-    layer: i32,
-    scaling: T,
-    distance: Universal,
-) -> Result<Polygon<Universal>> {
-    let data = attributes
-        .get("d")
-        .ok_or_else(|| anyhow!("path sin atributo 'd'"))?;
-    let data =
-        Data::parse(data).context("En approximate_path() no se pudo parsear el atributo 'd'.")?;
-
-    let mut borders = Vec::<Line<Universal>>::new();
+/// Reads the path's data into one `Path` per subpath, keeping its curves. Only relative
+/// commands are supported.
+fn parse_path_data(data: &Data) -> Result<Vec<Path>> {
+    let mut paths: Vec<Path> = Vec::new();
 
     for command in data.iter() {
         match command {
             m @ Command::Move(Position::Relative, params) => {
-                //println!( "m command:\tx: {}\ty: {}\tborders.len()={}", params[0], params[1], borders.len());
-                let new_point = match borders.len() {
-                    0 => Point::<Universal>::new(params[0], params[1]),
-                    _ => {
-                        let anchor = get_anchor(&borders, m)?;
-                        Point::<Universal>::new(anchor.x() + params[0], anchor.y() + params[1])
-                    }
-                }?;
-                borders.push(vec![new_point]);
-                if params.len() > 2 {
-                    let mut extension = approximate_straight_lines(
-                        &Command::Line(
-                            Position::Relative,
-                            Parameters::from({
-                                let mut vec = Vec::with_capacity(params.len() - 2);
-                                vec.extend_from_slice(&params[2..]);
-                                vec
-                            }),
-                        ),
-                        &borders,
-                    )?;
-                    get_last_border_mut(&mut borders, m)?.append(&mut extension);
+                check_parameters(m, params, 2)?;
+                // El primer 'm' es absoluto, los demás se miden desde donde terminó el anterior
+                let start = match paths.last() {
+                    None => Point::<Universal>::new(params[0], params[1])?,
+                    Some(path) => relative(path.end(), params[0], params[1])?,
+                };
+                let mut path = Path::new(start);
+                // Los pares que siguen al primero son líneas
+                for p in params[2..].chunks_exact(2) {
+                    let to = relative(path.end(), p[0], p[1])?;
+                    path.push(Curve::Line { to });
+                }
+                paths.push(path);
+            }
+            l @ Command::Line(Position::Relative, params) => {
+                check_parameters(l, params, 2)?;
+                let path = last_path_mut(&mut paths, l)?;
+                for p in params.chunks_exact(2) {
+                    let to = relative(path.end(), p[0], p[1])?;
+                    path.push(Curve::Line { to });
                 }
             }
-            line @ (Command::Line(Position::Relative, _)
-            | Command::HorizontalLine(Position::Relative, _)
-            | Command::VerticalLine(Position::Relative, _)) => {
-                let mut extension = approximate_straight_lines(line, &borders)?;
-                get_last_border_mut(&mut borders, line)?.append(&mut extension);
+            h @ Command::HorizontalLine(Position::Relative, params) => {
+                let path = last_path_mut(&mut paths, h)?;
+                for dx in params.iter() {
+                    let to = relative(path.end(), *dx, 0.0)?;
+                    path.push(Curve::Line { to });
+                }
+            }
+            v @ Command::VerticalLine(Position::Relative, params) => {
+                let path = last_path_mut(&mut paths, v)?;
+                for dy in params.iter() {
+                    let to = relative(path.end(), 0.0, *dy)?;
+                    path.push(Curve::Line { to });
+                }
+            }
+            q @ Command::QuadraticCurve(Position::Relative, params) => {
+                check_parameters(q, params, 4)?;
+                let path = last_path_mut(&mut paths, q)?;
+                for p in params.chunks_exact(4) {
+                    let anchor = path.end();
+                    path.push(Curve::Quadratic {
+                        control: relative(anchor, p[0], p[1])?,
+                        to: relative(anchor, p[2], p[3])?,
+                    });
+                }
             }
             c @ Command::CubicCurve(Position::Relative, params) => {
-                //println!("c command");
-                let anchor = get_anchor(&borders, c)?;
-                get_last_border_mut(&mut borders, c)?
-                    .append(&mut approximate_cubic_beziers(params, anchor, distance)?);
+                check_parameters(c, params, 6)?;
+                let path = last_path_mut(&mut paths, c)?;
+                for p in params.chunks_exact(6) {
+                    let anchor = path.end();
+                    path.push(Curve::Cubic {
+                        control1: relative(anchor, p[0], p[1])?,
+                        control2: relative(anchor, p[2], p[3])?,
+                        to: relative(anchor, p[4], p[5])?,
+                    });
+                }
+            }
+            a @ Command::EllipticalArc(Position::Relative, params) => {
+                check_parameters(a, params, 7)?;
+                let path = last_path_mut(&mut paths, a)?;
+                for p in params.chunks_exact(7) {
+                    let anchor = path.end();
+                    let to = relative(anchor, p[5], p[6])?;
+                    path.push(Curve::svg_arc(
+                        anchor,
+                        (p[0], p[1]),
+                        p[2],
+                        p[3] != 0.0,
+                        p[4] != 0.0,
+                        to,
+                    ));
+                }
             }
             z @ Command::Close => {
-                //println!("z command");
                 // Recordar que en un borde que se "completa" (meaning it forms a loop) su punto
                 // inicial y el final son el mismo punto
-                let border_start = *(borders.last().ok_or_else(|| { anyhow!( "Llamado comando '{:?}' sin haber inicializado algún Line dentro de borders", z) })?
-                    .first().ok_or_else(|| { anyhow!("Llamado comando '{:?}' sin haber agregado ningún punto previo a último borde (osea sin comando 'm')", z)})?);
-
-                get_last_border_mut(&mut borders, z)?.push(border_start);
+                last_path_mut(&mut paths, z)?.close();
             }
             c => return Err(anyhow!("!!!! PATH ERROR: Unhandled command: {:?}", c)),
         }
     }
 
-    let mut path_poly = init_polygon(&attributes, layer)?;
-    path_poly.set_borders(borders);
+    Ok(paths)
+}
 
-    //println!("Path finished\n");
-    path_poly.scale(scaling.into())
+/// Builds the polygon out of its paths, which get placed in the scene with `placement`, and
+/// flattens them with `tolerance` into its borders.
+fn polygon_from_paths(
+    mut polygon: Polygon<Universal>,
+    paths: Vec<Path>,
+    placement: &Transform2D,
+    tolerance: Universal,
+) -> Result<Polygon<Universal>> {
+    polygon.set_paths(Some(Rc::new(paths)));
+    polygon.transform(placement);
+    let polygon = polygon.flattened(tolerance);
+    for point in polygon.get_borders().iter().flatten() {
+        Point::<Universal>::new(point.x(), point.y())?;
+    }
+    Ok(polygon)
 }
 
-fn approximate_circle<T: Into<Universal>>(
+fn parse_path(
     attributes: Attributes,
     layer: i32,
-    scaling: T,
-    distance: Universal,
+    placement: &Transform2D,
+    tolerance: Universal,
 ) -> Result<Polygon<Universal>> {
-    let mut circle_poly = init_polygon(&attributes, layer)?;
+    let data = attributes
+        .get("d")
+        .ok_or_else(|| anyhow!("path sin atributo 'd'"))?;
+    let data = Data::parse(data).context("En parse_path() no se pudo parsear el atributo 'd'.")?;
+
+    let paths = parse_path_data(&data)?;
+    polygon_from_paths(
+        init_polygon(&attributes, layer)?,
+        paths,
+        placement,
+        tolerance,
+    )
+}
+
+/// Reads a numeric attribute of `element` (circle/ellipse).
+fn parse_number(attributes: &Attributes, element: &str, name: &str) -> Result<Universal> {
+    attributes
+        .get(name)
+        .ok_or_else(|| anyhow!("{} no trae '{}'", element, name))?
+        .parse::<f32>()
+        .with_context(|| format!("'{}' de {} no se pudo parsear a f32", name, element))
+}
 
+fn parse_circle(
+    attributes: Attributes,
+    layer: i32,
+    placement: &Transform2D,
+    tolerance: Universal,
+) -> Result<Polygon<Universal>> {
     let center = Point::<Universal>::new(
-        attributes
-            .get("cx")
-            .ok_or_else(|| anyhow!("circle no trae 'cx'"))?
-            .parse::<f32>()?,
-        attributes
-            .get("cy")
-            .ok_or_else(|| anyhow!("circle no trae 'cy'"))?
-            .parse::<f32>()?,
+        parse_number(&attributes, "circle", "cx")?,
+        parse_number(&attributes, "circle", "cy")?,
     )?;
-
-    let radius: f32 = attributes
-        .get("r")
-        .ok_or_else(|| anyhow!("circle no trae 'r'"))?
-        .parse::<f32>()?;
-
-    let perimeter: f32 = 2.0 * radius * PI;
-    let num_points: u32 = (perimeter / distance).round() as u32;
-    let theta: f32 = 2.0 * PI / num_points as f32;
+    let radius = parse_number(&attributes, "circle", "r")?;
 
     // circles can assume a single border
-    let mut border: Line<Universal> = (0..num_points)
-        .map(|i| {
-            Point::<Universal>::new(
-                center.x() + (theta * i as f32).cos() * radius,
-                center.y() + (theta * i as f32).sin() * radius,
-            )
-        })
-        .collect::<Result<Line<Universal>>>()?;
-
-    // Agregar punto inical al final para completar círculo
-    border.push(border[0]);
-
-    circle_poly.add_border(border);
-
-    circle_poly.scale(scaling.into())
+    polygon_from_paths(
+        init_polygon(&attributes, layer)?,
+        vec![Path::ellipse(center, radius, radius)],
+        placement,
+        tolerance,
+    )
 }
 
-fn approximate_ellipse<T: Into<Universal>>(
+fn parse_ellipse(
     attributes: Attributes,
     layer: i32,
-    scaling: T,
-    distance: Universal,
+    placement: &Transform2D,
+    tolerance: Universal,
 ) -> Result<Polygon<Universal>> {
-    let mut ellipse_poly = init_polygon(&attributes, layer)?;
-
     let center = Point::<Universal>::new(
-        attributes
-            .get("cx")
-            .ok_or_else(|| anyhow!("ellipse no trae 'cx'"))?
-            .parse::<f32>()?,
-        attributes
-            .get("cy")
-            .ok_or_else(|| anyhow!("ellipse no trae 'cy'"))?
-            .parse::<f32>()?,
+        parse_number(&attributes, "ellipse", "cx")?,
+        parse_number(&attributes, "ellipse", "cy")?,
     )?;
-
-    let radius_x: f32 = attributes
-        .get("rx")
-        .ok_or_else(|| anyhow!("ellipse no trae 'rx'"))?
-        .parse::<f32>()?;
-    let radius_y: f32 = attributes
-        .get("ry")
-        .ok_or_else(|| anyhow!("ellipse no trae 'ry'"))?
-        .parse::<f32>()?;
-
-    let perimeter: f32 = 2.0 * PI * ((radius_x.powi(2) + radius_y.powi(2)) / 2.0).sqrt();
-    let num_points: u32 = (perimeter / distance).round() as u32;
-
-    // La siguiente sección de código adapta al siguiente pseudocódigo:
-    //
-    //dp(t) = sqrt( (r1*sin(t))^2 + (r2*cos(t))^2)
-    //circ = sum(dp(t), t=0..2*Pi step 0.0001)
-    //
-    //n = 20
-    //
-    //nextPoint = 0
-    //run = 0.0
-    //for t=0..2*Pi step 0.0001
-    //    if n*run/circ >= nextPoint then
-    //        set point (r1*cos(t), r2*sin(t))
-    //        nextPoint = nextPoint + 1
-    //    next
-    //    run = run + dp(t)
-    //next
-    //
-    // Ref: https://stackoverflow.com/questions/6972331/how-can-i-generate-a-set-of-points-evenly-distributed-along-the-perimeter-of-an
-
-    let dp = |t: f32| ((radius_x * t.sin()).powi(2) + (radius_y * t.cos()).powi(2)).sqrt();
-    let step: f32 = 0.0001;
-    let circ: f32 = (0..(2.0 * PI / step).trunc() as u32)
-        .map(|i| dp(i as f32 * step))
-        .sum();
-
-    let mut run: f32 = 0.0;
-    let mut next_point = 0.0;
-
-    let mut border: Line<Universal> = Line::new();
-    for t in 0..(2.0 * PI / step).trunc() as u32 {
-        let theta: f32 = t as f32 * step;
-        if num_points as f32 * run / circ >= next_point {
-            next_point += distance;
-            border.push(Point::<Universal>::new(
-                center.x() + (theta).cos() * radius_x,
-                center.y() + (theta).sin() * radius_y,
-            )?);
-        }
-        run += dp(theta);
-    }
-
-    // Agregar punto inicial al final para aproximar círculo
-    border.push(border[0]);
-
-    // println!(
-    //     "Ellipse:\n\tcx:{}\tcy:{}\n\trx:{}\try:{}\n\tApprox: {:?}",
-    //     center.x(),
-    //     center.y(),
-    //     radius_x,
-    //     radius_y,
-    //     border,
-    // );
-
-    ellipse_poly.add_border(border);
-
-    ellipse_poly.scale(scaling.into())
+    let radius_x = parse_number(&attributes, "ellipse", "rx")?;
+    let radius_y = parse_number(&attributes, "ellipse", "ry")?;
+
+    polygon_from_paths(
+        init_polygon(&attributes, layer)?,
+        vec![Path::ellipse(center, radius_x, radius_y)],
+        placement,
+        tolerance,
+    )
 }
 
 /// This function parse the initial lines of the "car.svg" file, ignoring anything before the <svg>
 /// tag, but making sure that <svg> is the first tag in the file and that it does exist. When found
-/// it obtains the "viewBox" size and scales it by the "scale" factor. Returns a Car object that
-/// still holds no polygons but has its dimensions defined.
-fn init_svg<'l, T: Into<Universal> + From<f32>>(
-    path: &str,
-    scene_size: u32,
-    content: &'l mut String,
-) -> Result<(Parser<'l>, Car, T)> {
+/// it obtains the "viewBox" and the transform that places it on the scene, with its min corner
+/// at the origin and its size scaled to `scene_size`. Returns a Car object that still holds no
+/// polygons.
+fn init_svg(content: &str, scene_size: u32) -> Result<(Parser<'_>, Car, Transform2D)> {
     // init car with dummy values
     let car: Car = Vec::new();
 
-    let mut parser: Parser = svg::open(path, content)?;
+    let mut parser: Parser = svg::read(content)?;
 
     // Ignoramos las cosas antes de <svg>, pero si no se encuentra <svg> so si se encuentra otra
     // etiqueta antes retornamos error.
@@ -558,8 +767,10 @@ fn init_svg<'l, T: Into<Universal> + From<f32>>(
                     })?;
 
                 return if viewbox.len() == 4 && viewbox[2] == viewbox[3] {
-                    let scaling = T::from(scene_size as f32 / viewbox[2]);
-                    Ok((parser, car, scaling))
+                    let scaling = scene_size as f32 / viewbox[2];
+                    let placement = Transform2D::translate(-viewbox[0], -viewbox[1])
+                        .then(&Transform2D::scale(scaling, scaling));
+                    Ok((parser, car, placement))
                 } else {
                     Err(anyhow!(
                         "viewBox leído de .svg no es cuadrado o tiene más de dos dimensiones: {:?}",
@@ -584,3 +795,177 @@ fn init_svg<'l, T: Into<Universal> + From<f32>>(
     // Si nos quedamos sin elementos
     Err(anyhow!("No se encontró elemento <svg>"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG: &str = r##"<svg viewBox="0 0 100 100" xmlns="http://www.w3.org/2000/svg">
+<clipPath id="left"><path id="left_half" d="m 0,0 50,0 0,100 -50,0 z"/></clipPath>
+<mask id="fade">
+<path id="white" d="m 0,0 50,0 0,50 -50,0 z" style="fill:#ffffff;stroke:none"/>
+<path id="gray" d="m 50,0 50,0 0,50 -50,0 z" style="fill:#808080;stroke:none"/>
+<path id="default" d="m 0,50 100,0 0,50 -100,0 z"/>
+</mask>
+<g id="0">
+<path id="clipped" clip-path="url(#left)" d="m 20,10 60,0 0,10 -60,0 z" style="fill:#ff0000;stroke:none"/>
+<path id="outside" clip-path="url(#left)" d="m 60,60 10,0 0,10 -10,0 z" style="fill:#ff0000;stroke:none"/>
+<path id="masked" mask="url(#fade)" d="m 20,30 60,0 0,10 -60,0 z" style="fill:#ff0000;stroke:none"/>
+</g>
+<g id="1" clip-path="url(#left)" mask="url(#fade)">
+<path id="in_group" d="m 20,70 60,0 0,10 -60,0 z" style="fill:#ff0000;stroke:none"/>
+</g>
+<g id="2">
+<path id="after_group" d="m 20,80 60,0 0,10 -60,0 z" style="fill:#ff0000;stroke:none"/>
+</g>
+</svg>"##;
+
+    fn find<'a>(car: &'a Car, id: &str) -> &'a Polygon<Universal> {
+        car.iter().find(|p| p.id() == id).unwrap()
+    }
+
+    fn max_x(polygon: &Polygon<Universal>) -> Universal {
+        polygon
+            .get_borders()
+            .iter()
+            .flatten()
+            .map(|p| p.x())
+            .fold(Universal::MIN, Universal::max)
+    }
+
+    #[test]
+    fn clip_paths_cut_figures_and_groups() {
+        let car = parse_svg_content(SVG, 1000, 8.0).unwrap();
+        let ids: Vec<&str> = car.iter().map(|p| p.id().as_str()).collect();
+        // Definitions aren't drawn, and neither is what clipping leaves empty
+        assert_eq!(ids, ["clipped", "masked", "in_group", "after_group"]);
+
+        for id in ["clipped", "in_group"] {
+            let polygon = find(&car, id);
+            assert!((max_x(polygon) - 500.0).abs() < 1e-3, "{}", id);
+            let min_x = polygon
+                .get_borders()
+                .iter()
+                .flatten()
+                .map(|p| p.x())
+                .fold(Universal::MAX, Universal::min);
+            assert!((min_x - 200.0).abs() < 1e-3, "{}", id);
+        }
+        // The group's clip path ends with it
+        assert!((max_x(find(&car, "after_group")) - 800.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn masks_keep_the_luminance_of_their_figures() {
+        let car = parse_svg_content(SVG, 1000, 8.0).unwrap();
+        let mask = find(&car, "masked").get_mask().unwrap();
+        assert!(Rc::ptr_eq(mask, find(&car, "in_group").get_mask().unwrap()));
+        assert!(find(&car, "clipped").get_mask().is_none());
+        assert!(find(&car, "after_group").get_mask().is_none());
+
+        // Figures without a style are filled black, like svg does
+        let luminances: Vec<f32> = mask
+            .iter()
+            .map(|figure| figure.get_fill_color().unwrap().luminance())
+            .collect();
+        assert_eq!(luminances.len(), 3);
+        assert!((luminances[0] - 1.0).abs() < 1e-3);
+        assert!((luminances[1] - 128.0 / 255.0).abs() < 1e-3);
+        assert_eq!(luminances[2], 0.0);
+    }
+
+    #[test]
+    fn one_mask_per_figure() {
+        let svg = SVG.replace(
+            r#"<path id="in_group""#,
+            r#"<path id="in_group" mask="url(#fade)""#,
+        );
+        assert!(parse_svg_content(&svg, 1000, 8.0).is_err());
+    }
+
+    #[test]
+    fn hidden_elements_are_kept_hidden() {
+        let svg = r##"<svg viewBox="0 0 100 100" xmlns="http://www.w3.org/2000/svg">
+<g id="0" style="display:none">
+<g id="1" style="display:inline">
+<path id="under_none" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+</g>
+</g>
+<g id="2" visibility="hidden">
+<path id="hidden" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+<path id="shown_again" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none;visibility:visible"/>
+<g id="3">
+<path id="still_hidden" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+</g>
+<path id="back_in_two" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+</g>
+<g id="4">
+<path id="inline" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none;display:inline"/>
+<path id="none" display="none" d="m 0,0 10,0 0,10 z" style="fill:#ff0000;stroke:none"/>
+</g>
+</svg>"##;
+        let car = parse_svg_content(svg, 1000, 8.0).unwrap();
+        let hidden: Vec<(&str, bool)> = car
+            .iter()
+            .map(|p| (p.id().as_str(), p.is_hidden()))
+            .collect();
+        assert_eq!(
+            hidden,
+            [
+                // display:none can't be undone by the children
+                ("under_none", true),
+                ("hidden", true),
+                ("shown_again", false),
+                ("still_hidden", true),
+                ("back_in_two", true),
+                ("inline", false),
+                ("none", true),
+            ]
+        );
+        // Closing a group goes back to the layer that was open before it
+        let layers: Vec<i32> = car.iter().map(|p| p.get_layer()).collect();
+        assert_eq!(layers, [1, 2, 2, 3, 2, 4, 4]);
+    }
+
+    #[test]
+    fn references_must_be_defined() {
+        let svg = SVG.replace(
+            r#"clip-path="url(#left)" d="m 60"#,
+            r#"clip-path="url(#right)" d="m 60"#,
+        );
+        assert!(parse_svg_content(&svg, 1000, 8.0).is_err());
+        assert!(parse_url_reference(Some("#left")).is_err());
+        assert_eq!(parse_url_reference(Some("none")).unwrap(), None);
+    }
+
+    #[test]
+    fn hits_come_in_paint_order_and_follow_the_fill_rule() {
+        // Both "ring" and "plate" have an inner square going the same way as the outer one
+        let svg = r##"<svg viewBox="0 0 100 100" xmlns="http://www.w3.org/2000/svg">
+<g id="2">
+<path id="roof" d="m 0,0 100,0 0,100 -100,0 z" style="fill:#ff0000;stroke:none"/>
+</g>
+<g id="0">
+<path id="ring" fill-rule="evenodd" d="m 10,10 80,0 0,80 -80,0 z m 20,20 40,0 0,40 -40,0 z" style="fill:#00ff00;stroke:none"/>
+<path id="plate" d="m 10,10 80,0 0,80 -80,0 z m 20,20 40,0 0,40 -40,0 z" style="fill:#0000ff;stroke:none"/>
+<path id="ghost" display="none" d="m 0,0 100,0 0,100 -100,0 z" style="fill:#000000;stroke:none"/>
+</g>
+<g id="1">
+<path id="window" d="m 40,40 20,0 0,20 -20,0 z" style="fill:#ffffff;stroke:none"/>
+</g>
+</svg>"##;
+        let car = parse_svg_content(svg, 100, 1.0).unwrap();
+        let ids_at = |x, y| -> Vec<String> {
+            polygons_at(&car, Point::new_unchecked(x, y))
+                .iter()
+                .map(|p| p.id().clone())
+                .collect()
+        };
+        // Lowest layer first, whatever the order in the file; hidden polygons aren't hit
+        assert_eq!(ids_at(20.0, 20.0), ["ring", "plate", "roof"]);
+        // Even-odd leaves the inner square of "ring" empty, nonzero fills the one of "plate"
+        assert_eq!(ids_at(50.0, 50.0), ["plate", "window", "roof"]);
+        assert_eq!(ids_at(35.0, 35.0), ["plate", "roof"]);
+        assert!(ids_at(150.0, 50.0).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+//! Weiler–Atherton clipping of polygons to a box.
+//!
+//! Unlike Sutherland–Hodgman, which keeps every border in one piece and so joins the parts of a
+//! concave shape that come back into the box with zero-width "bridges" along its edges, this
+//! traces the border of the clipped region: each stretch of a border inside the box runs until it
+//! leaves, and from there the box's edge is followed clockwise up to where the next stretch comes
+//! in. Every separate region becomes its own polygon.
+
+use crate::boolean;
+use crate::shapes::{BoundingBox, Line, LineClip, LineMethods, Point, Polygon, Universal};
+
+/// Distance along the box's edge from its min corner, going clockwise on screen.
+fn perimeter_position(bounds: &BoundingBox, point: Point<Universal>) -> Universal {
+    let (min, max) = (bounds.min(), bounds.max());
+    let (width, height) = (bounds.width(), bounds.height());
+    // Which edge the point is on is decided by the closest one, since rounding can leave it a
+    // hair away from all of them
+    let distances = [
+        (point.y() - min.y()).abs(),
+        (max.x() - point.x()).abs(),
+        (max.y() - point.y()).abs(),
+        (point.x() - min.x()).abs(),
+    ];
+    let edge = (0..4)
+        .min_by(|i, j| distances[*i].total_cmp(&distances[*j]))
+        .expect("a box has edges");
+    match edge {
+        0 => point.x() - min.x(),
+        1 => width + point.y() - min.y(),
+        2 => width + height + max.x() - point.x(),
+        _ => 2.0 * width + height + max.y() - point.y(),
+    }
+}
+
+/// The box's corners with their position along its edge, in clockwise order.
+fn corners(bounds: &BoundingBox) -> [(Universal, Point<Universal>); 4] {
+    let (min, max) = (bounds.min(), bounds.max());
+    let (width, height) = (bounds.width(), bounds.height());
+    [
+        (0.0, min),
+        (width, Point::new_unchecked(max.x(), min.y())),
+        (width + height, max),
+        (2.0 * width + height, Point::new_unchecked(min.x(), max.y())),
+    ]
+}
+
+/// Links the stretches of the borders that are inside the box (each going from where a border
+/// comes into the box to where it leaves) into closed borders, walking along the box's edge
+/// between them.
+fn link_stretches(bounds: &BoundingBox, stretches: Vec<Line<Universal>>) -> Vec<Line<Universal>> {
+    let perimeter = 2.0 * (bounds.width() + bounds.height());
+    let entries: Vec<Universal> = stretches
+        .iter()
+        .map(|s| perimeter_position(bounds, s[0]))
+        .collect();
+    let exits: Vec<Universal> = stretches
+        .iter()
+        .map(|s| perimeter_position(bounds, s[s.len() - 1]))
+        .collect();
+    let clockwise = |from: Universal, to: Universal| (to - from).rem_euclid(perimeter);
+
+    let mut used = vec![false; stretches.len()];
+    let mut borders = Vec::new();
+    for first in 0..stretches.len() {
+        if used[first] {
+            continue;
+        }
+        let mut border: Line<Universal> = Vec::new();
+        let mut current = first;
+        loop {
+            used[current] = true;
+            border.extend_from_slice(&stretches[current]);
+
+            // The next stretch is the first one that comes in clockwise from here, which is the
+            // one that closes the border if it isn't taken yet
+            let exit = exits[current];
+            let next = (0..stretches.len())
+                .filter(|i| !used[*i] || *i == first)
+                .min_by(|i, j| {
+                    clockwise(exit, entries[*i]).total_cmp(&clockwise(exit, entries[*j]))
+                })
+                .expect("the first stretch is always a candidate");
+
+            let gap = clockwise(exit, entries[next]);
+            let mut passed: Vec<(Universal, Point<Universal>)> = corners(bounds)
+                .iter()
+                .map(|(position, corner)| (clockwise(exit, *position), *corner))
+                .filter(|(distance, _)| *distance > 0.0 && *distance < gap)
+                .collect();
+            passed.sort_by(|a, b| a.0.total_cmp(&b.0));
+            border.extend(passed.iter().map(|(_, corner)| *corner));
+
+            if next == first {
+                break;
+            }
+            current = next;
+        }
+        border.push(border[0]);
+        border.dedup();
+        if border.len() > 3 {
+            borders.push(border);
+        }
+    }
+    borders
+}
+
+/// Groups non-overlapping borders into regions: every border nested inside an even number of
+/// others, together with the borders right inside it.
+fn split_regions(borders: Vec<Line<Universal>>) -> Vec<Vec<Line<Universal>>> {
+    let enclosing: Vec<Vec<usize>> = (0..borders.len())
+        .map(|i| {
+            (0..borders.len())
+                .filter(|j| *j != i && borders[*j].encloses(borders[i][0]))
+                .collect()
+        })
+        .collect();
+    let depth = |i: usize| enclosing[i].len();
+
+    let mut regions: Vec<(usize, Vec<Line<Universal>>)> = Vec::new();
+    for (i, border) in borders.iter().enumerate() {
+        if depth(i) % 2 == 0 {
+            regions.push((i, vec![border.clone()]));
+        }
+    }
+    for (i, border) in borders.iter().enumerate() {
+        if depth(i) % 2 == 1 {
+            let parent = enclosing[i].iter().find(|j| depth(**j) == depth(i) - 1);
+            if let Some(region) = parent.and_then(|p| regions.iter_mut().find(|(r, _)| r == p)) {
+                region.1.push(border.clone());
+            }
+        }
+    }
+    regions.into_iter().map(|(_, borders)| borders).collect()
+}
+
+impl Polygon<Universal> {
+    /// Clips the polygon to `bounds`, returning one polygon (with this one's attributes) for each
+    /// separate region that's left.
+    pub fn clip_to_box(&self, bounds: &BoundingBox) -> Vec<Polygon<Universal>> {
+        match self.bounding_box() {
+            None => return Vec::new(),
+            Some(own) if !own.intersects(bounds) => return Vec::new(),
+            Some(own) if bounds.contains(own.min()) && bounds.contains(own.max()) => {
+                return vec![self.clone()]
+            }
+            _ => (),
+        }
+
+        // Tracing needs outer borders going clockwise and holes the other way
+        let borders = boolean::oriented_borders(self);
+
+        let mut inside = Vec::new();
+        let mut stretches = Vec::new();
+        for border in borders {
+            match border.iter().position(|p| !bounds.contains(*p)) {
+                None => inside.push(border),
+                Some(start) => {
+                    // Starting outside makes every stretch start where the border comes in
+                    let mut rotated: Line<Universal> = border[start..border.len() - 1].to_vec();
+                    rotated.extend_from_slice(&border[..=start]);
+                    stretches.extend(
+                        rotated
+                            .clip_stroke(bounds)
+                            .into_iter()
+                            .filter(|s| s.first() != s.last()),
+                    );
+                }
+            }
+        }
+
+        let mut clipped = link_stretches(bounds, stretches);
+        if clipped.is_empty() && self.contains(bounds.min()) {
+            // No border crosses the box, and it's inside the polygon
+            clipped.push(corners(bounds).iter().map(|(_, c)| *c).collect());
+            clipped[0].push(bounds.min());
+        }
+        clipped.extend(inside);
+
+        split_regions(clipped)
+            .into_iter()
+            .map(|region| self.new_copy_attributes(region))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::{line, point, polygon, rectangle};
+    use crate::shapes::FillRule;
+
+    fn area(regions: &[Polygon<Universal>]) -> Universal {
+        regions.iter().map(|r| r.signed_area()).sum()
+    }
+
+    /// A U opening upwards, whose arms go above y = 4.
+    fn u_shape() -> Polygon<Universal> {
+        polygon(
+            vec![line(&[
+                (0.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 8.0),
+                (8.0, 8.0),
+                (8.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 10.0),
+                (0.0, 10.0),
+            ])],
+            FillRule::NonZero,
+        )
+    }
+
+    #[test]
+    fn concave_parts_become_separate_regions() {
+        let bounds = BoundingBox::new(point(-1.0, -1.0), point(11.0, 4.0));
+        let arms = u_shape().clip_to_box(&bounds);
+        assert_eq!(arms.len(), 2);
+        assert!((area(&arms) - 16.0).abs() < 1e-4);
+        // No bridge joins the arms along the box's edge
+        for arm in &arms {
+            let bbox = arm.bounding_box().unwrap();
+            assert!(bbox.width() <= 2.0 + 1e-4, "{:?}", bbox);
+        }
+    }
+
+    #[test]
+    fn polygons_within_or_outside_the_box() {
+        let u = u_shape();
+        let all = BoundingBox::new(point(-1.0, -1.0), point(11.0, 11.0));
+        let whole = u.clip_to_box(&all);
+        assert_eq!(whole.len(), 1);
+        assert_eq!(whole[0].get_borders(), u.get_borders());
+        let away = BoundingBox::new(point(20.0, 20.0), point(30.0, 30.0));
+        assert!(u.clip_to_box(&away).is_empty());
+        // Between the arms, outside the polygon
+        let gap = BoundingBox::new(point(3.0, 1.0), point(7.0, 5.0));
+        assert!(u.clip_to_box(&gap).is_empty());
+    }
+
+    #[test]
+    fn boxes_inside_the_polygon_are_filled() {
+        let u = u_shape();
+        let inside = BoundingBox::new(point(3.0, 8.5), point(7.0, 9.5));
+        let clipped = u.clip_to_box(&inside);
+        assert_eq!(clipped.len(), 1);
+        assert!((area(&clipped) - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn holes_are_kept() {
+        let mut donut = polygon(
+            vec![
+                rectangle(0.0, 0.0, 10.0, 10.0),
+                rectangle(4.0, 4.0, 6.0, 6.0),
+            ],
+            FillRule::NonZero,
+        );
+        donut.set_fill_rule(FillRule::EvenOdd);
+
+        // The hole stays inside the clipped region
+        let bounds = BoundingBox::new(point(2.0, 2.0), point(20.0, 8.0));
+        let clipped = donut.clip_to_box(&bounds);
+        assert_eq!(clipped.len(), 1);
+        assert_eq!(clipped[0].get_borders().len(), 2);
+        assert!((area(&clipped) - (48.0 - 4.0)).abs() < 1e-4);
+
+        // Cutting through the hole notches the border instead
+        let bounds = BoundingBox::new(point(5.0, -1.0), point(11.0, 11.0));
+        let clipped = donut.clip_to_box(&bounds);
+        assert_eq!(clipped.len(), 1);
+        assert_eq!(clipped[0].get_borders().len(), 1);
+        assert!((area(&clipped) - (50.0 - 2.0)).abs() < 1e-4);
+    }
+}
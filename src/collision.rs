@@ -0,0 +1,281 @@
+//! Collision primitives: convex hulls, separating-axis overlap tests between convex shapes, and
+//! a general polygon overlap test for concave ones.
+
+use crate::shapes::{
+    closed_segments, segments_intersect, Line, LineMethods, Orientation, Point, Polygon, Universal,
+};
+
+/// Smallest convex border around `points` (Andrew's monotone chain), going clockwise and closed.
+/// Points on the hull's edges are left out.
+pub fn convex_hull<I: IntoIterator<Item = Point<Universal>>>(points: I) -> Line<Universal> {
+    let mut points: Vec<Point<Universal>> = points.into_iter().filter(|p| p.is_finite()).collect();
+    points.sort_unstable_by(|a, b| a.x().total_cmp(&b.x()).then(a.y().total_cmp(&b.y())));
+    points.dedup();
+    if points.len() < 3 {
+        if let Some(first) = points.first().copied() {
+            points.push(first);
+        }
+        return points;
+    }
+
+    // Builds one half of the hull, only keeping points where it turns the same way
+    let half = |points: &mut dyn Iterator<Item = &Point<Universal>>| {
+        let mut chain: Vec<Point<Universal>> = Vec::new();
+        for p in points {
+            while chain.len() >= 2
+                && (chain[chain.len() - 1] - chain[chain.len() - 2])
+                    .cross(*p - chain[chain.len() - 2])
+                    <= 0.0
+            {
+                chain.pop();
+            }
+            chain.push(*p);
+        }
+        chain
+    };
+    let mut hull = half(&mut points.iter());
+    let upper = half(&mut points.iter().rev());
+    hull.pop();
+    hull.extend_from_slice(&upper);
+
+    if hull.orientation() == Orientation::CounterClockwise {
+        hull.reverse();
+    }
+    hull
+}
+
+/// Range covered by the projections of `points` onto `axis`.
+fn project(points: &[Point<Universal>], axis: Point<Universal>) -> (Universal, Universal) {
+    points.iter().map(|p| p.dot(axis)).fold(
+        (Universal::INFINITY, Universal::NEG_INFINITY),
+        |(min, max), d| (min.min(d), max.max(d)),
+    )
+}
+
+/// Separating axis test of whether two convex shapes, given by their vertices in order around
+/// (closed or not), overlap. Shapes that only touch count as overlapping. The coordinate axes
+/// are always tried too, which keeps points and segments from being taken as infinite lines.
+pub fn convex_overlap(a: &[Point<Universal>], b: &[Point<Universal>]) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    let edge_normals = |shape: &[Point<Universal>]| -> Vec<Point<Universal>> {
+        closed_segments(shape)
+            .into_iter()
+            .filter_map(|(p, q)| (q - p).normalize())
+            .map(|d| Point::new_unchecked(-d.y(), d.x()))
+            .collect()
+    };
+    let axes = [
+        Point::new_unchecked(1.0, 0.0),
+        Point::new_unchecked(0.0, 1.0),
+    ];
+
+    axes.iter()
+        .copied()
+        .chain(edge_normals(a))
+        .chain(edge_normals(b))
+        .all(|axis| {
+            let ((min_a, max_a), (min_b, max_b)) = (project(a, axis), project(b, axis));
+            min_a <= max_b && min_b <= max_a
+        })
+}
+
+/// Whether any segment of `a`'s borders touches any of `b`'s.
+fn borders_touch(a: &Polygon<Universal>, b: &Polygon<Universal>) -> bool {
+    type Tagged = (bool, Point<Universal>, Point<Universal>);
+    let mut segments: Vec<Tagged> = a
+        .get_borders()
+        .iter()
+        .flat_map(|border| closed_segments(border))
+        .map(|(p, q)| (true, p, q))
+        .chain(
+            b.get_borders()
+                .iter()
+                .flat_map(|border| closed_segments(border))
+                .map(|(p, q)| (false, p, q)),
+        )
+        .collect();
+
+    // Sweep over x so that only segments with overlapping x ranges get compared
+    let min_x = |s: &Tagged| s.1.x().min(s.2.x());
+    let max_x = |s: &Tagged| s.1.x().max(s.2.x());
+    segments.sort_unstable_by(|s, t| min_x(s).total_cmp(&min_x(t)));
+    segments.iter().enumerate().any(|(k, s)| {
+        segments[k + 1..]
+            .iter()
+            .take_while(|t| min_x(t) <= max_x(s))
+            .any(|t| s.0 != t.0 && segments_intersect(s.1, s.2, t.1, t.2))
+    })
+}
+
+impl Polygon<Universal> {
+    /// Smallest convex border around all of the polygon's points.
+    pub fn convex_hull(&self) -> Line<Universal> {
+        convex_hull(self.get_borders().iter().flatten().copied())
+    }
+
+    /// Whether the polygon is a single border that turns the same way at every corner and goes
+    /// around only once.
+    pub fn is_convex(&self) -> bool {
+        let borders: Vec<&Line<Universal>> = self
+            .get_borders()
+            .iter()
+            .filter(|b| !b.is_empty())
+            .collect();
+        if borders.len() != 1 {
+            return false;
+        }
+        let directions: Vec<Point<Universal>> = closed_segments(borders[0])
+            .into_iter()
+            .filter_map(|(p, q)| (q - p).normalize())
+            .collect();
+        if directions.len() < 3 {
+            return false;
+        }
+
+        let n = directions.len();
+        let turns = (0..n).map(|i| {
+            let (d1, d2) = (directions[i], directions[(i + 1) % n]);
+            d1.cross(d2).atan2(d1.dot(d2))
+        });
+        let (mut clockwise, mut counterclockwise, mut total) = (false, false, 0.0);
+        for turn in turns {
+            clockwise |= turn > 1e-6;
+            counterclockwise |= turn < -1e-6;
+            total += turn;
+        }
+        // A star also turns the same way everywhere, but goes around more than once
+        !(clockwise && counterclockwise) && (total.abs() - 2.0 * std::f32::consts::PI).abs() < 1e-2
+    }
+
+    /// Whether the two polygons overlap or touch, according to their fill rules. Convex
+    /// polygons are compared with the separating axis test; otherwise the polygons overlap when
+    /// their borders touch, or when one of them has a border inside the other.
+    pub fn intersects(&self, other: &Polygon<Universal>) -> bool {
+        match (self.bounding_box(), other.bounding_box()) {
+            (Some(a), Some(b)) if a.intersects(&b) => (),
+            _ => return false,
+        }
+        if self.is_convex() && other.is_convex() {
+            return convex_overlap(&self.get_borders()[0], &other.get_borders()[0]);
+        }
+
+        let border_inside = |a: &Polygon<Universal>, b: &Polygon<Universal>| {
+            a.get_borders()
+                .iter()
+                .filter_map(|border| border.first())
+                .any(|p| b.contains(*p))
+        };
+        borders_touch(self, other) || border_inside(self, other) || border_inside(other, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::{line, polygon, square};
+    use crate::shapes::FillRule;
+
+    /// A U opening upwards, with room for a 4x4 square between its arms.
+    fn u_shape() -> Polygon<Universal> {
+        polygon(
+            vec![line(&[
+                (0.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 8.0),
+                (8.0, 8.0),
+                (8.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 10.0),
+                (0.0, 10.0),
+            ])],
+            FillRule::NonZero,
+        )
+    }
+
+    #[test]
+    fn hull_goes_clockwise_around_the_extremes() {
+        let points = line(&[
+            (0.0, 0.0),
+            (5.0, 5.0),
+            (10.0, 0.0),
+            (5.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (3.0, 7.0),
+        ]);
+        let hull = convex_hull(points);
+        assert_eq!(hull.len(), 5);
+        assert!(hull.is_closed());
+        assert_eq!(hull.orientation(), Orientation::Clockwise);
+        for corner in square(0.0, 0.0, 10.0) {
+            assert!(hull.contains(&corner));
+        }
+    }
+
+    #[test]
+    fn hull_of_few_points() {
+        assert!(convex_hull(Vec::new()).is_empty());
+        assert_eq!(
+            convex_hull(line(&[(1.0, 1.0)])),
+            line(&[(1.0, 1.0), (1.0, 1.0)])
+        );
+        let hull = convex_hull(line(&[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (1.0, 1.0)]));
+        assert!(hull.iter().all(|p| p.x() == p.y()));
+    }
+
+    #[test]
+    fn convex_shapes_overlap_unless_an_axis_separates_them() {
+        let a = square(0.0, 0.0, 10.0);
+        assert!(convex_overlap(&a, &square(5.0, 5.0, 10.0)));
+        assert!(convex_overlap(&a, &square(10.0, 0.0, 10.0)));
+        assert!(!convex_overlap(&a, &square(11.0, 0.0, 10.0)));
+        // Only the diagonal edge's normal separates these
+        let triangle = line(&[(20.0, 0.0), (20.0, 20.0), (0.0, 20.0)]);
+        assert!(!convex_overlap(&square(0.0, 0.0, 9.0), &triangle));
+        assert!(convex_overlap(&square(0.0, 0.0, 11.0), &triangle));
+        // A point and a segment
+        assert!(convex_overlap(&a, &line(&[(5.0, 5.0)])));
+        assert!(!convex_overlap(&line(&[(0.0, 20.0), (20.0, 20.0)]), &a));
+    }
+
+    #[test]
+    fn convexity() {
+        assert!(polygon(vec![square(0.0, 0.0, 10.0)], FillRule::NonZero).is_convex());
+        assert!(!u_shape().is_convex());
+        let star = line(&[
+            (0.0, 3.0),
+            (10.0, 3.0),
+            (2.0, 10.0),
+            (5.0, 0.0),
+            (8.0, 10.0),
+        ]);
+        assert!(!polygon(vec![star], FillRule::NonZero).is_convex());
+        let donut = vec![square(0.0, 0.0, 10.0), square(4.0, 4.0, 2.0)];
+        assert!(!polygon(donut, FillRule::NonZero).is_convex());
+    }
+
+    #[test]
+    fn concave_polygons_only_intersect_where_they_overlap() {
+        let u = u_shape();
+        // Between the arms: the bounding boxes overlap but the polygons don't
+        assert!(!u.intersects(&polygon(vec![square(3.0, 2.0, 4.0)], FillRule::NonZero)));
+        assert!(u.intersects(&polygon(vec![square(1.0, 2.0, 4.0)], FillRule::NonZero)));
+        // Fully inside an arm, without any border touching
+        assert!(u.intersects(&polygon(vec![square(0.5, 2.0, 1.0)], FillRule::NonZero)));
+        assert!(polygon(vec![square(0.5, 2.0, 1.0)], FillRule::NonZero).intersects(&u));
+        assert!(!u.intersects(&polygon(vec![square(20.0, 0.0, 1.0)], FillRule::NonZero)));
+    }
+
+    #[test]
+    fn holes_dont_intersect() {
+        let mut donut = polygon(
+            vec![square(0.0, 0.0, 10.0), square(2.0, 2.0, 6.0)],
+            FillRule::NonZero,
+        );
+        donut.set_fill_rule(FillRule::EvenOdd);
+        assert!(!donut.intersects(&polygon(vec![square(4.0, 4.0, 2.0)], FillRule::NonZero)));
+        assert!(donut.intersects(&polygon(vec![square(1.0, 4.0, 2.0)], FillRule::NonZero)));
+    }
+}
@@ -11,18 +11,49 @@ pub const SCENE_SIZE: u32 = 1000;
 
 pub const SCENE_CENTER: f32 = SCENE_SIZE as f32 / 2.0;
 
-/// Point polling spacing
-pub const POINT_SPACING: f32 = 0.08;
+/// How far the borders the car gets parsed into can stray from its curves, in scene units
+pub const CURVE_TOLERANCE: f32 = 0.08;
 
-/// Bezier polyline approximation for then finding equidistant points
-pub const POLYLINE_N: u32 = 1000;
+/// How far (in pixels) the drawn lines can stray from the car's curves
+pub const CURVE_PIXEL_TOLERANCE: f32 = 0.25;
+
+/// Zoom bands (powers of two of the scene units that fit in a pixel) the curves get flattened
+/// for. Zooming in past the finest one just magnifies its lines, and zooming out past the
+/// coarsest one keeps drawing it.
+pub const FINEST_DETAIL_BAND: i32 = -6;
+pub const COARSEST_DETAIL_BAND: i32 = 4;
+
+/// Directory where parsed cars are cached
+pub const CACHE_DIR: &str = "cache";
+
+/// Paint jobs the viewer can cycle through
+pub const THEMES_PATH: &str = "images/car_themes.json";
+
+/// Where the viewer exports the scene to
+pub const EXPORT_PATH: &str = "images/car_export.svg";
 
 pub const BACKGROUND_COLOR: &str = "#77a8c9";
 
 pub const ZOOM_AMOUNT: f32 = 0.3;
 pub const PAN_PERCENT: f32 = 0.1;
 
-/// Cosine of 15 degrees
-pub const COS15: f32 = 0.965_925_8;
-/// Sine of 15 degrees
-pub const SEN15: f32 = 0.258_819_04;
+/// How far (in pixels) simplifying the car's lines can move them
+pub const SIMPLIFY_TOLERANCE: f32 = 0.5;
+
+/// Space left around the content when fitting the view to it, as a fraction of its size
+pub const FIT_MARGIN: f32 = 0.05;
+
+/// Degrees rotated by the coarse and fine rotation keys
+pub const ROTATION_STEP: f32 = 15.0;
+pub const FINE_ROTATION_STEP: f32 = 1.0;
+
+/// Degrees that rotations snap to when snapping is turned on
+pub const ANGLE_SNAP: f32 = 15.0;
+
+/// Longest a miter join can get when offsetting, as a multiple of the offset distance
+#[allow(dead_code)] // Only used by `offset`, which the viewer doesn't call
+pub const MITER_LIMIT: f32 = 4.0;
+
+/// How far round joins can stray from a true circle when offsetting
+#[allow(dead_code)] // Only used by `offset`, which the viewer doesn't call
+pub const ARC_TOLERANCE: f32 = 0.1;
@@ -0,0 +1,499 @@
+//! Path segments kept as the curves they are, so they can be turned into lines (flattened) at
+//! whatever resolution the current zoom needs instead of a fixed one chosen when parsing.
+
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::shapes::{Line, Point, Polygon, Transform2D, Universal};
+
+/// Most segments a single curve gets flattened into, however small the tolerance.
+const MAX_SEGMENTS: u32 = 4096;
+
+/// One piece of a path, going from wherever the previous one ended.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Curve {
+    Line {
+        to: Point<Universal>,
+    },
+    Quadratic {
+        control: Point<Universal>,
+        to: Point<Universal>,
+    },
+    Cubic {
+        control1: Point<Universal>,
+        control2: Point<Universal>,
+        to: Point<Universal>,
+    },
+    /// Part of the ellipse `center + axis_x * cos(t) + axis_y * sin(t)`, from `t = start` to
+    /// `t = start + sweep`, which lands on `to`. Keeping the axes as vectors (instead of radii
+    /// and a rotation) keeps it an exact ellipse under any affine transform.
+    Arc {
+        center: Point<Universal>,
+        axis_x: Point<Universal>,
+        axis_y: Point<Universal>,
+        start: Universal,
+        sweep: Universal,
+        to: Point<Universal>,
+    },
+    /// A whole ellipse, starting and ending at `t = 0`.
+    Ellipse {
+        center: Point<Universal>,
+        axis_x: Point<Universal>,
+        axis_y: Point<Universal>,
+    },
+}
+
+/// Segments needed for a Bézier curve of `degree` to stay within `tolerance` of its chords
+/// (Wang's formula), `second_difference` being the longest second difference of its points.
+fn bezier_segments(degree: Universal, second_difference: Universal, tolerance: Universal) -> u32 {
+    let n = (degree * (degree - 1.0) / 8.0 * second_difference / tolerance).sqrt();
+    clamp_segments(n)
+}
+
+fn clamp_segments(n: Universal) -> u32 {
+    if n.is_finite() {
+        (n.ceil() as u32).clamp(1, MAX_SEGMENTS)
+    } else {
+        MAX_SEGMENTS
+    }
+}
+
+impl Curve {
+    /// SVG's elliptical arc (`A`), which is given by its endpoints, converted to the center
+    /// parameterization. Arcs with a zero radius are straight lines, as the spec says.
+    pub fn svg_arc(
+        from: Point<Universal>,
+        (rx, ry): (Universal, Universal),
+        x_rotation: Universal,
+        large_arc: bool,
+        sweep: bool,
+        to: Point<Universal>,
+    ) -> Curve {
+        let (mut rx, mut ry) = (rx.abs(), ry.abs());
+        if rx == 0.0 || ry == 0.0 || from == to {
+            return Curve::Line { to };
+        }
+
+        // Endpoints in the ellipse's own axes, relative to the middle of the chord
+        let (sin, cos) = x_rotation.to_radians().sin_cos();
+        let half = (from - to) / 2.0;
+        let x1 = cos * half.x() + sin * half.y();
+        let y1 = -sin * half.x() + cos * half.y();
+
+        // Radii too small to reach both endpoints get scaled up until they just do
+        let lambda = (x1 / rx).powi(2) + (y1 / ry).powi(2);
+        if lambda > 1.0 {
+            rx *= lambda.sqrt();
+            ry *= lambda.sqrt();
+        }
+
+        let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+        let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+        let sign = if large_arc != sweep { 1.0 } else { -1.0 };
+        let coefficient = sign * (numerator / denominator).max(0.0).sqrt();
+        let (cx, cy) = (coefficient * rx * y1 / ry, -coefficient * ry * x1 / rx);
+
+        let center =
+            Point::new_unchecked(cos * cx - sin * cy, sin * cx + cos * cy) + (from + to) / 2.0;
+        let angle = |x: Universal, y: Universal| y.atan2(x);
+        let start = angle((x1 - cx) / rx, (y1 - cy) / ry);
+        let end = angle((-x1 - cx) / rx, (-y1 - cy) / ry);
+        let mut delta = (end - start).rem_euclid(2.0 * PI);
+        if !sweep && delta > 0.0 {
+            delta -= 2.0 * PI;
+        }
+
+        Curve::Arc {
+            center,
+            axis_x: Point::new_unchecked(rx * cos, rx * sin),
+            axis_y: Point::new_unchecked(-ry * sin, ry * cos),
+            start,
+            sweep: delta,
+            to,
+        }
+    }
+
+    /// Where the curve ends, `from` being where it starts.
+    pub fn end(&self, from: Point<Universal>) -> Point<Universal> {
+        match self {
+            Curve::Line { to }
+            | Curve::Quadratic { to, .. }
+            | Curve::Cubic { to, .. }
+            | Curve::Arc { to, .. } => *to,
+            Curve::Ellipse { .. } => from,
+        }
+    }
+
+    /// Adds to `out` the points of the curve after `from`, close enough together that the lines
+    /// between them never stray further than `tolerance` from it.
+    fn flatten_into(
+        &self,
+        from: Point<Universal>,
+        tolerance: Universal,
+        out: &mut Line<Universal>,
+    ) {
+        let steps = |n: u32| (1..n).map(move |i| i as Universal / n as Universal);
+        match *self {
+            Curve::Line { to } => out.push(to),
+            Curve::Quadratic { control, to } => {
+                let n = bezier_segments(2.0, (from - control * 2.0 + to).length(), tolerance);
+                out.extend(steps(n).map(|t| {
+                    (1.0 - t).powi(2) * from + 2.0 * (1.0 - t) * t * control + t * t * to
+                }));
+                out.push(to);
+            }
+            Curve::Cubic {
+                control1,
+                control2,
+                to,
+            } => {
+                let second_difference = (from - control1 * 2.0 + control2)
+                    .length()
+                    .max((control1 - control2 * 2.0 + to).length());
+                let n = bezier_segments(3.0, second_difference, tolerance);
+                out.extend(steps(n).map(|t| {
+                    (1.0 - t).powi(3) * from
+                        + 3.0 * (1.0 - t).powi(2) * t * control1
+                        + 3.0 * (1.0 - t) * t * t * control2
+                        + t.powi(3) * to
+                }));
+                out.push(to);
+            }
+            Curve::Arc {
+                center,
+                axis_x,
+                axis_y,
+                start,
+                sweep,
+                to,
+            } => {
+                let n = arc_segments(axis_x, axis_y, sweep, tolerance);
+                out.extend(steps(n).map(|t| {
+                    let (sin, cos) = (start + sweep * t).sin_cos();
+                    center + axis_x * cos + axis_y * sin
+                }));
+                out.push(to);
+            }
+            Curve::Ellipse {
+                center,
+                axis_x,
+                axis_y,
+            } => {
+                let n = arc_segments(axis_x, axis_y, 2.0 * PI, tolerance).max(4);
+                out.extend(steps(n).map(|t| {
+                    let (sin, cos) = (2.0 * PI * t).sin_cos();
+                    center + axis_x * cos + axis_y * sin
+                }));
+                out.push(from);
+            }
+        }
+    }
+
+    fn transform(&mut self, transform: &Transform2D) {
+        // Axes are directions, so only the linear part of the transform applies to them
+        let origin = transform.apply(Point::new_unchecked(0.0, 0.0));
+        let linear = |v: Point<Universal>| transform.apply(v) - origin;
+        match self {
+            Curve::Line { to } => *to = transform.apply(*to),
+            Curve::Quadratic { control, to } => {
+                *control = transform.apply(*control);
+                *to = transform.apply(*to);
+            }
+            Curve::Cubic {
+                control1,
+                control2,
+                to,
+            } => {
+                *control1 = transform.apply(*control1);
+                *control2 = transform.apply(*control2);
+                *to = transform.apply(*to);
+            }
+            Curve::Arc {
+                center,
+                axis_x,
+                axis_y,
+                to,
+                ..
+            } => {
+                *center = transform.apply(*center);
+                *axis_x = linear(*axis_x);
+                *axis_y = linear(*axis_y);
+                *to = transform.apply(*to);
+            }
+            Curve::Ellipse {
+                center,
+                axis_x,
+                axis_y,
+            } => {
+                *center = transform.apply(*center);
+                *axis_x = linear(*axis_x);
+                *axis_y = linear(*axis_y);
+            }
+        }
+    }
+}
+
+/// Segments needed for `sweep` radians of an ellipse to stay within `tolerance` of its chords,
+/// going by the longest axis (where chords stray the most).
+fn arc_segments(
+    axis_x: Point<Universal>,
+    axis_y: Point<Universal>,
+    sweep: Universal,
+    tolerance: Universal,
+) -> u32 {
+    let radius = axis_x.length().max(axis_y.length());
+    let step = 2.0 * (1.0 - (tolerance / radius).min(1.0)).acos();
+    clamp_segments(sweep.abs() / step)
+}
+
+/// A subpath: a start point and the curves that follow it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Path {
+    start: Point<Universal>,
+    curves: Vec<Curve>,
+    /// Closed paths (`z` in the svg) go back to their start
+    closed: bool,
+}
+
+impl Path {
+    pub fn new(start: Point<Universal>) -> Path {
+        Path {
+            start,
+            curves: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// The whole ellipse with semi-axes `rx` and `ry` along x and y, starting at its rightmost
+    /// point.
+    pub fn ellipse(center: Point<Universal>, rx: Universal, ry: Universal) -> Path {
+        let axis_x = Point::new_unchecked(rx, 0.0);
+        Path {
+            start: center + axis_x,
+            curves: vec![Curve::Ellipse {
+                center,
+                axis_x,
+                axis_y: Point::new_unchecked(0.0, ry),
+            }],
+            closed: true,
+        }
+    }
+
+    pub fn push(&mut self, curve: Curve) {
+        self.curves.push(curve);
+    }
+
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Where the path currently ends, which is where relative commands are measured from.
+    pub fn end(&self) -> Point<Universal> {
+        if self.closed {
+            return self.start;
+        }
+        self.curves
+            .iter()
+            .fold(self.start, |from, curve| curve.end(from))
+    }
+
+    /// The path as a line that never strays further than `tolerance` from it. Closed paths end
+    /// on their first point.
+    pub fn flatten(&self, tolerance: Universal) -> Line<Universal> {
+        let mut line = vec![self.start];
+        let mut from = self.start;
+        for curve in self.curves.iter() {
+            curve.flatten_into(from, tolerance, &mut line);
+            from = curve.end(from);
+        }
+        if self.closed && line.last() != Some(&self.start) {
+            line.push(self.start);
+        }
+        line
+    }
+
+    pub fn transform(&mut self, transform: &Transform2D) {
+        self.start = transform.apply(self.start);
+        for curve in self.curves.iter_mut() {
+            curve.transform(transform);
+        }
+    }
+}
+
+impl Polygon<Universal> {
+    /// Copy of the polygon with its borders flattened from its paths with `tolerance`, keeping
+    /// the paths. Polygons without paths (like the ones left by clipping) are copied as they are.
+    pub fn flattened(&self, tolerance: Universal) -> Polygon<Universal> {
+        match self.get_paths() {
+            Some(paths) => {
+                let mut flat =
+                    self.new_copy_attributes(paths.iter().map(|p| p.flatten(tolerance)).collect());
+                flat.set_paths(Some(paths.clone()));
+                flat
+            }
+            None => self.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::point;
+    use crate::simplify::distance_to_segment;
+
+    fn distance_to_line(p: Point<Universal>, line: &[Point<Universal>]) -> Universal {
+        line.windows(2)
+            .map(|w| distance_to_segment(p, w[0], w[1]))
+            .fold(Universal::INFINITY, Universal::min)
+    }
+
+    /// Checks that every point of `exact` (sampled along the curve) is within `tolerance` of the
+    /// flattened line, with some room for rounding.
+    fn assert_within(
+        line: &[Point<Universal>],
+        exact: impl Iterator<Item = Point<Universal>>,
+        tolerance: Universal,
+    ) {
+        for p in exact {
+            let distance = distance_to_line(p, line);
+            assert!(
+                distance <= tolerance * 1.01 + 1e-4,
+                "{:?} is {} away",
+                p,
+                distance
+            );
+        }
+    }
+
+    fn samples() -> impl Iterator<Item = Universal> {
+        (0..=1000).map(|i| i as Universal / 1000.0)
+    }
+
+    #[test]
+    fn beziers_stay_within_tolerance() {
+        let (from, control1, control2, to) = (
+            point(0.0, 0.0),
+            point(0.0, 100.0),
+            point(100.0, -50.0),
+            point(100.0, 50.0),
+        );
+        let mut cubic = Path::new(from);
+        cubic.push(Curve::Cubic {
+            control1,
+            control2,
+            to,
+        });
+        let exact = samples().map(|t| {
+            (1.0 - t).powi(3) * from
+                + 3.0 * (1.0 - t).powi(2) * t * control1
+                + 3.0 * (1.0 - t) * t * t * control2
+                + t.powi(3) * to
+        });
+        let coarse = cubic.flatten(1.0);
+        assert_within(&coarse, exact, 1.0);
+        assert_eq!((coarse[0], coarse[coarse.len() - 1]), (from, to));
+        assert!(cubic.flatten(0.01).len() > coarse.len());
+
+        let mut quadratic = Path::new(from);
+        quadratic.push(Curve::Quadratic {
+            control: control1,
+            to,
+        });
+        let exact = samples()
+            .map(|t| (1.0 - t).powi(2) * from + 2.0 * (1.0 - t) * t * control1 + t * t * to);
+        assert_within(&quadratic.flatten(0.1), exact, 0.1);
+    }
+
+    #[test]
+    fn straight_curves_need_one_segment() {
+        let mut path = Path::new(point(0.0, 0.0));
+        path.push(Curve::Cubic {
+            control1: point(1.0, 1.0),
+            control2: point(2.0, 2.0),
+            to: point(3.0, 3.0),
+        });
+        assert_eq!(path.flatten(0.001).len(), 2);
+    }
+
+    #[test]
+    fn svg_arcs_find_their_center() {
+        let (from, to) = (point(0.0, 0.0), point(10.0, 0.0));
+        for sweep in [false, true] {
+            let arc = Curve::svg_arc(from, (5.0, 5.0), 0.0, false, sweep, to);
+            let mut path = Path::new(from);
+            path.push(arc);
+            let line = path.flatten(0.01);
+            assert_eq!(line[line.len() - 1], to);
+            for p in &line {
+                assert!((p.distance(point(5.0, 0.0)) - 5.0).abs() < 1e-3, "{:?}", p);
+            }
+            // Sweeping clockwise on screen (y down) goes through the top
+            let middle = line[line.len() / 2];
+            assert!(
+                if sweep {
+                    middle.y() < 0.0
+                } else {
+                    middle.y() > 0.0
+                },
+                "{:?}",
+                middle
+            );
+        }
+
+        // Radii too small get scaled up, and zero radii give a straight line
+        let small = Curve::svg_arc(from, (1.0, 1.0), 0.0, false, true, to);
+        assert!(
+            matches!(small, Curve::Arc { center, .. } if center.distance(point(5.0, 0.0)) < 1e-4)
+        );
+        assert_eq!(
+            Curve::svg_arc(from, (0.0, 5.0), 0.0, false, true, to),
+            Curve::Line { to }
+        );
+    }
+
+    #[test]
+    fn ellipses_are_closed_and_on_the_ellipse() {
+        let ellipse = Path::ellipse(point(10.0, 20.0), 8.0, 4.0);
+        let line = ellipse.flatten(0.05);
+        assert_eq!(line[0], line[line.len() - 1]);
+        for p in &line {
+            let (x, y) = ((p.x() - 10.0) / 8.0, (p.y() - 20.0) / 4.0);
+            assert!((x * x + y * y - 1.0).abs() < 1e-4, "{:?}", p);
+        }
+        let exact = samples().map(|t| {
+            let (sin, cos) = (2.0 * PI * t).sin_cos();
+            point(10.0 + 8.0 * cos, 20.0 + 4.0 * sin)
+        });
+        assert_within(&line, exact, 0.05);
+    }
+
+    #[test]
+    fn transforms_keep_curves_exact() {
+        let transform = Transform2D::skew(0.4, 0.0)
+            .then(&Transform2D::rotate(0.7))
+            .then(&Transform2D::translate(30.0, -5.0));
+        let mut ellipse = Path::ellipse(point(0.0, 0.0), 10.0, 5.0);
+        ellipse.transform(&transform);
+        let exact = samples().map(|t| {
+            let (sin, cos) = (2.0 * PI * t).sin_cos();
+            transform.apply(point(10.0 * cos, 5.0 * sin))
+        });
+        assert_within(&ellipse.flatten(0.05), exact, 0.05);
+    }
+
+    #[test]
+    fn paths_end_where_their_curves_do() {
+        let mut path = Path::new(point(1.0, 1.0));
+        path.push(Curve::Line {
+            to: point(5.0, 1.0),
+        });
+        path.push(Curve::Line {
+            to: point(5.0, 5.0),
+        });
+        assert_eq!(path.end(), point(5.0, 5.0));
+        path.close();
+        assert_eq!(path.end(), point(1.0, 1.0));
+        assert_eq!(path.flatten(1.0).len(), 4);
+    }
+}
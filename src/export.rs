@@ -0,0 +1,313 @@
+//! Serializes a [`Car`] back into an svg document.
+//!
+//! The output only uses the features that `car::parse_svg` understands (numbered layer groups,
+//! relative path commands and a `style` with both `fill` and `stroke`), so an exported file can be
+//! loaded again.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::iter;
+use std::rc::Rc;
+use svg::node::element::{path::Data, Definitions, Group, Mask as MaskElement, Path};
+use svg::Document;
+
+use crate::car::{self, Car};
+use crate::shapes::{Color, FillRule, Mask, Polygon, Universal, WorldBounds};
+
+/// Which version of the car gets exported.
+#[derive(Debug, Clone, Copy)]
+pub enum ExportState {
+    /// The car as it was parsed
+    Pristine,
+    /// The car with the transformations done in the window
+    Transformed,
+}
+
+fn color_to_style(color: Option<Color>) -> String {
+    color.map_or_else(|| String::from("none"), |c| c.to_hex())
+}
+
+/// Path data for the polygon's borders. Each border starts with a relative move from where the
+/// previous one ended, and closed borders (last point equal to the first) end in `z`.
+fn borders_to_data(polygon: &Polygon<Universal>) -> Data {
+    let mut data = Data::new();
+    let (mut x, mut y) = (0.0, 0.0);
+    for border in polygon.get_borders().iter().filter(|b| !b.is_empty()) {
+        let closed = border.len() > 1 && border.first() == border.last();
+        let points = if closed {
+            &border[..border.len() - 1]
+        } else {
+            &border[..]
+        };
+
+        data = data.move_by(vec![points[0].x() - x, points[0].y() - y]);
+        if points.len() > 1 {
+            data = data.line_by(
+                points
+                    .windows(2)
+                    .flat_map(|w| vec![w[1].x() - w[0].x(), w[1].y() - w[0].y()])
+                    .collect::<Vec<Universal>>(),
+            );
+        }
+
+        // After 'z' the current point goes back to the start of the border
+        let end = if closed {
+            points[0]
+        } else {
+            points[points.len() - 1]
+        };
+        if closed {
+            data = data.close();
+        }
+        x = end.x();
+        y = end.y();
+    }
+    data
+}
+
+fn polygon_to_path(polygon: &Polygon<Universal>, mask_ids: &HashMap<*const Mask, String>) -> Path {
+    let mut style = format!(
+        "fill:{};fill-rule:{};stroke:{}",
+        color_to_style(polygon.get_fill_color()),
+        match polygon.get_fill_rule() {
+            FillRule::NonZero => "nonzero",
+            FillRule::EvenOdd => "evenodd",
+        },
+        color_to_style(polygon.get_stroke_color()),
+    );
+    if polygon.is_hidden() {
+        style.push_str(";display:none");
+    }
+
+    let path = Path::new()
+        .set("id", polygon.id().as_str())
+        .set("style", style)
+        .set("d", borders_to_data(polygon));
+
+    match polygon
+        .get_mask()
+        .and_then(|m| mask_ids.get(&Rc::as_ptr(m)))
+    {
+        Some(id) => path.set("mask", format!("url(#{})", id)),
+        None => path,
+    }
+}
+
+/// Square viewBox (as x, y, width and height) around everything in `car` (masks included) and,
+/// for a bounded `world`, the whole world, so that the parts a rotation pushed out of it don't get
+/// cropped. It's square because `car::parse_svg` only reads square ones back, and at least one
+/// unit wide so that a car squashed into a line still gets a valid one.
+fn square_view(car: &Car, world: &WorldBounds) -> [Universal; 4] {
+    let world = match world {
+        WorldBounds::Bounded(bounds) => Some(*bounds),
+        WorldBounds::Unbounded => None,
+    };
+    let content = car
+        .iter()
+        .filter_map(|p| p.get_mask())
+        .map(|mask| &**mask)
+        .chain(iter::once(car))
+        .filter_map(car::bounding_box)
+        .reduce(|a, b| a.union(&b));
+    let bounds = match (content, world) {
+        (Some(content), Some(world)) => content.union(&world),
+        (content, world) => match content.or(world) {
+            Some(bounds) => bounds,
+            None => return [0.0, 0.0, 1.0, 1.0],
+        },
+    };
+    // Whatever sticks out gets a little room, or rounding in the relative path commands could
+    // leave its outermost points just outside the viewBox when it's read back.
+    let margin = bounds.width().max(bounds.height()).max(1.0) * 1e-4;
+    let (min, max) = (bounds.min(), bounds.max());
+    let (world_min, world_max) = (world.map(|w| w.min()), world.map(|w| w.max()));
+    let (min_x, min_y) = (
+        pad(min.x(), world_min.map(|p| p.x()), -margin),
+        pad(min.y(), world_min.map(|p| p.y()), -margin),
+    );
+    let (max_x, max_y) = (
+        pad(max.x(), world_max.map(|p| p.x()), margin),
+        pad(max.y(), world_max.map(|p| p.y()), margin),
+    );
+    let side = (max_x - min_x).max(max_y - min_y).max(1.0);
+    let (center_x, center_y) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+    [center_x - side / 2.0, center_y - side / 2.0, side, side]
+}
+
+/// Moves `bound` by `margin` unless it's the world's `edge`.
+fn pad(bound: Universal, edge: Option<Universal>, margin: Universal) -> Universal {
+    if Some(bound) == edge {
+        bound
+    } else {
+        bound + margin
+    }
+}
+
+/// Builds the svg document for `car`, with a square viewBox around everything in it and, for a
+/// bounded `world`, around the whole world too. Polygons are grouped in one `<g>` per layer
+/// (whose id is the layer number) and masks are written to `<defs>`.
+pub fn car_to_document(car: &Car, world: &WorldBounds) -> Document {
+    // Masks are shared between polygons, so each distinct one is written once
+    let mut mask_ids: HashMap<*const Mask, String> = HashMap::new();
+    let mut definitions = Definitions::new();
+    for mask in car.iter().filter_map(|p| p.get_mask()) {
+        if !mask_ids.contains_key(&Rc::as_ptr(mask)) {
+            let id = format!("mask{}", mask_ids.len());
+            let element = mask
+                .iter()
+                .fold(MaskElement::new().set("id", id.as_str()), |m, p| {
+                    m.add(polygon_to_path(p, &HashMap::new()))
+                });
+            definitions = definitions.add(element);
+            mask_ids.insert(Rc::as_ptr(mask), id);
+        }
+    }
+
+    let view = square_view(car, world);
+    let mut document = Document::new()
+        .set("viewBox", view.to_vec())
+        .set("width", view[2])
+        .set("height", view[3])
+        .add(definitions);
+
+    if car.is_empty() {
+        return document;
+    }
+    for layer in car::get_lowest_layer(car)..=car::get_top_layer(car) {
+        let mut polygons = car.iter().filter(|p| p.get_layer() == layer).peekable();
+        if polygons.peek().is_none() {
+            continue;
+        }
+        let group = polygons.fold(Group::new().set("id", layer), |g, p| {
+            g.add(polygon_to_path(p, &mask_ids))
+        });
+        document = document.add(group);
+    }
+
+    document
+}
+
+/// Writes `car`, which lives in `world`, as an svg file to `path`.
+pub fn save_svg(path: &str, car: &Car, world: &WorldBounds) -> Result<()> {
+    svg::save(path, &car_to_document(car, world))
+        .with_context(|| format!("No se pudo guardar el svg en '{}'", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::parse_svg_content;
+    use crate::shapes::fixtures::point;
+    use crate::shapes::Transform2D;
+    use std::f32::consts::FRAC_PI_4;
+
+    const SVG: &str = r##"<svg viewBox="0 0 100 100" xmlns="http://www.w3.org/2000/svg">
+<mask id="fade"><path id="white" d="m 0,0 50,0 0,50 -50,0 z" style="fill:#ffffff;stroke:none"/></mask>
+<g id="0">
+<path id="body" mask="url(#fade)" d="m 10,10 80,0 0,40 -80,0 z" style="fill:#ff0000;stroke:#000000"/>
+</g>
+<g id="2">
+<path id="wheel" mask="url(#fade)" d="m 20,60 10,0 0,10 z" style="fill:#333333;stroke:none"/>
+<path id="antenna" display="none" d="m 50,60 0,30" style="fill:none;stroke:#000000"/>
+</g>
+</svg>"##;
+
+    /// Checks that `parsed` is `car` with every point p moved to `place(p)`.
+    fn assert_same_car(
+        car: &Car,
+        parsed: &Car,
+        place: impl Fn(Universal, Universal) -> (Universal, Universal),
+    ) {
+        assert_eq!(car.len(), parsed.len());
+        for (a, b) in car.iter().zip(parsed) {
+            assert_eq!(a.id(), b.id());
+            assert_eq!(a.get_layer(), b.get_layer());
+            assert_eq!(
+                color_to_style(a.get_fill_color()),
+                color_to_style(b.get_fill_color())
+            );
+            assert_eq!(
+                color_to_style(a.get_stroke_color()),
+                color_to_style(b.get_stroke_color())
+            );
+            assert_eq!(a.is_hidden(), b.is_hidden());
+            assert_eq!(a.get_borders().len(), b.get_borders().len());
+            for (p, q) in a
+                .get_borders()
+                .iter()
+                .flatten()
+                .zip(b.get_borders().iter().flatten())
+            {
+                let (x, y) = place(p.x(), p.y());
+                assert!(
+                    (x - q.x()).abs() < 1e-2 && (y - q.y()).abs() < 1e-2,
+                    "{:?} {:?}",
+                    p,
+                    q
+                );
+            }
+            assert_eq!(a.get_mask().map(|m| m.len()), b.get_mask().map(|m| m.len()));
+        }
+        let figure = &parsed[0].get_mask().unwrap()[0];
+        assert_eq!(color_to_style(figure.get_fill_color()), "#ffffff");
+    }
+
+    #[test]
+    fn pristine_cars_come_back_the_same() {
+        let car = parse_svg_content(SVG, 1000, 8.0).unwrap();
+        let document = car_to_document(&car, &WorldBounds::square(1000.0)).to_string();
+        assert!(
+            document.contains(r#"viewBox="0 0 1000 1000""#),
+            "{}",
+            document
+        );
+        assert_eq!(document.matches("<mask").count(), 1);
+        assert!(document.contains(r#"<g id="0">"#) && document.contains(r#"<g id="2">"#));
+
+        let parsed = parse_svg_content(&document, 1000, 8.0).unwrap();
+        assert_same_car(&car, &parsed, |x, y| (x, y));
+        // The mask is written once and stays shared
+        assert!(Rc::ptr_eq(
+            parsed[0].get_mask().unwrap(),
+            parsed[1].get_mask().unwrap()
+        ));
+    }
+
+    #[test]
+    fn transformed_cars_are_framed_whole() {
+        let mut car = parse_svg_content(SVG, 1000, 8.0).unwrap();
+        // 45 degrees, which takes the body's top corner out of the scene
+        car::transform_car(
+            &mut car,
+            &Transform2D::rotate(FRAC_PI_4).about(point(500.0, 500.0)),
+        );
+        let view = square_view(&car, &WorldBounds::square(1000.0));
+        assert!(view[1] < 0.0 && view[2] > 1000.0, "{:?}", view);
+
+        // Parsing it back fits the whole view in the scene
+        let document = car_to_document(&car, &WorldBounds::square(1000.0)).to_string();
+        let parsed = parse_svg_content(&document, 1000, 8.0).unwrap();
+        let scale = 1000.0 / view[2];
+        assert_same_car(&car, &parsed, |x, y| {
+            ((x - view[0]) * scale, (y - view[1]) * scale)
+        });
+    }
+
+    #[test]
+    fn unbounded_worlds_are_framed_around_the_content() {
+        let car = parse_svg_content(SVG, 1000, 8.0).unwrap();
+        let [x, y, width, height] = square_view(&car, &WorldBounds::Unbounded);
+        // The content goes from (0, 0) (the mask's corner) to (900, 900), with a little room
+        assert!(
+            width > 900.0 && width < 901.0 && width == height,
+            "{}",
+            width
+        );
+        assert!(x < 0.0 && x > -1.0 && y < 0.0 && y > -1.0, "{} {}", x, y);
+
+        assert_eq!(
+            square_view(&Car::new(), &WorldBounds::Unbounded),
+            [0.0, 0.0, 1.0, 1.0]
+        );
+    }
+}
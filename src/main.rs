@@ -1,32 +1,92 @@
+mod boolean;
+mod cache;
+mod canvas;
 mod car;
+mod clip;
+// For the driving demo to check the car against the scenery, the viewer doesn't collide anything
+#[allow(dead_code)]
+mod collision;
 mod constants;
+mod curves;
+mod export;
+// Halos, panel gaps and collision margins get derived from the parts by whoever needs them, the
+// viewer itself doesn't offset anything
+#[allow(dead_code)]
+mod offset;
 mod shapes;
+mod simplify;
+mod spatial;
+mod theme;
+mod triangulate;
 mod window;
 
 use anyhow::{Context, Result};
-use constants::{POINT_SPACING, SCENE_SIZE, WINDOW_HEIGHT, WINDOW_WIDTH, ZOOM_AMOUNT};
+use cache::CacheFormat;
+use constants::{
+    ANGLE_SNAP, CACHE_DIR, CURVE_TOLERANCE, EXPORT_PATH, FINE_ROTATION_STEP, FIT_MARGIN,
+    ROTATION_STEP, SCENE_SIZE, THEMES_PATH, WINDOW_HEIGHT, WINDOW_WIDTH, ZOOM_AMOUNT,
+};
+use export::ExportState;
 use futures::executor::block_on;
 use sdl_wrapper::{Event, Keycode};
-use window::{DisplayMode, Pan, Window};
+use shapes::{Universal, WorldBounds};
+use simplify::Simplification;
+use theme::Theme;
+use window::{Angle, DisplayMode, Fit, Pan, Pivot, Window};
 
 fn main() -> Result<()> {
     let path = "images/car.svg";
-    let car = car::parse_svg(path, SCENE_SIZE, POINT_SPACING)?;
-    let window = Window::new("2D World", WINDOW_WIDTH, WINDOW_HEIGHT, car)?;
+    let cache_format = if std::env::args().any(|arg| arg == "--json-cache") {
+        CacheFormat::Json
+    } else {
+        CacheFormat::Binary
+    };
+    let car = cache::load_car(path, SCENE_SIZE, CURVE_TOLERANCE, CACHE_DIR, cache_format)?;
+    if std::env::args().any(|arg| arg == "--metrics") {
+        car::print_metrics(&car);
+    }
+    let world =
+        match std::env::args().find_map(|arg| arg.strip_prefix("--world=").map(String::from)) {
+            Some(bounds) => bounds.parse()?,
+            None => WorldBounds::square(SCENE_SIZE as Universal),
+        };
+    let window = Window::new("2D World", WINDOW_WIDTH, WINDOW_HEIGHT, car, world)?;
+    // Polígono al que se ajusta la vista con B, hasta que se haga click en otro
+    let chosen = std::env::args().find_map(|arg| arg.strip_prefix("--polygon=").map(String::from));
+    let themes = theme::load_themes(THEMES_PATH).unwrap_or_else(|err| {
+        println!("No themes loaded: {:#}", err);
+        Vec::new()
+    });
 
-    block_on(screen_loop(window))?;
+    block_on(screen_loop(window, chosen, themes))?;
 
     Ok(())
 }
 
-async fn screen_loop(mut window: Window) -> Result<()> {
-    let (mut zoom, mut pan, mut rotate, mut reset, mut mode) = (
+async fn screen_loop(
+    mut window: Window,
+    mut chosen: Option<String>,
+    themes: Vec<Theme>,
+) -> Result<()> {
+    let (mut zoom, mut pan, mut rotate, mut reset, mut mode, mut toggle_hidden, mut export) = (
         1.0,
         Option::<Pan>::None,
-        0_i32,
+        0.0,
         false,
         Option::<DisplayMode>::None,
+        false,
+        Option::<ExportState>::None,
     );
+    let (mut pivot, mut toggle_snap, mut mouse, mut fit, mut click, mut cycle_simplification) = (
+        Pivot::SceneCenter,
+        false,
+        None,
+        Option::<Fit>::None,
+        None,
+        false,
+    );
+    // Tema actual (índice en `themes`), `None` para los colores originales
+    let (mut theme, mut cycle_theme) = (Option::<usize>::None, false);
 
     'main: loop {
         window.update().await?;
@@ -45,13 +105,33 @@ async fn screen_loop(mut window: Window) -> Result<()> {
                     Keycode::Down | Keycode::J => pan = Some(Pan::Down),
                     Keycode::Left | Keycode::H => pan = Some(Pan::Left),
                     Keycode::Right | Keycode::L => pan = Some(Pan::Right),
-                    Keycode::E => rotate = 1,
-                    Keycode::Q => rotate = -1,
+                    Keycode::E => rotate = ROTATION_STEP,
+                    Keycode::Q => rotate = -ROTATION_STEP,
+                    Keycode::X => rotate = FINE_ROTATION_STEP,
+                    Keycode::Z => rotate = -FINE_ROTATION_STEP,
+                    Keycode::O => {
+                        pivot = pivot.next();
+                        println!("Rotating around: {:?}", pivot);
+                    }
+                    Keycode::G => toggle_snap = true,
+                    Keycode::F => fit = Some(Fit::Car),
+                    Keycode::B => match &chosen {
+                        Some(id) => fit = Some(Fit::Polygon(id.clone())),
+                        None => println!("No polygon chosen, click one or pass --polygon=<id>"),
+                    },
+                    Keycode::I => cycle_simplification = true,
+                    Keycode::M => cycle_theme = true,
                     Keycode::R => reset = true,
                     Keycode::N => mode = Some(DisplayMode::NoColor),
                     Keycode::C => mode = Some(DisplayMode::ColorFill),
+                    Keycode::T => mode = Some(DisplayMode::Triangles),
+                    Keycode::V => toggle_hidden = true,
+                    Keycode::S => export = Some(ExportState::Transformed),
+                    Keycode::P => export = Some(ExportState::Pristine),
                     _ => (),
                 },
+                Event::MouseMotion { x, y, .. } => mouse = Some((x, y)),
+                Event::MouseButtonDown { x, y, .. } => click = Some((x, y)),
                 _ => (),
             }
         }
@@ -76,14 +156,81 @@ async fn screen_loop(mut window: Window) -> Result<()> {
             pan = None;
         }
 
+        if let Some(target) = fit.take() {
+            window
+                .fit(&target, FIT_MARGIN)
+                .unwrap_or_else(|err| println!("Fit unsuccesful: {}", err));
+        }
+
         if let Some(m) = mode {
             window.switch_mode(m);
             mode = None;
         }
 
-        if rotate != 0 {
-            window.rotate(rotate);
-            rotate = 0;
+        if toggle_hidden {
+            window.toggle_hidden();
+            toggle_hidden = false;
+        }
+
+        if let Some((x, y)) = mouse {
+            window.set_mouse(x, y);
+            mouse = None;
+        }
+
+        if let Some((x, y)) = click.take() {
+            match window.polygons_at(x, y) {
+                Ok(polygons) if polygons.is_empty() => println!("Nothing at ({}, {})", x, y),
+                Ok(polygons) => {
+                    for polygon in polygons.iter().rev() {
+                        println!("'{}' (layer {})", polygon.id(), polygon.get_layer());
+                    }
+                    // El de hasta arriba queda elegido para B
+                    chosen = polygons.last().map(|p| p.id().to_string());
+                }
+                Err(err) => println!("Couldn't look up what's under the mouse: {}", err),
+            }
+        }
+
+        if cycle_simplification {
+            window.set_simplification(Simplification::next(window.get_simplification()));
+            println!("Simplification: {:?}", window.get_simplification());
+            cycle_simplification = false;
+        }
+
+        if cycle_theme {
+            theme = match theme {
+                None if !themes.is_empty() => Some(0),
+                Some(i) if i + 1 < themes.len() => Some(i + 1),
+                _ => None,
+            };
+            window.set_theme(theme.map(|i| &themes[i]));
+            println!(
+                "Theme: {}",
+                theme.map_or("original colors", |i| themes[i].name())
+            );
+            cycle_theme = false;
+        }
+
+        if toggle_snap {
+            window.set_angle_snap(match window.get_angle_snap() {
+                Some(_) => None,
+                None => Some(Angle::Degrees(ANGLE_SNAP)),
+            });
+            println!("Angle snapping: {:?}", window.get_angle_snap());
+            toggle_snap = false;
+        }
+
+        if rotate != 0.0 {
+            window.rotate(Angle::Degrees(rotate), pivot);
+            rotate = 0.0;
+        }
+
+        if let Some(state) = export {
+            match window.export(EXPORT_PATH, state) {
+                Ok(_) => println!("Exported {:?} car to '{}'", state, EXPORT_PATH),
+                Err(err) => println!("Export unsuccesful: {:?}", err),
+            }
+            export = None;
         }
 
         if reset {
@@ -0,0 +1,379 @@
+//! Offsetting of lines and polygons by a signed distance, for halos around parts, insets for
+//! panel gaps and margins for collisions.
+//!
+//! Every segment is moved sideways by the distance and consecutive segments are joined: on the
+//! outer side of a corner with the chosen `Join`, on the inner side by just going through the
+//! point where the moved segments cross. Whatever comes out crossing itself is then cleaned up:
+//! loops are cut out of open lines, and closed borders are resolved into the region they go
+//! around clockwise.
+
+use crate::boolean;
+use crate::constants::{ARC_TOLERANCE, MITER_LIMIT};
+use crate::shapes::{
+    segments_intersect, Line, LineMethods, Orientation, Point, Polygon, Universal,
+};
+
+/// How offset segments are joined on the outer side of a corner.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Join {
+    /// Extends both segments until they meet, or cuts the corner flat (bevel) when they'd meet
+    /// further than `MITER_LIMIT` times the distance from the corner.
+    Miter,
+    /// Goes around the corner along a circle of radius the distance.
+    Round,
+    /// Extends both segments by the distance and cuts the corner there.
+    Square,
+}
+
+pub trait LineOffset {
+    /// Moves the line `distance` to its left on screen, which for a clockwise border is
+    /// outwards. An open line gives a single line with the loops that the offset makes on the
+    /// inside of tight turns cut out. A closed line gives the borders of the region that the
+    /// offset border goes around, going in the same direction as this one.
+    fn offset(&self, distance: Universal, join: Join) -> Vec<Line<Universal>>;
+}
+
+/// Normal pointing to the left on screen of the segment from `a` to `b`.
+fn left_normal(a: Point<Universal>, b: Point<Universal>) -> Option<Point<Universal>> {
+    let direction = (b - a).normalize()?;
+    Some(Point::new_unchecked(direction.y(), -direction.x()))
+}
+
+/// Rotates `v` by `angle`, positive angles going clockwise on screen.
+fn rotate(v: Point<Universal>, angle: Universal) -> Point<Universal> {
+    let (sin, cos) = angle.sin_cos();
+    Point::new_unchecked(v.x() * cos - v.y() * sin, v.x() * sin + v.y() * cos)
+}
+
+/// Adds the points that join, around `corner`, the segment arriving with direction `t1` and
+/// normal `n1` to the one leaving with `t2` and `n2`, both moved by `distance`. `inner_corner`
+/// is added on the inner side of turns, where the moved segments cross.
+fn join_corner(
+    out: &mut Line<Universal>,
+    corner: Point<Universal>,
+    (t1, n1): (Point<Universal>, Point<Universal>),
+    (t2, n2): (Point<Universal>, Point<Universal>),
+    distance: Universal,
+    join: Join,
+    inner_corner: bool,
+) {
+    let (a, b) = (corner + n1 * distance, corner + n2 * distance);
+    let turn = t1.cross(t2);
+    let straight = turn.abs() <= 1e-6 && t1.dot(t2) > 0.0;
+    if straight {
+        out.push(a);
+        return;
+    }
+    if turn * distance < 0.0 {
+        // Inner side: the moved segments cross, and the loop they make gets cleaned up later
+        out.push(a);
+        if inner_corner {
+            out.push(corner);
+        }
+        out.push(b);
+        return;
+    }
+
+    let radius = distance.abs();
+    // Directions from the corner to the moved segments
+    let (u1, u2) = (n1 * distance.signum(), n2 * distance.signum());
+    match join {
+        Join::Miter => {
+            let cos_half = ((1.0 + u1.dot(u2)) / 2.0).sqrt();
+            if cos_half * MITER_LIMIT > 1.0 {
+                out.push(corner + (u1 + u2) * (radius / (1.0 + u1.dot(u2))));
+            } else {
+                out.push(a);
+                out.push(b);
+            }
+        }
+        Join::Square => {
+            out.push(a + t1 * radius);
+            out.push(b - t2 * radius);
+        }
+        Join::Round => {
+            // The arc goes around the outside of the corner, which is ahead along `t1`
+            let perpendicular = Point::new_unchecked(-u1.y(), u1.x());
+            let sweep = u1.cross(u2).atan2(u1.dot(u2)).abs() * perpendicular.dot(t1).signum();
+            let step = 2.0 * (1.0 - (ARC_TOLERANCE / radius).min(1.0)).acos();
+            let steps = (sweep.abs() / step.max(1e-3)).ceil().max(1.0) as usize;
+            out.push(a);
+            for i in 1..steps {
+                out.push(corner + rotate(u1, sweep * i as Universal / steps as Universal) * radius);
+            }
+            out.push(b);
+        }
+    }
+}
+
+/// The line without repeated points, which have no direction to offset along.
+fn distinct_points(line: &[Point<Universal>]) -> Line<Universal> {
+    let mut points: Line<Universal> = line.iter().copied().filter(|p| p.is_finite()).collect();
+    points.dedup_by(|a, b| a.distance(*b) <= Universal::EPSILON * a.length().max(1.0));
+    points
+}
+
+/// Moves the closed border `ring` (given without repeating its first point), joining every
+/// corner including the one where it closes. The result may cross itself.
+fn offset_ring(ring: &[Point<Universal>], distance: Universal, join: Join) -> Line<Universal> {
+    let n = ring.len();
+    let segments: Vec<(Point<Universal>, Point<Universal>)> = (0..n)
+        .filter_map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % n]);
+            Some(((b - a).normalize()?, left_normal(a, b)?))
+        })
+        .collect();
+    if segments.len() != n {
+        return Vec::new();
+    }
+
+    let mut out = Vec::with_capacity(2 * n);
+    for i in 0..n {
+        let previous = segments[(i + n - 1) % n];
+        join_corner(
+            &mut out,
+            ring[i],
+            previous,
+            segments[i],
+            distance,
+            join,
+            true,
+        );
+    }
+    if let Some(first) = out.first() {
+        out.push(*first);
+    }
+    out
+}
+
+/// Point where segments (p0, p1) and (q0, q1) cross, if they do.
+fn crossing(
+    p0: Point<Universal>,
+    p1: Point<Universal>,
+    q0: Point<Universal>,
+    q1: Point<Universal>,
+) -> Option<Point<Universal>> {
+    if !segments_intersect(p0, p1, q0, q1) {
+        return None;
+    }
+    let (r, s) = (p1 - p0, q1 - q0);
+    let denominator = r.cross(s);
+    if denominator == 0.0 {
+        // Overlapping segments: the closest end of the second one is on both
+        return Some(if p0.distance(q0) < p0.distance(q1) {
+            q0
+        } else {
+            q1
+        });
+    }
+    let t = (q0 - p0).cross(s) / denominator;
+    Some(p0.lerp(p1, t.clamp(0.0, 1.0)))
+}
+
+/// Cuts out every loop of an open line: whenever a segment crosses a later one, everything in
+/// between is dropped and the line goes straight through the crossing.
+fn remove_loops(line: &[Point<Universal>]) -> Line<Universal> {
+    if line.len() < 4 {
+        return line.to_vec();
+    }
+    let mut out = vec![line[0]];
+    let mut current = line[0];
+    let mut i = 0;
+    while i + 1 < line.len() {
+        // The furthest crossing cuts the biggest loop, which holds any smaller ones
+        let cut = (i + 2..line.len() - 1).rev().find_map(|j| {
+            crossing(current, line[i + 1], line[j], line[j + 1]).map(|point| (j, point))
+        });
+        match cut {
+            Some((j, point)) => {
+                out.push(point);
+                current = point;
+                i = j;
+            }
+            None => {
+                out.push(line[i + 1]);
+                current = line[i + 1];
+                i += 1;
+            }
+        }
+    }
+    out.dedup();
+    out
+}
+
+impl LineOffset for Line<Universal> {
+    fn offset(&self, distance: Universal, join: Join) -> Vec<Line<Universal>> {
+        let points = distinct_points(self);
+        if points.len() < 2 || distance == 0.0 || !distance.is_finite() {
+            return vec![points];
+        }
+
+        if points.is_closed() && points.len() > 3 {
+            // Resolving works on clockwise borders, so other ones get flipped over and back
+            let clockwise = points.orientation() != Orientation::CounterClockwise;
+            let mut ring = points[..points.len() - 1].to_vec();
+            if !clockwise {
+                ring.reverse();
+            }
+            let distance = if clockwise { distance } else { -distance };
+            let mut borders = boolean::positive_region(&[offset_ring(&ring, distance, join)]);
+            if !clockwise {
+                borders.iter_mut().for_each(|b| b.reverse());
+            }
+            return borders;
+        }
+
+        let segments: Vec<(Point<Universal>, Point<Universal>)> = points
+            .windows(2)
+            .filter_map(|w| Some(((w[1] - w[0]).normalize()?, left_normal(w[0], w[1])?)))
+            .collect();
+        let mut out = vec![points[0] + segments[0].1 * distance];
+        for i in 1..points.len() - 1 {
+            let (previous, next) = (segments[i - 1], segments[i]);
+            join_corner(&mut out, points[i], previous, next, distance, join, false);
+        }
+        out.push(points[points.len() - 1] + segments[segments.len() - 1].1 * distance);
+        vec![remove_loops(&out)]
+    }
+}
+
+impl Polygon<Universal> {
+    /// Grows the polygon by `distance`, or shrinks it when it's negative, keeping its attributes.
+    /// Holes shrink when the polygon grows and the other way around, and the parts that get
+    /// swallowed or vanish are dropped, so the borders that come out never cross.
+    pub fn offset(&self, distance: Universal, join: Join) -> Polygon<Universal> {
+        if distance == 0.0 || !distance.is_finite() {
+            return self.clone();
+        }
+        // Oriented borders have the filled side on their right, so their left is always outwards
+        let moved: Vec<Line<Universal>> = boolean::oriented_borders(self)
+            .iter()
+            .map(|border| distinct_points(border))
+            .filter(|border| border.len() > 3)
+            .map(|border| offset_ring(&border[..border.len() - 1], distance, join))
+            .collect();
+        self.new_copy_attributes(boolean::positive_region(&moved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::{self, line, point, polygon};
+    use crate::shapes::FillRule;
+
+    /// Clockwise square, closed.
+    fn square(x: Universal, y: Universal, size: Universal) -> Line<Universal> {
+        let mut square = fixtures::square(x, y, size);
+        square.push(square[0]);
+        square
+    }
+
+    fn assert_about(value: Universal, expected: Universal, tolerance: Universal) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn right_angles_get_mitered() {
+        for join in [Join::Miter, Join::Square] {
+            let grown = polygon(vec![square(0.0, 0.0, 10.0)], FillRule::EvenOdd).offset(1.0, join);
+            assert_about(grown.signed_area(), 144.0, 1e-3);
+            let bounds = grown.bounding_box().unwrap();
+            assert_eq!(
+                (bounds.min(), bounds.max()),
+                (point(-1.0, -1.0), point(11.0, 11.0))
+            );
+        }
+    }
+
+    #[test]
+    fn sharp_miters_get_beveled() {
+        // The tip turns so sharply that a miter would reach about 20 past it
+        let spike = polygon(
+            vec![line(&[(0.0, 0.0), (100.0, 5.0), (0.0, 10.0), (0.0, 0.0)])],
+            FillRule::EvenOdd,
+        );
+        let grown = spike.offset(1.0, Join::Miter);
+        let tip = grown.bounding_box().unwrap().max().x();
+        assert!(tip > 100.0 && tip <= 100.0 + MITER_LIMIT, "{}", tip);
+    }
+
+    #[test]
+    fn round_joins_follow_a_circle() {
+        let grown =
+            polygon(vec![square(0.0, 0.0, 10.0)], FillRule::EvenOdd).offset(1.0, Join::Round);
+        // The square, a strip along each side and a quarter circle at each corner, whose arcs
+        // are cut short by at most the tolerance along their length
+        let (circle, area) = (std::f32::consts::PI, grown.signed_area());
+        assert!(area <= 140.0 + circle, "{}", area);
+        assert!(
+            area >= 140.0 + circle - 2.0 * circle * ARC_TOLERANCE,
+            "{}",
+            area
+        );
+        let corner = point(10.0, 10.0);
+        let around: Vec<Point<Universal>> = grown.get_borders()[0]
+            .iter()
+            .copied()
+            .filter(|p| p.x() >= 10.0 && p.y() >= 10.0)
+            .collect();
+        assert!(around.len() >= 3, "{:?}", around);
+        for p in around {
+            assert_about(p.distance(corner), 1.0, ARC_TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn insets_shrink_and_vanish() {
+        let square = polygon(vec![square(0.0, 0.0, 10.0)], FillRule::EvenOdd);
+        let inset = square.offset(-2.0, Join::Miter);
+        assert_about(inset.signed_area(), 36.0, 1e-3);
+        assert!(square.offset(-6.0, Join::Miter).get_borders().is_empty());
+    }
+
+    #[test]
+    fn holes_go_the_other_way() {
+        let donut = polygon(
+            vec![square(0.0, 0.0, 10.0), square(4.0, 4.0, 2.0)],
+            FillRule::EvenOdd,
+        );
+        let inset = donut.offset(-1.0, Join::Miter);
+        assert_eq!(inset.get_borders().len(), 2);
+        assert_about(inset.signed_area(), 64.0 - 16.0, 1e-3);
+        // Growing by the hole's half width closes it
+        let grown = donut.offset(1.0, Join::Miter);
+        assert_eq!(grown.get_borders().len(), 1);
+        assert_about(grown.signed_area(), 144.0, 1e-3);
+    }
+
+    #[test]
+    fn open_lines_move_to_their_left() {
+        let moved = line(&[(0.0, 0.0), (10.0, 0.0)]).offset(1.0, Join::Miter);
+        assert_eq!(moved, vec![line(&[(0.0, -1.0), (10.0, -1.0)])]);
+    }
+
+    #[test]
+    fn open_lines_lose_their_loops() {
+        // A zigzag whose inner corners are too tight for the offset
+        let zigzag = line(&[(0.0, 0.0), (2.0, 2.0), (4.0, 0.0), (6.0, 2.0), (8.0, 0.0)]);
+        let moved = zigzag.offset(-1.5, Join::Round);
+        assert_eq!(moved.len(), 1);
+        assert!(!moved[0].is_self_intersecting(), "{:?}", moved[0]);
+    }
+
+    #[test]
+    fn closed_lines_keep_their_direction() {
+        let mut counterclockwise = square(0.0, 0.0, 10.0);
+        counterclockwise.reverse();
+        // The left of a counterclockwise border is inwards
+        let moved = counterclockwise.offset(1.0, Join::Miter);
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].orientation(), Orientation::CounterClockwise);
+        assert_about(moved[0].signed_area().abs(), 64.0, 1e-3);
+    }
+}
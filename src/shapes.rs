@@ -1,13 +1,19 @@
 //! Mathematical representations of Points, Lines, and Polygons
 
 use std::cmp;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::rc::Rc;
+use std::str::FromStr;
 
-use crate::constants::{SCENE_SIZE, WINDOW_HEIGHT, WINDOW_WIDTH};
+use crate::boolean;
+use crate::constants::{WINDOW_HEIGHT, WINDOW_WIDTH};
+use crate::curves::Path;
 use crate::window::Window;
 use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 
 /// Important to note that this is a point in universal, continous coordinates.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Point<T> {
     x: T,
     y: T,
@@ -16,6 +22,20 @@ pub struct Point<T> {
 pub type Universal = f32;
 pub type Framebuffer = u32;
 
+/// Framebuffer coordinates in 24.8 fixed point, so that vertices keep their position inside the
+/// pixel instead of snapping to it.
+pub type Subpixel = i32;
+
+/// Fractional bits of `Subpixel` coordinates.
+pub const SUBPIXEL_BITS: u32 = 8;
+
+/// One pixel in `Subpixel` units.
+pub const SUBPIXEL_ONE: Subpixel = 1 << SUBPIXEL_BITS;
+
+/// How far (in pixels) outside the window a point can be and still get mapped to its closest
+/// pixel, to absorb rounding errors.
+const PIXEL_TOLERANCE: Universal = 1e-3;
+
 fn check_ranges<N: PartialOrd + ToString>(values: Vec<N>, min: N, max: N) -> Result<()> {
     let mut wrong_vals = values.iter().filter(|v| **v < min || **v > max).peekable();
     if wrong_vals.peek().is_some() {
@@ -36,9 +56,27 @@ impl Point<Framebuffer> {
 }
 
 impl Point<Universal> {
+    /// Only rejects coordinates that aren't finite, see `new_within()` to also check the world's
+    /// bounds.
     pub fn new(x: Universal, y: Universal) -> Result<Point<Universal>> {
-        check_ranges(vec![x, y], 0.0, SCENE_SIZE as Universal)?;
-        Ok(Point { x, y })
+        if x.is_finite() && y.is_finite() {
+            Ok(Point { x, y })
+        } else {
+            Err(anyhow!(
+                "Point ({}, {}) doesn't have finite coordinates",
+                x,
+                y
+            ))
+        }
+    }
+
+    pub fn new_within(x: Universal, y: Universal, world: &WorldBounds) -> Result<Point<Universal>> {
+        let point = Point::<Universal>::new(x, y)?;
+        if let WorldBounds::Bounded(bounds) = world {
+            check_ranges(vec![x], bounds.min.x, bounds.max.x)?;
+            check_ranges(vec![y], bounds.min.y, bounds.max.y)?;
+        }
+        Ok(point)
     }
 }
 
@@ -55,7 +93,495 @@ impl<T: Copy> Point<T> {
     }
 }
 
-#[derive(Copy, Clone)]
+/// Points in universal coordinates double as vectors.
+impl Point<Universal> {
+    pub fn dot(self, other: Point<Universal>) -> Universal {
+        self.x * other.x + self.y * other.y
+    }
+
+    /// z component of the 3D cross product, positive when `other` is clockwise from `self` on
+    /// screen.
+    pub fn cross(self, other: Point<Universal>) -> Universal {
+        self.x * other.y - self.y * other.x
+    }
+
+    pub fn length(self) -> Universal {
+        self.x.hypot(self.y)
+    }
+
+    /// Same direction with length 1, `None` for the zero vector.
+    pub fn normalize(self) -> Option<Point<Universal>> {
+        let length = self.length();
+        (length > 0.0).then(|| self / length)
+    }
+
+    /// Point at `t` of the way from `self` to `other` (`self` at 0, `other` at 1).
+    pub fn lerp(self, other: Point<Universal>, t: Universal) -> Point<Universal> {
+        self + (other - self) * t
+    }
+
+    pub fn distance(self, other: Point<Universal>) -> Universal {
+        (other - self).length()
+    }
+
+    pub fn is_finite(self) -> bool {
+        self.x.is_finite() && self.y.is_finite()
+    }
+
+    /// The point in subpixel framebuffer coordinates, where pixel (i, j) covers
+    /// [i, i + 1) x [j, j + 1) and has its center at (i + 0.5, j + 0.5). Fails for points outside
+    /// the window by more than `PIXEL_TOLERANCE`, and the ones just outside get moved onto its
+    /// edge.
+    pub fn to_subpixel(self) -> Result<Point<Subpixel>> {
+        let subpixel = |value: Universal, size: Framebuffer| {
+            let size = size as Universal;
+            if value >= -PIXEL_TOLERANCE && value <= size + PIXEL_TOLERANCE {
+                Some((value.clamp(0.0, size) * SUBPIXEL_ONE as Universal).round() as Subpixel)
+            } else {
+                None
+            }
+        };
+        match (
+            subpixel(self.x, WINDOW_WIDTH),
+            subpixel(self.y, WINDOW_HEIGHT),
+        ) {
+            (Some(x), Some(y)) => Ok(Point { x, y }),
+            _ => Err(anyhow!(
+                "Point ({}, {}) is outside of the framebuffer",
+                self.x,
+                self.y
+            )),
+        }
+    }
+}
+
+impl From<Point<Framebuffer>> for Point<Universal> {
+    fn from(point: Point<Framebuffer>) -> Self {
+        Point::new_unchecked(point.x as Universal, point.y as Universal)
+    }
+}
+
+impl Add for Point<Universal> {
+    type Output = Point<Universal>;
+    fn add(self, other: Point<Universal>) -> Point<Universal> {
+        Point::new_unchecked(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Point<Universal> {
+    type Output = Point<Universal>;
+    fn sub(self, other: Point<Universal>) -> Point<Universal> {
+        Point::new_unchecked(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Neg for Point<Universal> {
+    type Output = Point<Universal>;
+    fn neg(self) -> Point<Universal> {
+        Point::new_unchecked(-self.x, -self.y)
+    }
+}
+
+impl Mul<Universal> for Point<Universal> {
+    type Output = Point<Universal>;
+    fn mul(self, scalar: Universal) -> Point<Universal> {
+        Point::new_unchecked(self.x * scalar, self.y * scalar)
+    }
+}
+
+impl Mul<Point<Universal>> for Universal {
+    type Output = Point<Universal>;
+    fn mul(self, point: Point<Universal>) -> Point<Universal> {
+        point * self
+    }
+}
+
+impl Div<Universal> for Point<Universal> {
+    type Output = Point<Universal>;
+    fn div(self, scalar: Universal) -> Point<Universal> {
+        Point::new_unchecked(self.x / scalar, self.y / scalar)
+    }
+}
+
+/// An affine transformation, stored as the 3x2 matrix
+///
+/// ```text
+/// | a c e |
+/// | b d f |
+/// ```
+///
+/// (the same layout svg uses for `matrix(a b c d e f)`), which maps (x, y) to
+/// (a*x + c*y + e, b*x + d*y + f).
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transform2D {
+    a: Universal,
+    b: Universal,
+    c: Universal,
+    d: Universal,
+    e: Universal,
+    f: Universal,
+}
+
+impl Default for Transform2D {
+    fn default() -> Self {
+        Transform2D::identity()
+    }
+}
+
+impl Transform2D {
+    pub fn new(
+        a: Universal,
+        b: Universal,
+        c: Universal,
+        d: Universal,
+        e: Universal,
+        f: Universal,
+    ) -> Transform2D {
+        Transform2D { a, b, c, d, e, f }
+    }
+
+    pub fn identity() -> Transform2D {
+        Transform2D::new(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+    }
+
+    pub fn translate(tx: Universal, ty: Universal) -> Transform2D {
+        Transform2D::new(1.0, 0.0, 0.0, 1.0, tx, ty)
+    }
+
+    /// Scaling about the origin.
+    pub fn scale(sx: Universal, sy: Universal) -> Transform2D {
+        Transform2D::new(sx, 0.0, 0.0, sy, 0.0, 0.0)
+    }
+
+    /// Rotation about the origin by `theta` radians. Since y grows downwards in the scene,
+    /// positive angles turn clockwise on screen.
+    pub fn rotate(theta: Universal) -> Transform2D {
+        let (sin, cos) = theta.sin_cos();
+        Transform2D::new(cos, sin, -sin, cos, 0.0, 0.0)
+    }
+
+    /// Composition that applies `self` first and `next` afterwards.
+    pub fn then(&self, next: &Transform2D) -> Transform2D {
+        Transform2D {
+            a: next.a * self.a + next.c * self.b,
+            b: next.b * self.a + next.d * self.b,
+            c: next.a * self.c + next.c * self.d,
+            d: next.b * self.c + next.d * self.d,
+            e: next.a * self.e + next.c * self.f + next.e,
+            f: next.b * self.e + next.d * self.f + next.f,
+        }
+    }
+
+    pub fn invert(&self) -> Result<Transform2D> {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() <= Universal::EPSILON {
+            return Err(anyhow!("Transformación no es invertible: {:?}", self));
+        }
+        Ok(Transform2D {
+            a: self.d / det,
+            b: -self.b / det,
+            c: -self.c / det,
+            d: self.a / det,
+            e: (self.c * self.f - self.d * self.e) / det,
+            f: (self.b * self.e - self.a * self.f) / det,
+        })
+    }
+
+    /// The result isn't checked against the scene's boundaries.
+    pub fn apply(&self, point: Point<Universal>) -> Point<Universal> {
+        Point::new_unchecked(
+            self.a * point.x() + self.c * point.y() + self.e,
+            self.b * point.x() + self.d * point.y() + self.f,
+        )
+    }
+}
+
+// The viewer keeps its own placement in a `ModelTransform`, but these round out the affine
+// toolkit for the transforms an svg can carry.
+#[allow(dead_code)]
+impl Transform2D {
+    /// The same transformation, but with `pivot` acting as the origin. For example
+    /// `Transform2D::rotate(theta).about(center)` rotates about `center`.
+    pub fn about(&self, pivot: Point<Universal>) -> Transform2D {
+        Transform2D::translate(-pivot.x(), -pivot.y())
+            .then(self)
+            .then(&Transform2D::translate(pivot.x(), pivot.y()))
+    }
+
+    /// Skew by the angles (in radians) that vertical lines make with the y axis (`x_angle`) and
+    /// horizontal lines with the x axis (`y_angle`), like svg's `skewX()` and `skewY()`.
+    pub fn skew(x_angle: Universal, y_angle: Universal) -> Transform2D {
+        Transform2D::shear(x_angle.tan(), y_angle.tan())
+    }
+
+    /// Shear where x moves by `shx` times y, and y by `shy` times x.
+    pub fn shear(shx: Universal, shy: Universal) -> Transform2D {
+        Transform2D::new(1.0, shy, shx, 1.0, 0.0, 0.0)
+    }
+
+    /// Reflection across the line through the origin that makes `angle` radians with the x axis.
+    pub fn reflect(angle: Universal) -> Transform2D {
+        let (sin, cos) = (2.0 * angle).sin_cos();
+        Transform2D::new(cos, sin, sin, -cos, 0.0, 0.0)
+    }
+}
+
+/// Axis-aligned rectangle that encloses a shape.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    min: Point<Universal>,
+    max: Point<Universal>,
+}
+
+// Not every query has a caller in the viewer yet
+#[allow(dead_code)]
+impl BoundingBox {
+    pub fn new(min: Point<Universal>, max: Point<Universal>) -> BoundingBox {
+        BoundingBox {
+            min: Point::new_unchecked(min.x().min(max.x()), min.y().min(max.y())),
+            max: Point::new_unchecked(min.x().max(max.x()), min.y().max(max.y())),
+        }
+    }
+
+    /// Smallest box containing all `points`, `None` if there are none.
+    pub fn from_points<I: IntoIterator<Item = Point<Universal>>>(points: I) -> Option<BoundingBox> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(
+            points.fold(BoundingBox::new(first, first), |bb, p| BoundingBox {
+                min: Point::new_unchecked(bb.min.x().min(p.x()), bb.min.y().min(p.y())),
+                max: Point::new_unchecked(bb.max.x().max(p.x()), bb.max.y().max(p.y())),
+            }),
+        )
+    }
+
+    pub fn min(&self) -> Point<Universal> {
+        self.min
+    }
+    pub fn max(&self) -> Point<Universal> {
+        self.max
+    }
+    pub fn width(&self) -> Universal {
+        self.max.x() - self.min.x()
+    }
+    pub fn height(&self) -> Universal {
+        self.max.y() - self.min.y()
+    }
+    pub fn center(&self) -> Point<Universal> {
+        Point::new_unchecked(
+            (self.min.x() + self.max.x()) / 2.0,
+            (self.min.y() + self.max.y()) / 2.0,
+        )
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Point::new_unchecked(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+            ),
+            max: Point::new_unchecked(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+            ),
+        }
+    }
+
+    /// Borders are inclusive.
+    pub fn contains(&self, point: Point<Universal>) -> bool {
+        point.x() >= self.min.x()
+            && point.x() <= self.max.x()
+            && point.y() >= self.min.y()
+            && point.y() <= self.max.y()
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min.x() <= other.max.x()
+            && other.min.x() <= self.max.x()
+            && self.min.y() <= other.max.y()
+            && other.min.y() <= self.max.y()
+    }
+
+    /// Grows the box by `amount` on every side (shrinks it if negative).
+    pub fn expand(&self, amount: Universal) -> BoundingBox {
+        BoundingBox::new(
+            Point::new_unchecked(self.min.x() - amount, self.min.y() - amount),
+            Point::new_unchecked(self.max.x() + amount, self.max.y() + amount),
+        )
+    }
+
+    /// Box enclosing this one after being transformed. It's exact for translations and scalings,
+    /// but for rotations it's bigger than the box of the transformed shape would be.
+    pub fn transformed(&self, transform: &Transform2D) -> BoundingBox {
+        let corners = [
+            self.min,
+            Point::new_unchecked(self.max.x(), self.min.y()),
+            self.max,
+            Point::new_unchecked(self.min.x(), self.max.y()),
+        ];
+        BoundingBox::from_points(corners.iter().map(|c| transform.apply(*c)))
+            .expect("a box always has corners")
+    }
+}
+
+/// The part of the universal coordinates that makes up the world.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WorldBounds {
+    /// Only the points inside the box (borders included)
+    Bounded(BoundingBox),
+    /// Any point with finite coordinates
+    Unbounded,
+}
+
+impl WorldBounds {
+    /// The square from (0, 0) to (`size`, `size`).
+    pub fn square(size: Universal) -> WorldBounds {
+        WorldBounds::Bounded(BoundingBox::new(
+            Point::new_unchecked(0.0, 0.0),
+            Point::new_unchecked(size, size),
+        ))
+    }
+
+    pub fn contains(&self, point: Point<Universal>) -> bool {
+        match self {
+            WorldBounds::Bounded(bounds) => bounds.contains(point),
+            WorldBounds::Unbounded => point.x.is_finite() && point.y.is_finite(),
+        }
+    }
+
+    /// Closest point of the world to `point`.
+    pub fn clamp(&self, point: Point<Universal>) -> Point<Universal> {
+        match self {
+            WorldBounds::Bounded(bounds) => Point::new_unchecked(
+                point.x.max(bounds.min.x).min(bounds.max.x),
+                point.y.max(bounds.min.y).min(bounds.max.y),
+            ),
+            WorldBounds::Unbounded => point,
+        }
+    }
+
+    /// `None` for an unbounded world.
+    pub fn as_box(&self) -> Option<BoundingBox> {
+        match self {
+            WorldBounds::Bounded(bounds) => Some(*bounds),
+            WorldBounds::Unbounded => None,
+        }
+    }
+}
+
+/// Parses either `unbounded` or the corners of the world as `min_x,min_y,max_x,max_y`.
+impl FromStr for WorldBounds {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<WorldBounds> {
+        if s.trim() == "unbounded" {
+            return Ok(WorldBounds::Unbounded);
+        }
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<Universal>())
+            .collect::<Result<Vec<Universal>, _>>()
+            .map_err(|err| anyhow!("Límites del mundo '{}' no son números: {}", s, err))?;
+        match values[..] {
+            [min_x, min_y, max_x, max_y] if min_x < max_x && min_y < max_y => {
+                Ok(WorldBounds::Bounded(BoundingBox::new(
+                    Point::<Universal>::new(min_x, min_y)?,
+                    Point::<Universal>::new(max_x, max_y)?,
+                )))
+            }
+            [_, _, _, _] => Err(anyhow!(
+                "Límites del mundo '{}' no tienen el mínimo por debajo del máximo",
+                s
+            )),
+            _ => Err(anyhow!(
+                "Límites del mundo '{}' no son 'unbounded' ni 'min_x,min_y,max_x,max_y'",
+                s
+            )),
+        }
+    }
+}
+
+/// Placement of a model in the scene: scaled (about the origin) first, then rotated and finally
+/// translated. Unlike composing matrices, these three values can't drift into a skew no matter
+/// how many operations get accumulated.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelTransform {
+    /// Radians
+    rotation: Universal,
+    translation: (Universal, Universal),
+    scale: Universal,
+}
+
+impl Default for ModelTransform {
+    fn default() -> Self {
+        ModelTransform::identity()
+    }
+}
+
+impl ModelTransform {
+    pub fn identity() -> ModelTransform {
+        ModelTransform {
+            rotation: 0.0,
+            translation: (0.0, 0.0),
+            scale: 1.0,
+        }
+    }
+
+    pub fn rotation(&self) -> Universal {
+        self.rotation
+    }
+
+    pub fn scale(&self) -> Universal {
+        self.scale
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == ModelTransform::identity()
+    }
+
+    pub fn to_matrix(self) -> Transform2D {
+        Transform2D::scale(self.scale, self.scale)
+            .then(&Transform2D::rotate(self.rotation))
+            .then(&Transform2D::translate(
+                self.translation.0,
+                self.translation.1,
+            ))
+    }
+
+    /// Where a point of the model ends up in the scene.
+    pub fn apply(&self, point: Point<Universal>) -> Point<Universal> {
+        self.to_matrix().apply(point)
+    }
+
+    /// Rotates the placed model `theta` radians about `pivot` (in scene coordinates).
+    pub fn rotate_about(&mut self, theta: Universal, pivot: Point<Universal>) {
+        let moved = Transform2D::rotate(theta).apply(Point::new_unchecked(
+            self.translation.0 - pivot.x(),
+            self.translation.1 - pivot.y(),
+        ));
+        self.translation = (pivot.x() + moved.x(), pivot.y() + moved.y());
+        self.rotation += theta;
+    }
+
+    // Only rotations are bound to keys so far; moving and scaling the car come with the svg's
+    // own transforms
+    #[allow(dead_code)]
+    pub fn translate(&mut self, dx: Universal, dy: Universal) {
+        self.translation = (self.translation.0 + dx, self.translation.1 + dy);
+    }
+
+    /// Scales the placed model by `factor` about `pivot` (in scene coordinates).
+    #[allow(dead_code)]
+    pub fn scale_about(&mut self, factor: Universal, pivot: Point<Universal>) {
+        self.translation = (
+            pivot.x() + (self.translation.0 - pivot.x()) * factor,
+            pivot.y() + (self.translation.1 - pivot.y()) * factor,
+        );
+        self.scale *= factor;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Color {
     r: f32,
     g: f32,
@@ -67,6 +593,12 @@ fn is_hex_format(hex: &str) -> bool {
 }
 
 impl Color {
+    pub const BLACK: Color = Color {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+    };
+
     pub fn new(r: f32, g: f32, b: f32) -> Result<Color> {
         check_ranges(vec![r, g, b], 0.0, 1.0)?;
         Ok(Color { r, g, b })
@@ -96,6 +628,122 @@ impl Color {
     pub fn b(&self) -> f32 {
         self.b
     }
+
+    /// Formats the color as '#rrggbb', the inverse of `from_hex()`.
+    pub fn to_hex(self) -> String {
+        let channel = |c: f32| (c * 255.0).round() as u8;
+        format!(
+            "#{:02x}{:02x}{:02x}",
+            channel(self.r),
+            channel(self.g),
+            channel(self.b)
+        )
+    }
+}
+
+// Themes only swap whole colors, these are for tools that work on hue or fade between them
+#[allow(dead_code)]
+impl Color {
+    /// Color from hue (in degrees), saturation and value, the last two from 0 to 1.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Result<Color> {
+        check_ranges(vec![saturation, value], 0.0, 1.0)?;
+        let chroma = value * saturation;
+        Color::from_hue(hue, chroma, value - chroma)
+    }
+
+    /// Hue (in degrees, from 0 to 360), saturation and value of the color.
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue_max_min();
+        let saturation = if max > 0.0 { (max - min) / max } else { 0.0 };
+        (hue, saturation, max)
+    }
+
+    /// Color from hue (in degrees), saturation and lightness, the last two from 0 to 1.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Result<Color> {
+        check_ranges(vec![saturation, lightness], 0.0, 1.0)?;
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        Color::from_hue(hue, chroma, lightness - chroma / 2.0)
+    }
+
+    /// Hue (in degrees, from 0 to 360), saturation and lightness of the color.
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue_max_min();
+        let lightness = (max + min) / 2.0;
+        let saturation = if lightness > 0.0 && lightness < 1.0 {
+            (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+        } else {
+            0.0
+        };
+        (hue, saturation.min(1.0), lightness)
+    }
+
+    /// The color with the given hue and chroma, `min` being added to every channel.
+    fn from_hue(hue: f32, chroma: f32, min: f32) -> Result<Color> {
+        if !hue.is_finite() {
+            return Err(anyhow!("Hue no es un número finito: {}", hue));
+        }
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        // Rounding can leave a channel a hair outside of [0, 1]
+        let channel = |c: f32| (c + min).clamp(0.0, 1.0);
+        Color::new(channel(r), channel(g), channel(b))
+    }
+
+    /// Hue (in degrees) and the largest and smallest channels, shared by HSV and HSL.
+    fn hue_max_min(&self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let chroma = max - min;
+        let sector = if chroma == 0.0 {
+            0.0
+        } else if max == self.r {
+            ((self.g - self.b) / chroma).rem_euclid(6.0)
+        } else if max == self.g {
+            (self.b - self.r) / chroma + 2.0
+        } else {
+            (self.r - self.g) / chroma + 4.0
+        };
+        (sector * 60.0, max, min)
+    }
+
+    /// Goes from `self` (at `t = 0`) to `other` (at `t = 1`) in a straight line through RGB.
+    pub fn lerp(&self, other: Color, t: f32) -> Color {
+        other.blend(*self, t.clamp(0.0, 1.0))
+    }
+
+    /// Goes from `self` to `other` through HSV, turning the hue the short way around, which
+    /// keeps the colors in between saturated (instead of going through gray).
+    pub fn lerp_hsv(&self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let ((h0, s0, v0), (h1, s1, v1)) = (self.to_hsv(), other.to_hsv());
+        let turn = (h1 - h0 + 180.0).rem_euclid(360.0) - 180.0;
+        Color::from_hsv(h0 + turn * t, s0 + (s1 - s0) * t, v0 + (v1 - v0) * t)
+            .unwrap_or_else(|_| self.lerp(other, t))
+    }
+}
+
+impl Color {
+    /// Relative luminance of the color, as used by svg luminance masks.
+    pub fn luminance(&self) -> f32 {
+        0.2125 * self.r + 0.7154 * self.g + 0.0721 * self.b
+    }
+
+    /// Mixes `self` over `background`, `alpha` being the opacity of `self`.
+    pub fn blend(&self, background: Color, alpha: f32) -> Color {
+        Color {
+            r: self.r * alpha + background.r * (1.0 - alpha),
+            g: self.g * alpha + background.g * (1.0 - alpha),
+            b: self.b * alpha + background.b * (1.0 - alpha),
+        }
+    }
 }
 
 /// Note that a 'Line' isn't a straight 2-point line. It's composed of an arbitrary amount of
@@ -103,34 +751,203 @@ impl Color {
 /// line circles back then the last Point will be equal to the first one.
 pub type Line<T> = Vec<Point<T>>;
 
-/// Represents a straight segment, with (x0, y0) being the starting point and (x1, y1) the ending point.
+/// Represents a straight segment in subpixel framebuffer coordinates, with (x0, y0) being the
+/// starting point and (x1, y1) the ending point.
 #[derive(Debug)]
 pub struct Segment {
-    pub x0: Framebuffer,
-    pub y0: Framebuffer,
-    pub x1: Framebuffer,
-    pub y1: Framebuffer,
+    pub x0: Subpixel,
+    pub y0: Subpixel,
+    pub x1: Subpixel,
+    pub y1: Subpixel,
 }
 
 impl Segment {
-    pub fn y_max(&self) -> Framebuffer {
+    pub fn y_max(&self) -> Subpixel {
         cmp::max(self.y0, self.y1)
     }
-    pub fn y_min(&self) -> Framebuffer {
+    pub fn y_min(&self) -> Subpixel {
         cmp::min(self.y0, self.y1)
     }
 
-    pub fn x_of_y_max(&self) -> Framebuffer {
-        match self.y0.cmp(&self.y1) {
-            cmp::Ordering::Less => self.x1,
-            cmp::Ordering::Greater => self.x0,
-            cmp::Ordering::Equal => WINDOW_WIDTH,
+    /// x of the segment's line at height `y`, in subpixel units. Meaningless for horizontal
+    /// segments.
+    pub fn x_at(&self, y: Universal) -> Universal {
+        let t = (y - self.y0 as Universal) / (self.y1 - self.y0) as Universal;
+        self.x0 as Universal + t * (self.x1 - self.x0) as Universal
+    }
+}
+
+/// Direction in which a closed line goes around. Since y grows downwards, these are as seen on
+/// screen.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Orientation {
+    Clockwise,
+    CounterClockwise,
+    /// The line encloses no area
+    Degenerate,
+}
+
+/// Rule deciding which points are inside a shape whose borders overlap or nest, as in svg's
+/// `fill-rule`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum FillRule {
+    /// Inside if the borders wind around the point a nonzero amount of times (svg's default)
+    #[default]
+    NonZero,
+    /// Inside if a ray from the point crosses the borders an odd amount of times
+    EvenOdd,
+}
+
+impl FillRule {
+    /// Whether a point around which the borders wind `winding` times is inside.
+    pub fn is_inside(&self, winding: i32) -> bool {
+        match self {
+            FillRule::NonZero => winding != 0,
+            FillRule::EvenOdd => winding % 2 != 0,
         }
     }
 }
 
+/// Measurements on lines. The ones about enclosed area treat the line as closed even if its last
+/// point isn't equal to the first one, the same way a fill does.
 pub trait LineMethods<T> {
-    fn euclidean_length(&self) -> T;
+    fn transform(&mut self, transform: &Transform2D);
+    fn bounding_box(&self) -> Option<BoundingBox>;
+
+    fn is_closed(&self) -> bool;
+    /// Length including the segment that closes the line.
+    fn perimeter(&self) -> T;
+    /// Shoelace area, positive for clockwise lines.
+    fn signed_area(&self) -> T;
+    fn orientation(&self) -> Orientation;
+    /// Center of mass of the enclosed area, `None` if there's no area.
+    fn centroid(&self) -> Option<Point<T>>;
+    /// Even-odd test of whether `point` is inside the enclosed area.
+    fn encloses(&self, point: Point<T>) -> bool;
+    /// How many times the line goes around `point`, positive for clockwise turns.
+    fn winding_number(&self, point: Point<T>) -> i32;
+    /// Whether any two segments that aren't neighbours touch.
+    fn is_self_intersecting(&self) -> bool;
+}
+
+/// The line's segments, plus the closing one if the last point isn't the first.
+pub(crate) fn closed_segments(
+    line: &[Point<Universal>],
+) -> Vec<(Point<Universal>, Point<Universal>)> {
+    let mut segments: Vec<(Point<Universal>, Point<Universal>)> =
+        line.windows(2).map(|w| (w[0], w[1])).collect();
+    if let (Some(first), Some(last)) = (line.first(), line.last()) {
+        if first != last && line.len() > 2 {
+            segments.push((*last, *first));
+        }
+    }
+    segments
+}
+
+/// The closed segments of `line` that have some length. A repeated point would otherwise leave
+/// a zero-length segment between two neighbours, which then touch without being consecutive.
+fn proper_segments(line: &[Point<Universal>]) -> Vec<(Point<Universal>, Point<Universal>)> {
+    closed_segments(line)
+        .into_iter()
+        .filter(|(a, b)| a != b)
+        .collect()
+}
+
+fn cross_product(o: Point<Universal>, a: Point<Universal>, b: Point<Universal>) -> f64 {
+    (a.x() as f64 - o.x() as f64) * (b.y() as f64 - o.y() as f64)
+        - (a.y() as f64 - o.y() as f64) * (b.x() as f64 - o.x() as f64)
+}
+
+/// Whether `p` lies within the bounding box of segment (a, b), used once it's known to be collinear.
+fn within_segment_box(a: Point<Universal>, b: Point<Universal>, p: Point<Universal>) -> bool {
+    p.x() >= a.x().min(b.x())
+        && p.x() <= a.x().max(b.x())
+        && p.y() >= a.y().min(b.y())
+        && p.y() <= a.y().max(b.y())
+}
+
+/// Whether segments (p0, p1) and (q0, q1) share at least one point.
+pub fn segments_intersect(
+    p0: Point<Universal>,
+    p1: Point<Universal>,
+    q0: Point<Universal>,
+    q1: Point<Universal>,
+) -> bool {
+    let d1 = cross_product(q0, q1, p0);
+    let d2 = cross_product(q0, q1, p1);
+    let d3 = cross_product(p0, p1, q0);
+    let d4 = cross_product(p0, p1, q1);
+
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+    {
+        return true;
+    }
+    (d1 == 0.0 && within_segment_box(q0, q1, p0))
+        || (d2 == 0.0 && within_segment_box(q0, q1, p1))
+        || (d3 == 0.0 && within_segment_box(p0, p1, q0))
+        || (d4 == 0.0 && within_segment_box(p0, p1, q1))
+}
+
+/// Looks for touching segments among `segments`, which are tagged with the border they belong
+/// to and their index in it. Neighbouring segments of the same border (which always share a
+/// point) are skipped, `border_lengths` telling where each closed border wraps around.
+fn any_segments_intersect(
+    segments: &[(usize, usize, Point<Universal>, Point<Universal>)],
+    border_lengths: &[usize],
+) -> bool {
+    let min_x = |s: &(usize, usize, Point<Universal>, Point<Universal>)| s.2.x().min(s.3.x());
+    let max_x = |s: &(usize, usize, Point<Universal>, Point<Universal>)| s.2.x().max(s.3.x());
+
+    let mut order: Vec<usize> = (0..segments.len()).collect();
+    order.sort_unstable_by(|i, j| min_x(&segments[*i]).total_cmp(&min_x(&segments[*j])));
+
+    order.iter().enumerate().any(|(k, i)| {
+        let (border_a, index_a, p0, p1) = segments[*i];
+        order[k + 1..]
+            .iter()
+            .take_while(|j| min_x(&segments[**j]) <= max_x(&segments[*i]))
+            .any(|j| {
+                let (border_b, index_b, q0, q1) = segments[*j];
+                let neighbours = border_a == border_b && {
+                    let n = border_lengths[border_a];
+                    let (lo, hi) = (index_a.min(index_b), index_a.max(index_b));
+                    hi - lo == 1 || (lo == 0 && hi == n - 1 && n > 2)
+                };
+                !neighbours && segments_intersect(p0, p1, q0, q1)
+            })
+    })
+}
+
+/// Each border's area (negative for holes) and centroid, for borders that don't cross each
+/// other. What a border adds is whether `rule` fills the area just inside it minus whether it
+/// fills the area just outside, so under "nonzero" a border going the same way as the one around
+/// it adds nothing.
+fn signed_parts(borders: &[Line<Universal>], rule: FillRule) -> Vec<(Universal, Point<Universal>)> {
+    let windings: Vec<i32> = borders
+        .iter()
+        .map(|border| match border.orientation() {
+            Orientation::Clockwise => 1,
+            Orientation::CounterClockwise => -1,
+            Orientation::Degenerate => 0,
+        })
+        .collect();
+    borders
+        .iter()
+        .enumerate()
+        .filter_map(|(i, border)| {
+            let centroid = border.centroid()?;
+            let outside: i32 = borders
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.encloses(border[0]))
+                .map(|(j, _)| windings[j])
+                .sum();
+            let weight =
+                rule.is_inside(outside + windings[i]) as i32 - rule.is_inside(outside) as i32;
+            Some((weight as Universal * border.signed_area().abs(), centroid))
+        })
+        .collect()
 }
 
 pub trait LineClip {
@@ -141,14 +958,273 @@ pub trait LineClip {
         intersection: fn(Point<Universal>, Point<Universal>, Universal) -> Point<Universal>,
         inside_edge: fn(&Window, Point<Universal>, Universal) -> bool,
     ) -> Line<Universal>;
+    /// Clips the line as an open stroke (Liang–Barsky): every stretch inside `bounds` becomes
+    /// its own line, instead of being joined to the next one along the box's edges.
+    fn clip_stroke(&self, bounds: &BoundingBox) -> Vec<Line<Universal>>;
 }
 
 impl LineMethods<Universal> for Line<Universal> {
-    fn euclidean_length(&self) -> Universal {
-        self.windows(2)
-            .map(|w| ((w[1].x() - w[0].x()).powi(2) + (w[1].y() - w[0].y()).powi(2)).sqrt())
+    fn transform(&mut self, transform: &Transform2D) {
+        for point in self.iter_mut() {
+            *point = transform.apply(*point);
+        }
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        BoundingBox::from_points(self.iter().copied())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.len() > 1 && self.first() == self.last()
+    }
+
+    fn perimeter(&self) -> Universal {
+        closed_segments(self)
+            .iter()
+            .map(|(a, b)| ((b.x() - a.x()).powi(2) + (b.y() - a.y()).powi(2)).sqrt())
             .sum()
     }
+
+    fn signed_area(&self) -> Universal {
+        // f64 because the products of coordinates lose too much precision in f32 when summed
+        (closed_segments(self)
+            .iter()
+            .map(|(a, b)| a.x() as f64 * b.y() as f64 - b.x() as f64 * a.y() as f64)
+            .sum::<f64>()
+            / 2.0) as Universal
+    }
+
+    fn orientation(&self) -> Orientation {
+        let area = self.signed_area();
+        if area > 0.0 {
+            Orientation::Clockwise
+        } else if area < 0.0 {
+            Orientation::CounterClockwise
+        } else {
+            Orientation::Degenerate
+        }
+    }
+
+    fn centroid(&self) -> Option<Point<Universal>> {
+        let (area, cx, cy) = closed_segments(self).iter().fold(
+            (0.0, 0.0, 0.0),
+            |(area, cx, cy): (f64, f64, f64), (a, b)| {
+                let (ax, ay, bx, by) = (a.x() as f64, a.y() as f64, b.x() as f64, b.y() as f64);
+                let cross = ax * by - bx * ay;
+                (area + cross, cx + (ax + bx) * cross, cy + (ay + by) * cross)
+            },
+        );
+        (area != 0.0).then(|| {
+            Point::new_unchecked(
+                (cx / (3.0 * area)) as Universal,
+                (cy / (3.0 * area)) as Universal,
+            )
+        })
+    }
+
+    fn encloses(&self, point: Point<Universal>) -> bool {
+        closed_segments(self)
+            .iter()
+            .filter(|(a, b)| (a.y() > point.y()) != (b.y() > point.y()))
+            .filter(|(a, b)| {
+                point.x() < a.x() + (point.y() - a.y()) * (b.x() - a.x()) / (b.y() - a.y())
+            })
+            .count()
+            % 2
+            == 1
+    }
+
+    fn winding_number(&self, point: Point<Universal>) -> i32 {
+        closed_segments(self)
+            .iter()
+            .map(|(a, b)| {
+                let side = cross_product(*a, *b, point);
+                if a.y() <= point.y() && b.y() > point.y() && side > 0.0 {
+                    // Crossing downwards with the point to the right
+                    1
+                } else if a.y() > point.y() && b.y() <= point.y() && side < 0.0 {
+                    -1
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+
+    fn is_self_intersecting(&self) -> bool {
+        let segments: Vec<(usize, usize, Point<Universal>, Point<Universal>)> =
+            proper_segments(self)
+                .into_iter()
+                .enumerate()
+                .map(|(i, (a, b))| (0, i, a, b))
+                .collect();
+        any_segments_intersect(&segments, &[segments.len()])
+    }
+}
+
+/// Arc-length parametrization of a line: positions along it are given by the distance travelled
+/// from its first point. Distances outside of the line are clamped to its ends, and distances
+/// that aren't finite give nothing.
+///
+/// Every call measures the whole line, so looking up several positions is better done on the
+/// [`MeasuredLine`] from `measured()`.
+#[allow(dead_code)] // For dashes, markers and text on paths, which the viewer doesn't draw yet
+pub trait ArcLength {
+    /// The line with the distance to each of its points measured once.
+    fn measured(&self) -> MeasuredLine<'_>;
+    /// Point reached after travelling `distance` along the line, `None` if it has no points.
+    fn point_at(&self, distance: Universal) -> Option<Point<Universal>>;
+    /// Unit direction of the line at `distance`, `None` if it has no length.
+    fn tangent_at(&self, distance: Universal) -> Option<Point<Universal>>;
+    /// Unit normal to the left of the line on screen at `distance`, which for a clockwise
+    /// border points outwards.
+    fn normal_at(&self, distance: Universal) -> Option<Point<Universal>>;
+    /// The stretch of the line between two distances, going backwards if `to` comes before
+    /// `from`.
+    fn sub_line(&self, from: Universal, to: Universal) -> Line<Universal>;
+    /// Points every `spacing` along the line, from its first point, plus its last one.
+    fn resample(&self, spacing: Universal) -> Line<Universal>;
+}
+
+/// A line together with the distance along it at each of its points, which is what every
+/// [`ArcLength`] query needs. Positions are then looked up with a binary search.
+#[allow(dead_code)] // For dashes, markers and text on paths, which the viewer doesn't draw yet
+pub struct MeasuredLine<'a> {
+    line: &'a [Point<Universal>],
+    lengths: Vec<Universal>,
+}
+
+#[allow(dead_code)] // For dashes, markers and text on paths, which the viewer doesn't draw yet
+impl<'a> MeasuredLine<'a> {
+    pub fn new(line: &'a [Point<Universal>]) -> MeasuredLine<'a> {
+        let mut travelled = 0.0;
+        let mut lengths = Vec::with_capacity(line.len());
+        lengths.push(0.0);
+        for w in line.windows(2) {
+            travelled += w[0].distance(w[1]);
+            lengths.push(travelled);
+        }
+        MeasuredLine { line, lengths }
+    }
+
+    /// Total length of the line.
+    pub fn length(&self) -> Universal {
+        self.lengths.last().copied().unwrap_or(0.0)
+    }
+
+    /// Index of the segment (starting at that point) that holds `distance`, and how far along
+    /// it it is, from 0 to 1. Zero length segments never hold a distance.
+    fn locate(&self, distance: Universal) -> (usize, Universal) {
+        let lengths = &self.lengths;
+        let last_segment = lengths.len().saturating_sub(2);
+        let segment = lengths
+            .partition_point(|length| *length <= distance)
+            .saturating_sub(1)
+            .min(last_segment);
+        let span = lengths[segment + 1] - lengths[segment];
+        let t = if span > 0.0 {
+            ((distance - lengths[segment]) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (segment, t)
+    }
+
+    pub fn point_at(&self, distance: Universal) -> Option<Point<Universal>> {
+        if !distance.is_finite() {
+            return None;
+        }
+        if self.line.len() < 2 {
+            return self.line.first().copied();
+        }
+        let (segment, t) = self.locate(distance);
+        Some(self.line[segment].lerp(self.line[segment + 1], t))
+    }
+
+    pub fn tangent_at(&self, distance: Universal) -> Option<Point<Universal>> {
+        if !distance.is_finite() || self.line.len() < 2 {
+            return None;
+        }
+        let (segment, _) = self.locate(distance);
+        // Past the end the last segment with a length still gives the direction
+        self.line
+            .windows(2)
+            .skip(segment)
+            .chain(self.line.windows(2).take(segment).rev())
+            .find_map(|w| (w[1] - w[0]).normalize())
+    }
+
+    pub fn normal_at(&self, distance: Universal) -> Option<Point<Universal>> {
+        let tangent = self.tangent_at(distance)?;
+        Some(Point::new_unchecked(tangent.y(), -tangent.x()))
+    }
+
+    pub fn sub_line(&self, from: Universal, to: Universal) -> Line<Universal> {
+        if from > to {
+            let mut backwards = self.sub_line(to, from);
+            backwards.reverse();
+            return backwards;
+        }
+        let (start, end) = match (self.point_at(from), self.point_at(to)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Vec::new(),
+        };
+        let mut line = vec![start];
+        line.extend(
+            self.line
+                .iter()
+                .zip(self.lengths.iter())
+                .filter(|(_, length)| **length > from && **length < to)
+                .map(|(point, _)| *point),
+        );
+        line.push(end);
+        line.dedup();
+        line
+    }
+
+    pub fn resample(&self, spacing: Universal) -> Line<Universal> {
+        let total = self.length();
+        if !spacing.is_finite() || spacing <= 0.0 || total == 0.0 {
+            return self.line.first().copied().into_iter().collect();
+        }
+        let count = (total / spacing).floor() as usize;
+        let mut line: Line<Universal> = (0..=count)
+            .map(|i| {
+                let (segment, t) = self.locate(i as Universal * spacing);
+                self.line[segment].lerp(self.line[segment + 1], t)
+            })
+            .collect();
+        if line.last() != self.line.last() {
+            line.push(self.line[self.line.len() - 1]);
+        }
+        line
+    }
+}
+
+impl ArcLength for Line<Universal> {
+    fn measured(&self) -> MeasuredLine<'_> {
+        MeasuredLine::new(self)
+    }
+
+    fn point_at(&self, distance: Universal) -> Option<Point<Universal>> {
+        self.measured().point_at(distance)
+    }
+
+    fn tangent_at(&self, distance: Universal) -> Option<Point<Universal>> {
+        self.measured().tangent_at(distance)
+    }
+
+    fn normal_at(&self, distance: Universal) -> Option<Point<Universal>> {
+        self.measured().normal_at(distance)
+    }
+
+    fn sub_line(&self, from: Universal, to: Universal) -> Line<Universal> {
+        self.measured().sub_line(from, to)
+    }
+
+    fn resample(&self, spacing: Universal) -> Line<Universal> {
+        self.measured().resample(spacing)
+    }
 }
 
 impl LineClip for Line<Universal> {
@@ -165,6 +1241,10 @@ impl LineClip for Line<Universal> {
         let mut clipped = self
             .windows(2)
             .fold(Vec::with_capacity(self.len()), |mut clip, s| {
+                // Segments with broken points are dropped instead of spreading NaNs
+                if !s[0].is_finite() || !s[1].is_finite() {
+                    return clip;
+                }
                 match (
                     inside_edge(window, s[0], edge),
                     inside_edge(window, s[1], edge),
@@ -186,8 +1266,61 @@ impl LineClip for Line<Universal> {
         //println!("Output: {:?}\n------------------------\n", clipped);
         clipped
     }
+
+    fn clip_stroke(&self, bounds: &BoundingBox) -> Vec<Line<Universal>> {
+        if let [point] = self[..] {
+            return if bounds.contains(point) {
+                vec![vec![point]]
+            } else {
+                Vec::new()
+            };
+        }
+
+        let mut pieces: Vec<Line<Universal>> = Vec::new();
+        for s in self.windows(2) {
+            let (a, b) = (s[0], s[1]);
+            if !a.is_finite() || !b.is_finite() {
+                continue;
+            }
+            let d = b - a;
+            // Each edge limits the part of the segment inside it to t >= q/p (if p < 0) or
+            // t <= q/p (if p > 0), where p is how fast the segment goes out through it
+            let limits = [
+                (-d.x(), a.x() - bounds.min().x()),
+                (d.x(), bounds.max().x() - a.x()),
+                (-d.y(), a.y() - bounds.min().y()),
+                (d.y(), bounds.max().y() - a.y()),
+            ];
+            let (mut t0, mut t1) = (0.0, 1.0);
+            for (p, q) in limits {
+                if p == 0.0 {
+                    if q < 0.0 {
+                        // Parallel to the edge and outside of it
+                        t1 = -1.0;
+                    }
+                } else if p < 0.0 {
+                    t0 = (q / p).max(t0);
+                } else {
+                    t1 = (q / p).min(t1);
+                }
+            }
+            if t0 > t1 {
+                continue;
+            }
+
+            let start = if t0 == 0.0 { a } else { a.lerp(b, t0) };
+            let end = if t1 == 1.0 { b } else { a.lerp(b, t1) };
+            match pieces.last_mut() {
+                // The segment goes on from where the previous one left off
+                Some(piece) if t0 == 0.0 && piece.last() == Some(&a) => piece.push(end),
+                _ => pieces.push(vec![start, end]),
+            }
+        }
+        pieces
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Polygon<T> {
     /// The borders being a Vec<Line> doesn't mean that every straight line encapsulating for
     /// example a square is a different border. That would be a polygon considered having just one border. The multiple borders are for polygons that have "holes" in them, like hollowed out circles.
@@ -206,8 +1339,29 @@ pub struct Polygon<T> {
 
     /// Id given in the svg.
     id: String,
+
+    /// Luminance mask that multiplies the alpha of the fill, pixel by pixel. Masks are shared
+    /// between polygons, which serde can't keep, so whoever serializes polygons writes them
+    /// apart (see `cache.rs`).
+    #[serde(skip)]
+    mask: Option<Rc<Mask>>,
+
+    /// Which points count as inside when the borders overlap or nest.
+    fill_rule: FillRule,
+
+    /// Hidden polygons (`display:none` or `visibility:hidden` in the svg) are kept but only drawn
+    /// when the viewer asks for them.
+    hidden: bool,
+
+    /// The curves the borders were flattened from, one path per border. Polygons made out of
+    /// other ones' borders (clipping, boolean operations...) don't have them.
+    paths: Option<Rc<Vec<Path>>>,
 }
 
+/// The shapes inside an svg `<mask>`. The luminance of their fill colors is used as the alpha of
+/// the masked polygon's fill, and anywhere they don't cover is fully transparent.
+pub type Mask = Vec<Polygon<Universal>>;
+
 impl<T> Polygon<T> {
     pub fn new(layer: i32, id: String) -> Polygon<T> {
         Polygon {
@@ -216,6 +1370,10 @@ impl<T> Polygon<T> {
             fill_color: None,
             layer,
             id,
+            mask: None,
+            fill_rule: FillRule::default(),
+            hidden: false,
+            paths: None,
         }
     }
 
@@ -226,13 +1384,13 @@ impl<T> Polygon<T> {
             border_color: self.border_color,
             fill_color: self.fill_color,
             layer: self.layer,
+            mask: self.mask.clone(),
+            fill_rule: self.fill_rule,
+            hidden: self.hidden,
+            paths: None,
         }
     }
 
-    pub fn add_border(&mut self, border: Line<T>) {
-        self.borders.push(border);
-    }
-
     pub fn set_borders(&mut self, borders: Vec<Line<T>>) {
         self.borders = borders;
     }
@@ -241,10 +1399,6 @@ impl<T> Polygon<T> {
         &self.borders
     }
 
-    pub fn get_borders_mut(&mut self) -> &mut Vec<Line<T>> {
-        &mut self.borders
-    }
-
     pub fn set_stroke_color(&mut self, color: Option<Color>) {
         self.border_color = color;
     }
@@ -268,15 +1422,782 @@ impl<T> Polygon<T> {
     pub fn get_layer(&self) -> i32 {
         self.layer
     }
+
+    pub fn set_mask(&mut self, mask: Option<Rc<Mask>>) {
+        self.mask = mask;
+    }
+
+    pub fn get_mask(&self) -> Option<&Rc<Mask>> {
+        self.mask.as_ref()
+    }
+
+    pub fn set_fill_rule(&mut self, fill_rule: FillRule) {
+        self.fill_rule = fill_rule;
+    }
+
+    pub fn get_fill_rule(&self) -> FillRule {
+        self.fill_rule
+    }
+
+    pub fn set_paths(&mut self, paths: Option<Rc<Vec<Path>>>) {
+        self.paths = paths;
+    }
+
+    pub fn get_paths(&self) -> Option<&Rc<Vec<Path>>> {
+        self.paths.as_ref()
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
 }
 
 impl Polygon<Universal> {
-    pub fn scale(mut self, scale: Universal) -> Result<Self> {
+    /// Whether `point` is covered by the polygon, according to its fill rule. Holes are left out
+    /// by either rule as long as they go around in the opposite direction than the outer border
+    /// (which is how svg editors write them), and by even-odd regardless of direction.
+    pub fn contains(&self, point: Point<Universal>) -> bool {
+        match self.bounding_box() {
+            Some(bounds) if bounds.contains(point) => self
+                .fill_rule
+                .is_inside(self.borders.iter().map(|b| b.winding_number(point)).sum()),
+            _ => false,
+        }
+    }
+
+    /// Area covered by the polygon, subtracting its holes. What's covered follows the fill rule,
+    /// so with "nonzero" a border going the same way as the one around it adds nothing.
+    pub fn area(&self) -> Universal {
+        self.filled_parts().iter().map(|(area, _)| area).sum()
+    }
+
+    /// Sum of the signed areas of the borders, which is the area when holes go around in the
+    /// opposite direction than the outer borders (the svg "nonzero" convention).
+    pub fn signed_area(&self) -> Universal {
+        self.borders.iter().map(|b| b.signed_area()).sum()
+    }
+
+    /// Orientation of the outer border, taken as the one enclosing the most area.
+    pub fn orientation(&self) -> Orientation {
+        self.borders
+            .iter()
+            .max_by(|a, b| a.signed_area().abs().total_cmp(&b.signed_area().abs()))
+            .map_or(Orientation::Degenerate, |b| b.orientation())
+    }
+
+    /// Center of mass of the area covered by the polygon (following the fill rule like `area()`),
+    /// with holes subtracted.
+    pub fn centroid(&self) -> Option<Point<Universal>> {
+        let parts = self.filled_parts();
+        let area: Universal = parts.iter().map(|(area, _)| area).sum();
+        if area == 0.0 {
+            return None;
+        }
+        let (x, y) = parts
+            .iter()
+            .fold((0.0, 0.0), |(x, y), (a, c)| (x + a * c.x(), y + a * c.y()));
+        Some(Point::new_unchecked(x / area, y / area))
+    }
+
+    /// Total length of all the borders.
+    pub fn perimeter(&self) -> Universal {
+        self.borders.iter().map(|b| b.perimeter()).sum()
+    }
+
+    /// Whether any border crosses or touches itself or another border.
+    pub fn is_self_intersecting(&self) -> bool {
+        let border_segments: Vec<Vec<(Point<Universal>, Point<Universal>)>> =
+            self.borders.iter().map(|b| proper_segments(b)).collect();
+        let lengths: Vec<usize> = border_segments.iter().map(|s| s.len()).collect();
+        let segments: Vec<(usize, usize, Point<Universal>, Point<Universal>)> = border_segments
+            .into_iter()
+            .enumerate()
+            .flat_map(|(border, segments)| {
+                segments
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, (a, b))| (border, i, a, b))
+            })
+            .collect();
+        any_segments_intersect(&segments, &lengths)
+    }
+
+    /// Area (negative for holes) and centroid of each border of the region the polygon fills.
+    fn filled_parts(&self) -> Vec<(Universal, Point<Universal>)> {
+        if self.is_self_intersecting() {
+            // Borders that cross or overlap get traced into the region they fill, whose borders
+            // don't. That takes a whole overlay, so it's left for the polygons that need it.
+            signed_parts(&boolean::filled_region(self), self.fill_rule)
+        } else {
+            signed_parts(&self.borders, self.fill_rule)
+        }
+    }
+
+    /// Box around all the borders, `None` if the polygon has no points.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.borders
+            .iter()
+            .filter_map(|border| border.bounding_box())
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Box around the borders after being transformed by `transform`.
+    pub fn transformed_bounding_box(&self, transform: &Transform2D) -> Option<BoundingBox> {
+        BoundingBox::from_points(self.borders.iter().flatten().map(|p| transform.apply(*p)))
+    }
+
+    /// Transforms the borders, and the mask and paths along with them.
+    pub fn transform(&mut self, transform: &Transform2D) {
         for line in self.borders.iter_mut() {
-            for point in line.iter_mut() {
-                *point = Point::<Universal>::new(point.x() * scale, point.y() * scale)?
+            line.transform(transform);
+        }
+        if let Some(mask) = self.mask.as_mut() {
+            for polygon in Rc::make_mut(mask).iter_mut() {
+                polygon.transform(transform);
             }
         }
-        Ok(self)
+        if let Some(paths) = self.paths.as_mut() {
+            for path in Rc::make_mut(paths).iter_mut() {
+                path.transform(transform);
+            }
+        }
+    }
+}
+
+/// Shapes shared by the test modules of the crate.
+#[cfg(test)]
+pub mod fixtures {
+    use super::*;
+
+    pub fn point(x: Universal, y: Universal) -> Point<Universal> {
+        Point::new_unchecked(x, y)
+    }
+
+    pub fn line(points: &[(Universal, Universal)]) -> Line<Universal> {
+        points.iter().map(|(x, y)| point(*x, *y)).collect()
+    }
+
+    /// Clockwise rectangle from (x0, y0) to (x1, y1), not closed.
+    pub fn rectangle(
+        x0: Universal,
+        y0: Universal,
+        x1: Universal,
+        y1: Universal,
+    ) -> Line<Universal> {
+        line(&[(x0, y0), (x1, y0), (x1, y1), (x0, y1)])
+    }
+
+    /// Clockwise square with its top left corner at (x, y), not closed.
+    pub fn square(x: Universal, y: Universal, size: Universal) -> Line<Universal> {
+        rectangle(x, y, x + size, y + size)
+    }
+
+    pub fn polygon(borders: Vec<Line<Universal>>, fill_rule: FillRule) -> Polygon<Universal> {
+        let mut polygon = Polygon::new(0, "test".to_string());
+        polygon.set_borders(borders);
+        polygon.set_fill_rule(fill_rule);
+        polygon
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{line, point, polygon, square};
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_close(a: Point<Universal>, b: Point<Universal>) {
+        assert!(
+            (a.x() - b.x()).hypot(a.y() - b.y()) < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn point_operators() {
+        let (a, b) = (point(1.0, 2.0), point(4.0, -2.0));
+        assert_eq!(a + b, point(5.0, 0.0));
+        assert_eq!(a - b, point(-3.0, 4.0));
+        assert_eq!(-a, point(-1.0, -2.0));
+        assert_eq!(a * 2.0, point(2.0, 4.0));
+        assert_eq!(2.0 * a, a * 2.0);
+        assert_eq!(b / 2.0, point(2.0, -1.0));
+        assert_eq!(a.dot(b), 0.0);
+        assert_eq!(a.cross(b), -10.0);
+        assert_eq!((a - b).length(), 5.0);
+        assert_eq!(a.distance(b), 5.0);
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.5), point(2.5, 0.0));
+    }
+
+    #[test]
+    fn normalize() {
+        assert_close(point(3.0, -4.0).normalize().unwrap(), point(0.6, -0.8));
+        assert_eq!(point(0.0, 0.0).normalize(), None);
+    }
+
+    #[test]
+    fn framebuffer_conversions() {
+        let pixel = Point::<Framebuffer>::new(3, 7).unwrap();
+        assert_eq!(Point::<Universal>::from(pixel), point(3.0, 7.0));
+
+        let subpixel = |x: Subpixel, y: Subpixel| Point::<Subpixel> { x, y };
+        // Positions inside the pixel are kept, down to 1/256th of it
+        assert_eq!(point(3.5, 7.25).to_subpixel().unwrap(), subpixel(896, 1856));
+        assert_eq!(
+            point(0.1, 0.0).to_subpixel().unwrap(),
+            subpixel((0.1 * SUBPIXEL_ONE as Universal).round() as Subpixel, 0)
+        );
+        // The window's far edges are included, and points just outside get moved onto them
+        assert_eq!(
+            point(WINDOW_WIDTH as Universal, WINDOW_HEIGHT as Universal)
+                .to_subpixel()
+                .unwrap(),
+            subpixel(
+                WINDOW_WIDTH as Subpixel * SUBPIXEL_ONE,
+                WINDOW_HEIGHT as Subpixel * SUBPIXEL_ONE
+            )
+        );
+        assert_eq!(point(-1e-4, 5.0).to_subpixel().unwrap(), subpixel(0, 1280));
+        assert!(point(-1.0, 5.0).to_subpixel().is_err());
+        assert!(point(5.0, WINDOW_HEIGHT as Universal + 0.5)
+            .to_subpixel()
+            .is_err());
+        assert!(point(Universal::NAN, 5.0).to_subpixel().is_err());
+    }
+
+    #[test]
+    fn world_bounds_from_str() {
+        assert_eq!(
+            "unbounded".parse::<WorldBounds>().unwrap(),
+            WorldBounds::Unbounded
+        );
+        assert_eq!(
+            " -500, -250.5 ,500,250".parse::<WorldBounds>().unwrap(),
+            WorldBounds::Bounded(BoundingBox::new(point(-500.0, -250.5), point(500.0, 250.0)))
+        );
+        assert_eq!(
+            "0,0,1000,1000".parse::<WorldBounds>().unwrap(),
+            WorldBounds::square(1000.0)
+        );
+
+        for malformed in [
+            "",
+            "bounded",
+            "0,0,1000",
+            "0,0,1000,1000,5",
+            "0,0,a,1000",
+            "0,0,inf,1",
+        ] {
+            assert!(malformed.parse::<WorldBounds>().is_err(), "{}", malformed);
+        }
+        // The minimum has to be below the maximum on both axes
+        for inverted in ["10,0,0,10", "0,10,10,0", "0,0,0,10"] {
+            assert!(inverted.parse::<WorldBounds>().is_err(), "{}", inverted);
+        }
+    }
+
+    #[test]
+    fn points_within_the_world() {
+        let unbounded = WorldBounds::Unbounded;
+        assert!(Point::new_within(-1e6, 1e6, &unbounded).is_ok());
+        assert!(Point::new_within(Universal::NAN, 0.0, &unbounded).is_err());
+        assert!(Point::new_within(0.0, Universal::INFINITY, &unbounded).is_err());
+
+        let rectangle: WorldBounds = "-100,-50,100,50".parse().unwrap();
+        assert!(Point::new_within(-100.0, -50.0, &rectangle).is_ok());
+        assert!(Point::new_within(100.0, 50.0, &rectangle).is_ok());
+        assert!(Point::new_within(-30.0, 20.0, &rectangle).is_ok());
+        assert!(Point::new_within(-100.5, 0.0, &rectangle).is_err());
+        assert!(Point::new_within(0.0, 60.0, &rectangle).is_err());
+        assert_eq!(rectangle.clamp(point(300.0, -70.0)), point(100.0, -50.0));
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        let p = point(2.0, 1.0);
+        let translate_then_scale =
+            Transform2D::translate(1.0, 0.0).then(&Transform2D::scale(2.0, 3.0));
+        assert_close(translate_then_scale.apply(p), point(6.0, 3.0));
+        let scale_then_translate =
+            Transform2D::scale(2.0, 3.0).then(&Transform2D::translate(1.0, 0.0));
+        assert_close(scale_then_translate.apply(p), point(5.0, 3.0));
+    }
+
+    #[test]
+    fn then_matches_applying_one_by_one() {
+        let steps = [
+            Transform2D::skew(0.3, 0.1),
+            Transform2D::translate(3.0, -4.0),
+            Transform2D::reflect(0.7),
+            Transform2D::rotate(1.2),
+            Transform2D::scale(2.0, 0.5),
+        ];
+        let composed = steps
+            .iter()
+            .fold(Transform2D::identity(), |total, step| total.then(step));
+        let p = point(-7.0, 5.0);
+        let stepped = steps.iter().fold(p, |p, step| step.apply(p));
+        assert_close(composed.apply(p), stepped);
+    }
+
+    #[test]
+    fn invert_undoes_the_transform() {
+        let t = Transform2D::skew(0.3, 0.1)
+            .then(&Transform2D::translate(3.0, 4.0))
+            .then(&Transform2D::reflect(0.7))
+            .then(&Transform2D::scale(2.0, 0.5));
+        let inverse = t.invert().unwrap();
+        let p = point(2.0, 1.0);
+        assert_close(inverse.apply(t.apply(p)), p);
+        assert_close(t.then(&inverse).apply(p), p);
+        assert_close(inverse.then(&t).apply(p), p);
+    }
+
+    #[test]
+    fn invert_rejects_singular_transforms() {
+        assert!(Transform2D::scale(0.0, 1.0).invert().is_err());
+        assert!(Transform2D::new(1.0, 2.0, 2.0, 4.0, 5.0, 6.0)
+            .invert()
+            .is_err());
+    }
+
+    #[test]
+    fn about_keeps_the_pivot_in_place() {
+        let pivot = point(1.0, 1.0);
+        let rotation = Transform2D::rotate(FRAC_PI_2).about(pivot);
+        assert_close(rotation.apply(pivot), pivot);
+        // y grows downwards, so a positive angle turns (1, 0) into (0, 1) relative to the pivot
+        assert_close(rotation.apply(point(2.0, 1.0)), point(1.0, 2.0));
+
+        let scaling = Transform2D::scale(3.0, 2.0).about(pivot);
+        assert_close(scaling.apply(pivot), pivot);
+        assert_close(scaling.apply(point(2.0, 0.0)), point(4.0, -1.0));
+    }
+
+    #[test]
+    fn reflect_mirrors_across_the_line() {
+        assert_close(
+            Transform2D::reflect(0.0).apply(point(2.0, 1.0)),
+            point(2.0, -1.0),
+        );
+        assert_close(
+            Transform2D::reflect(FRAC_PI_2 / 2.0).apply(point(2.0, 1.0)),
+            point(1.0, 2.0),
+        );
+    }
+
+    #[test]
+    fn model_transform_matches_composed_matrices() {
+        let mut model = ModelTransform::identity();
+        let mut matrix = Transform2D::identity();
+        let pivots = [point(500.0, 500.0), point(100.0, 300.0), point(-50.0, 20.0)];
+        for i in 0..300 {
+            let theta = 0.1 * (i % 7) as Universal - 0.2;
+            model.rotate_about(theta, pivots[i % 3]);
+            matrix = matrix.then(&Transform2D::rotate(theta).about(pivots[i % 3]));
+        }
+        model.scale_about(1.5, pivots[0]);
+        matrix = matrix.then(&Transform2D::scale(1.5, 1.5).about(pivots[0]));
+
+        let p = point(321.0, 123.0);
+        let (a, b) = (model.apply(p), matrix.apply(p));
+        assert!(
+            (a.x() - b.x()).hypot(a.y() - b.y()) < 0.05,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn rotating_back_and_forth_keeps_the_geometry() {
+        let p = point(321.0, 123.0);
+        let step = 15.0_f32.to_radians();
+        let mut model = ModelTransform::identity();
+        for i in 0..500 {
+            let pivot = point((i % 10) as Universal * 100.0, 500.0);
+            model.rotate_about(step, pivot);
+            model.rotate_about(-step, pivot);
+        }
+        // Neither skew nor scaling crept into the placement
+        let matrix = model.to_matrix();
+        assert!((matrix.a * matrix.d - matrix.b * matrix.c - 1.0).abs() < 1e-5);
+        assert!((matrix.a * matrix.c + matrix.b * matrix.d).abs() < 1e-5);
+        assert_close(model.apply(p), p);
+
+        // Resetting goes back to exactly where the car was parsed
+        model = ModelTransform::identity();
+        assert!(model.is_identity());
+        assert_eq!(model.apply(p), p);
+    }
+
+    #[test]
+    fn bounding_boxes_follow_transforms() {
+        let line = vec![point(1.0, 2.0), point(4.0, -1.0), point(3.0, 5.0)];
+        let bounds = line.bounding_box().unwrap();
+        assert_eq!(
+            (bounds.min(), bounds.max()),
+            (point(1.0, -1.0), point(4.0, 5.0))
+        );
+        assert!(Vec::<Point<Universal>>::new().bounding_box().is_none());
+
+        let mut polygon =
+            Polygon::<Universal>::new(0, "p".to_string()).new_copy_attributes(vec![line]);
+        let rotation = Transform2D::rotate(FRAC_PI_2).about(point(1.0, 2.0));
+        let expected = polygon.transformed_bounding_box(&rotation).unwrap();
+        polygon.transform(&rotation);
+        assert_eq!(polygon.bounding_box(), Some(expected));
+        // The box of the transformed box encloses the transformed shape
+        let loose = bounds.transformed(&rotation);
+        assert!(loose.contains(expected.min()) && loose.contains(expected.max()));
+    }
+
+    #[test]
+    fn even_odd_holes_dont_count_towards_area_or_centroid() {
+        let donut = polygon(
+            vec![square(0.0, 0.0, 10.0), square(2.0, 2.0, 2.0)],
+            FillRule::EvenOdd,
+        );
+        assert!((donut.area() - 96.0).abs() < 1e-3);
+        // (100 * 5 - 4 * 3) / 96 on both axes
+        let expected = (500.0 - 12.0) / 96.0;
+        assert_close(donut.centroid().unwrap(), point(expected, expected));
+    }
+
+    #[test]
+    fn nonzero_fills_borders_going_the_same_way() {
+        // Both squares clockwise: the inner one is no hole under nonzero
+        let filled = polygon(
+            vec![square(0.0, 0.0, 10.0), square(2.0, 2.0, 2.0)],
+            FillRule::NonZero,
+        );
+        assert!((filled.area() - 100.0).abs() < 1e-3);
+        assert_close(filled.centroid().unwrap(), point(5.0, 5.0));
+
+        let mut hole = square(2.0, 2.0, 2.0);
+        hole.reverse();
+        let donut = polygon(vec![square(0.0, 0.0, 10.0), hole], FillRule::NonZero);
+        assert!((donut.area() - 96.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn overlapping_borders_follow_the_fill_rule() {
+        let squares = vec![square(0.0, 0.0, 10.0), square(5.0, 0.0, 10.0)];
+        let overlapping = polygon(squares.clone(), FillRule::NonZero);
+        assert!((overlapping.area() - 150.0).abs() < 1e-3);
+        assert_close(overlapping.centroid().unwrap(), point(7.5, 5.0));
+        // The overlap is inside two borders, so even-odd leaves it empty
+        let overlapping = polygon(squares, FillRule::EvenOdd);
+        assert!((overlapping.area() - 100.0).abs() < 1e-3);
+        assert_close(overlapping.centroid().unwrap(), point(7.5, 5.0));
+    }
+
+    #[test]
+    fn borders_that_dont_cross_skip_the_overlay() {
+        // A square with a hole and an island in it, the hole going the other way
+        let mut hole = square(2.0, 2.0, 6.0);
+        hole.reverse();
+        let borders = vec![square(0.0, 0.0, 10.0), hole, square(4.0, 4.0, 1.0)];
+        for rule in [FillRule::NonZero, FillRule::EvenOdd] {
+            let polygon = polygon(borders.clone(), rule);
+            assert!(!polygon.is_self_intersecting());
+            let total = |parts: Vec<(Universal, Point<Universal>)>| -> Universal {
+                parts.iter().map(|(area, _)| area).sum()
+            };
+            let direct = total(signed_parts(&borders, rule));
+            let traced = total(signed_parts(&boolean::filled_region(&polygon), rule));
+            assert!((direct - 65.0).abs() < 1e-3, "{:?}: {}", rule, direct);
+            assert!((traced - 65.0).abs() < 1e-3, "{:?}: {}", rule, traced);
+        }
+    }
+
+    #[test]
+    fn containment_follows_the_fill_rule() {
+        let inner = square(2.0, 2.0, 2.0);
+        let mut hole = inner.clone();
+        hole.reverse();
+        let outer = square(0.0, 0.0, 10.0);
+        let in_hole = point(3.0, 3.0);
+
+        assert!(!polygon(vec![outer.clone(), inner.clone()], FillRule::EvenOdd).contains(in_hole));
+        assert!(polygon(vec![outer.clone(), inner], FillRule::NonZero).contains(in_hole));
+        assert!(!polygon(vec![outer.clone(), hole], FillRule::NonZero).contains(in_hole));
+        let solid = polygon(vec![outer], FillRule::NonZero);
+        assert!(solid.contains(point(1.0, 1.0)));
+        assert!(!solid.contains(point(11.0, 1.0)));
+    }
+
+    #[test]
+    fn repeated_points_arent_self_intersections() {
+        let mut border = square(0.0, 0.0, 10.0);
+        border.insert(2, border[1]);
+        border.push(border[0]);
+        border.push(border[0]);
+        assert!(!border.is_self_intersecting());
+        assert!(!polygon(vec![border], FillRule::NonZero).is_self_intersecting());
+    }
+
+    #[test]
+    fn crossings_are_self_intersections() {
+        let bow_tie = vec![
+            point(0.0, 0.0),
+            point(10.0, 10.0),
+            point(10.0, 0.0),
+            point(0.0, 10.0),
+        ];
+        assert!(bow_tie.is_self_intersecting());
+
+        let separate = vec![square(0.0, 0.0, 10.0), square(2.0, 2.0, 2.0)];
+        assert!(!polygon(separate.clone(), FillRule::EvenOdd).is_self_intersecting());
+        let mut crossing = separate;
+        crossing.push(square(3.0, 3.0, 2.0));
+        assert!(polygon(crossing, FillRule::EvenOdd).is_self_intersecting());
+    }
+
+    #[test]
+    fn strokes_inside_are_kept_whole() {
+        let bounds = BoundingBox::new(point(0.0, 0.0), point(10.0, 10.0));
+        let stroke = line(&[(1.0, 1.0), (5.0, 2.0), (9.0, 9.0)]);
+        assert_eq!(stroke.clip_stroke(&bounds), vec![stroke.clone()]);
+        assert_eq!(
+            line(&[(3.0, 3.0)]).clip_stroke(&bounds),
+            vec![line(&[(3.0, 3.0)])]
+        );
+        assert!(line(&[(11.0, 1.0), (20.0, 5.0)])
+            .clip_stroke(&bounds)
+            .is_empty());
+    }
+
+    #[test]
+    fn strokes_leaving_and_coming_back_split() {
+        let bounds = BoundingBox::new(point(0.0, 0.0), point(10.0, 10.0));
+        let stroke = line(&[(2.0, 5.0), (15.0, 5.0), (15.0, 8.0), (2.0, 8.0)]);
+        // Nothing gets added along the box's edge between the two pieces
+        assert_eq!(
+            stroke.clip_stroke(&bounds),
+            vec![
+                line(&[(2.0, 5.0), (10.0, 5.0)]),
+                line(&[(10.0, 8.0), (2.0, 8.0)])
+            ]
+        );
+    }
+
+    #[test]
+    fn strokes_crossing_the_box() {
+        let bounds = BoundingBox::new(point(0.0, 0.0), point(10.0, 10.0));
+        let diagonal = line(&[(-5.0, -5.0), (15.0, 15.0)]);
+        assert_eq!(
+            diagonal.clip_stroke(&bounds),
+            vec![line(&[(0.0, 0.0), (10.0, 10.0)])]
+        );
+        // Along an edge counts as inside
+        let along = line(&[(-5.0, 10.0), (15.0, 10.0)]);
+        assert_eq!(
+            along.clip_stroke(&bounds),
+            vec![line(&[(0.0, 10.0), (10.0, 10.0)])]
+        );
+        // Past a corner without going in
+        assert!(line(&[(5.0, -6.0), (16.0, 5.0)])
+            .clip_stroke(&bounds)
+            .is_empty());
+    }
+
+    #[test]
+    fn strokes_with_broken_points() {
+        let bounds = BoundingBox::new(point(0.0, 0.0), point(10.0, 10.0));
+        let stroke = line(&[
+            (1.0, 1.0),
+            (2.0, 2.0),
+            (Universal::NAN, 3.0),
+            (4.0, 4.0),
+            (5.0, 5.0),
+        ]);
+        assert_eq!(
+            stroke.clip_stroke(&bounds),
+            vec![
+                line(&[(1.0, 1.0), (2.0, 2.0)]),
+                line(&[(4.0, 4.0), (5.0, 5.0)])
+            ]
+        );
+    }
+
+    /// An L going right 10 and then down 5, with a repeated corner.
+    fn l_line() -> Line<Universal> {
+        line(&[(0.0, 0.0), (10.0, 0.0), (10.0, 0.0), (10.0, 5.0)])
+    }
+
+    #[test]
+    fn points_along_a_line() {
+        let l = l_line();
+        assert_close(l.point_at(4.0).unwrap(), point(4.0, 0.0));
+        assert_close(l.point_at(10.0).unwrap(), point(10.0, 0.0));
+        assert_close(l.point_at(12.5).unwrap(), point(10.0, 2.5));
+        // Clamped to the ends
+        assert_close(l.point_at(-3.0).unwrap(), point(0.0, 0.0));
+        assert_close(l.point_at(100.0).unwrap(), point(10.0, 5.0));
+        assert_eq!(line(&[(1.0, 2.0)]).point_at(5.0), Some(point(1.0, 2.0)));
+        assert_eq!(Line::<Universal>::new().point_at(0.0), None);
+    }
+
+    #[test]
+    fn distances_that_arent_finite_are_rejected() {
+        let l = l_line();
+        for distance in [Universal::NAN, Universal::INFINITY, Universal::NEG_INFINITY] {
+            assert_eq!(l.point_at(distance), None);
+            assert_eq!(l.tangent_at(distance), None);
+            assert_eq!(l.normal_at(distance), None);
+            assert!(l.sub_line(0.0, distance).is_empty());
+        }
+        assert_eq!(l.resample(Universal::NAN), line(&[(0.0, 0.0)]));
+        assert_eq!(l.resample(Universal::INFINITY), line(&[(0.0, 0.0)]));
+    }
+
+    #[test]
+    fn tangents_and_normals() {
+        let l = l_line();
+        assert_close(l.tangent_at(5.0).unwrap(), point(1.0, 0.0));
+        // The repeated corner has no direction, so the next segment gives it
+        assert_close(l.tangent_at(10.0).unwrap(), point(0.0, 1.0));
+        assert_close(l.tangent_at(50.0).unwrap(), point(0.0, 1.0));
+        // Left on screen, with y growing downwards
+        assert_close(l.normal_at(5.0).unwrap(), point(0.0, -1.0));
+        assert_close(l.normal_at(12.0).unwrap(), point(1.0, 0.0));
+        assert_eq!(line(&[(1.0, 1.0), (1.0, 1.0)]).tangent_at(0.0), None);
+    }
+
+    #[test]
+    fn sub_lines() {
+        let l = l_line();
+        assert_eq!(
+            l.sub_line(5.0, 12.0),
+            line(&[(5.0, 0.0), (10.0, 0.0), (10.0, 2.0)])
+        );
+        assert_eq!(
+            l.sub_line(12.0, 5.0),
+            line(&[(10.0, 2.0), (10.0, 0.0), (5.0, 0.0)])
+        );
+        assert_eq!(l.sub_line(-5.0, 3.0), line(&[(0.0, 0.0), (3.0, 0.0)]));
+    }
+
+    #[test]
+    fn resampling() {
+        let l = l_line();
+        assert_eq!(
+            l.resample(4.0),
+            line(&[(0.0, 0.0), (4.0, 0.0), (8.0, 0.0), (10.0, 2.0), (10.0, 5.0)])
+        );
+        assert_eq!(l.resample(5.0).len(), 4);
+        assert_eq!(l.resample(0.0), line(&[(0.0, 0.0)]));
+    }
+
+    #[test]
+    fn measured_lines_answer_like_the_line() {
+        let l = l_line();
+        let measured = l.measured();
+        assert!((measured.length() - 15.0).abs() < 1e-6);
+        for distance in [-1.0, 0.0, 3.0, 10.0, 11.0, 15.0, 20.0] {
+            assert_eq!(measured.point_at(distance), l.point_at(distance));
+            assert_eq!(measured.tangent_at(distance), l.tangent_at(distance));
+            assert_eq!(measured.sub_line(distance, 7.0), l.sub_line(distance, 7.0));
+        }
+    }
+
+    fn assert_color(a: Color, b: Color) {
+        let close = (a.r() - b.r()).abs() < 1e-4
+            && (a.g() - b.g()).abs() < 1e-4
+            && (a.b() - b.b()).abs() < 1e-4;
+        assert!(close, "{:?} != {:?}", a, b);
+    }
+
+    /// Colors spread over the whole RGB cube, grays and pure colors included.
+    fn color_grid() -> Vec<Color> {
+        let steps = [0.0, 0.2, 0.5, 0.75, 1.0];
+        let mut colors = Vec::new();
+        for r in steps.iter() {
+            for g in steps.iter() {
+                for b in steps.iter() {
+                    colors.push(Color::new(*r, *g, *b).unwrap());
+                }
+            }
+        }
+        colors
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for color in color_grid() {
+            let (h, s, v) = color.to_hsv();
+            assert!((0.0..360.0).contains(&h), "{:?}: {}", color, h);
+            assert_color(Color::from_hsv(h, s, v).unwrap(), color);
+        }
+        assert_eq!(
+            Color::from_hex("#ff0000").unwrap().to_hsv(),
+            (0.0, 1.0, 1.0)
+        );
+        let (h, s, v) = Color::from_hex("#0000ff").unwrap().to_hsv();
+        assert_eq!((h, s, v), (240.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for color in color_grid() {
+            let (h, s, l) = color.to_hsl();
+            assert!((0.0..=1.0).contains(&s), "{:?}: {}", color, s);
+            assert_color(Color::from_hsl(h, s, l).unwrap(), color);
+        }
+        assert_eq!(
+            Color::from_hex("#ff0000").unwrap().to_hsl(),
+            (0.0, 1.0, 0.5)
+        );
+        assert_eq!(Color::BLACK.to_hsl(), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn hues_wrap_and_ranges_are_checked() {
+        assert_color(
+            Color::from_hsv(-120.0, 1.0, 1.0).unwrap(),
+            Color::from_hsv(240.0, 1.0, 1.0).unwrap(),
+        );
+        assert_color(
+            Color::from_hsl(480.0, 1.0, 0.5).unwrap(),
+            Color::from_hsl(120.0, 1.0, 0.5).unwrap(),
+        );
+        assert!(Color::from_hsv(0.0, 1.5, 1.0).is_err());
+        assert!(Color::from_hsl(0.0, 1.0, -0.1).is_err());
+        assert!(Color::from_hsv(Universal::NAN, 1.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        for hex in ["#000000", "#ffffff", "#c0392b", "#1f3a60", "#7db0cc"] {
+            assert_eq!(Color::from_hex(hex).unwrap().to_hex(), hex);
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        let (red, blue) = (
+            Color::from_hex("#ff0000").unwrap(),
+            Color::from_hex("#0000ff").unwrap(),
+        );
+        assert_color(red.lerp(blue, 0.0), red);
+        assert_color(red.lerp(blue, 1.0), blue);
+        assert_color(red.lerp(blue, 0.5), Color::new(0.5, 0.0, 0.5).unwrap());
+        assert_color(red.lerp(blue, 7.0), blue);
+
+        assert_color(red.lerp_hsv(blue, 0.0), red);
+        assert_color(red.lerp_hsv(blue, 1.0), blue);
+        // The short way from red (0) to blue (240) goes through magenta (300), fully saturated
+        let middle = red.lerp_hsv(blue, 0.5);
+        assert_color(middle, Color::from_hex("#ff00ff").unwrap());
+        assert_eq!(middle.to_hsv().1, 1.0);
     }
 }
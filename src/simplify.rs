@@ -0,0 +1,290 @@
+//! Polyline simplification, used to drop the vertices that would collapse onto the same pixels
+//! when the car is seen from far away.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::shapes::{Line, Point, Universal};
+
+/// Algorithm used to simplify lines.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Simplification {
+    DouglasPeucker,
+    Visvalingam,
+}
+
+impl Simplification {
+    /// Cycles through the algorithms, `None` meaning no simplification.
+    pub fn next(current: Option<Simplification>) -> Option<Simplification> {
+        match current {
+            None => Some(Simplification::DouglasPeucker),
+            Some(Simplification::DouglasPeucker) => Some(Simplification::Visvalingam),
+            Some(Simplification::Visvalingam) => None,
+        }
+    }
+}
+
+pub trait LineSimplify {
+    /// Douglas–Peucker: keeps only the points needed for no removed point to be further than
+    /// `tolerance` from the simplified line.
+    fn simplify_douglas_peucker(&self, tolerance: Universal) -> Line<Universal>;
+    /// Visvalingam–Whyatt: repeatedly removes the point that forms the smallest triangle with its
+    /// neighbours, until none is smaller than `min_area`.
+    fn simplify_visvalingam(&self, min_area: Universal) -> Line<Universal>;
+    /// Simplifies with `algorithm`, so that the result is off by at most about `tolerance`.
+    fn simplify(&self, algorithm: Simplification, tolerance: Universal) -> Line<Universal>;
+}
+
+pub(crate) fn distance_to_segment(
+    p: Point<Universal>,
+    a: Point<Universal>,
+    b: Point<Universal>,
+) -> Universal {
+    let ab = b - a;
+    let length_sq = ab.dot(ab);
+    let t = if length_sq == 0.0 {
+        0.0
+    } else {
+        ((p - a).dot(ab) / length_sq).clamp(0.0, 1.0)
+    };
+    p.distance(a.lerp(b, t))
+}
+
+fn triangle_area(a: Point<Universal>, b: Point<Universal>, c: Point<Universal>) -> Universal {
+    (b - a).cross(c - a).abs() / 2.0
+}
+
+/// Marks in `keep` the points between `first` and `last` that Douglas–Peucker keeps.
+fn douglas_peucker(
+    line: &[Point<Universal>],
+    first: usize,
+    last: usize,
+    tolerance: Universal,
+    keep: &mut [bool],
+) {
+    // Iterative so that long lines don't overflow the stack
+    let mut ranges = vec![(first, last)];
+    while let Some((first, last)) = ranges.pop() {
+        let farthest = (first + 1..last)
+            .map(|i| (i, distance_to_segment(line[i], line[first], line[last])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = farthest {
+            if distance > tolerance {
+                keep[i] = true;
+                ranges.push((first, i));
+                ranges.push((i, last));
+            }
+        }
+    }
+}
+
+/// Point of the line waiting to be removed, ordered so that the smallest area pops first.
+#[derive(PartialEq)]
+struct Candidate {
+    area: Universal,
+    index: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .area
+            .total_cmp(&self.area)
+            .then(other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl LineSimplify for Line<Universal> {
+    fn simplify_douglas_peucker(&self, tolerance: Universal) -> Line<Universal> {
+        if self.len() < 3 {
+            return self.clone();
+        }
+        let last = self.len() - 1;
+        let mut keep = vec![false; self.len()];
+        keep[0] = true;
+        keep[last] = true;
+
+        if self[0] == self[last] {
+            // A closed line has no segment to measure against, so it's split at the point
+            // furthest from its start and each half is simplified on its own
+            let split = (1..last)
+                .max_by(|i, j| {
+                    let d = |k: usize| distance_to_segment(self[k], self[0], self[0]);
+                    d(*i).total_cmp(&d(*j))
+                })
+                .expect("lines with 3 or more points have a middle");
+            keep[split] = true;
+            douglas_peucker(self, 0, split, tolerance, &mut keep);
+            douglas_peucker(self, split, last, tolerance, &mut keep);
+            // Like with Visvalingam, closed lines keep at least a triangle
+            if keep.iter().filter(|keep| **keep).count() < 4 {
+                let third = (1..last).filter(|i| *i != split).max_by(|i, j| {
+                    let d = |k: usize| distance_to_segment(self[k], self[0], self[split]);
+                    d(*i).total_cmp(&d(*j))
+                });
+                if let Some(third) = third {
+                    keep[third] = true;
+                }
+            }
+        } else {
+            douglas_peucker(self, 0, last, tolerance, &mut keep);
+        }
+
+        self.iter()
+            .zip(keep)
+            .filter(|(_, keep)| *keep)
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    fn simplify_visvalingam(&self, min_area: Universal) -> Line<Universal> {
+        if self.len() < 3 {
+            return self.clone();
+        }
+        let n = self.len();
+        let mut previous: Vec<usize> = (0..n).map(|i| i.saturating_sub(1)).collect();
+        let mut next: Vec<usize> = (0..n).map(|i| (i + 1).min(n - 1)).collect();
+        let mut areas: Vec<Universal> = vec![Universal::INFINITY; n];
+        let mut heap = BinaryHeap::with_capacity(n);
+        for i in 1..n - 1 {
+            areas[i] = triangle_area(self[i - 1], self[i], self[i + 1]);
+            heap.push(Candidate {
+                area: areas[i],
+                index: i,
+            });
+        }
+
+        let mut removed = vec![false; n];
+        // Closed lines keep at least a triangle
+        let min_points = if self[0] == self[n - 1] { 4 } else { 2 };
+        let mut remaining = n;
+        while let Some(Candidate { area, index }) = heap.pop() {
+            if area >= min_area || remaining <= min_points {
+                break;
+            }
+            // Stale entries of points whose area changed get skipped
+            if removed[index] || area != areas[index] {
+                continue;
+            }
+            removed[index] = true;
+            remaining -= 1;
+            let (p, q) = (previous[index], next[index]);
+            next[p] = q;
+            previous[q] = p;
+
+            for neighbour in [p, q] {
+                if neighbour != 0 && neighbour != n - 1 {
+                    // The area never decreases, otherwise a point could be removed before the
+                    // one whose removal made it smaller
+                    areas[neighbour] = triangle_area(
+                        self[previous[neighbour]],
+                        self[neighbour],
+                        self[next[neighbour]],
+                    )
+                    .max(area);
+                    heap.push(Candidate {
+                        area: areas[neighbour],
+                        index: neighbour,
+                    });
+                }
+            }
+        }
+
+        self.iter()
+            .zip(removed)
+            .filter(|(_, removed)| !removed)
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    fn simplify(&self, algorithm: Simplification, tolerance: Universal) -> Line<Universal> {
+        match algorithm {
+            Simplification::DouglasPeucker => self.simplify_douglas_peucker(tolerance),
+            // A triangle whose height is the tolerance on a base of about twice the tolerance
+            Simplification::Visvalingam => self.simplify_visvalingam(tolerance * tolerance),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::line;
+
+    /// Largest distance from a point of `original` to the simplified line.
+    fn deviation(original: &Line<Universal>, simplified: &Line<Universal>) -> Universal {
+        original
+            .iter()
+            .map(|p| {
+                simplified
+                    .windows(2)
+                    .map(|w| distance_to_segment(*p, w[0], w[1]))
+                    .fold(Universal::INFINITY, Universal::min)
+            })
+            .fold(0.0, Universal::max)
+    }
+
+    /// A wavy line: a small zigzag on top of a large one.
+    fn wavy() -> Line<Universal> {
+        (0..=100)
+            .map(|i| {
+                let x = i as Universal;
+                let y = 20.0 * (x / 10.0).sin() + if i % 2 == 0 { 0.2 } else { -0.2 };
+                Point::new_unchecked(x, y)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn douglas_peucker_drops_collinear_points() {
+        let straight = line(&[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0), (4.0, 2.0)]);
+        assert_eq!(
+            straight.simplify_douglas_peucker(0.01),
+            line(&[(0.0, 0.0), (3.0, 3.0), (4.0, 2.0)])
+        );
+    }
+
+    #[test]
+    fn simplified_lines_stay_within_tolerance() {
+        let original = wavy();
+        for algorithm in [Simplification::DouglasPeucker, Simplification::Visvalingam] {
+            let simplified = original.simplify(algorithm, 1.0);
+            assert!(simplified.len() < original.len() / 2, "{:?}", algorithm);
+            assert_eq!(simplified.first(), original.first());
+            assert_eq!(simplified.last(), original.last());
+            // Visvalingam only bounds the areas, so it's given some leeway
+            assert!(deviation(&original, &simplified) <= 2.0, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn closed_lines_keep_a_triangle() {
+        let square = line(&[
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ]);
+        for algorithm in [Simplification::DouglasPeucker, Simplification::Visvalingam] {
+            let simplified = square.simplify(algorithm, 100.0);
+            assert!(simplified.len() >= 4, "{:?}: {:?}", algorithm, simplified);
+            assert_eq!(simplified.first(), simplified.last());
+        }
+    }
+
+    #[test]
+    fn short_lines_are_left_alone() {
+        let segment = line(&[(0.0, 0.0), (5.0, 1.0)]);
+        assert_eq!(segment.simplify_douglas_peucker(10.0), segment);
+        assert_eq!(segment.simplify_visvalingam(10.0), segment);
+    }
+}
@@ -0,0 +1,224 @@
+//! Bounding volume hierarchy over the polygons of a [`Car`], to find the ones near a point or
+//! inside a rectangle without looking at all of them.
+
+use crate::car::Car;
+use crate::constants::CURVE_TOLERANCE;
+use crate::shapes::{BoundingBox, Point, Universal};
+
+/// Polygons a leaf holds before it gets split.
+const LEAF_SIZE: usize = 4;
+
+enum Node {
+    Leaf {
+        bounds: BoundingBox,
+        polygons: Vec<(usize, BoundingBox)>,
+    },
+    Branch {
+        bounds: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &BoundingBox {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Branch { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Index over the bounding boxes of a car's polygons. Queries return positions in the car, so it
+/// stays valid for any car with the same polygons in the same places (like a simplified one).
+pub struct SpatialIndex {
+    nodes: Vec<Node>,
+}
+
+impl SpatialIndex {
+    pub fn new(car: &Car) -> SpatialIndex {
+        // The car is flattened at `CURVE_TOLERANCE`, so its curves may stray that far outside the
+        // boxes of its borders, and so may any other flattening of them
+        let mut items: Vec<(usize, BoundingBox)> = car
+            .iter()
+            .enumerate()
+            .filter_map(|(i, polygon)| Some((i, polygon.bounding_box()?.expand(CURVE_TOLERANCE))))
+            .collect();
+        let mut index = SpatialIndex { nodes: Vec::new() };
+        if !items.is_empty() {
+            index.build(&mut items);
+        }
+        index
+    }
+
+    /// Adds the node for `items` (and its children) and returns its position. Each branch splits
+    /// its boxes in half by their centers, along the axis where they're most spread out.
+    fn build(&mut self, items: &mut [(usize, BoundingBox)]) -> usize {
+        let bounds = items
+            .iter()
+            .skip(1)
+            .fold(items[0].1, |bounds, (_, b)| bounds.union(b));
+
+        if items.len() <= LEAF_SIZE {
+            self.nodes.push(Node::Leaf {
+                bounds,
+                polygons: items.to_vec(),
+            });
+            return self.nodes.len() - 1;
+        }
+
+        let centers = BoundingBox::from_points(items.iter().map(|(_, b)| b.center()))
+            .expect("there's more than one item");
+        let key: fn(&BoundingBox) -> Universal = if centers.width() >= centers.height() {
+            |b| b.center().x()
+        } else {
+            |b| b.center().y()
+        };
+        let middle = items.len() / 2;
+        items.select_nth_unstable_by(middle, |(_, a), (_, b)| key(a).total_cmp(&key(b)));
+
+        // The branch goes in first so that its children can be pushed after it
+        let node = self.nodes.len();
+        self.nodes.push(Node::Leaf {
+            bounds,
+            polygons: Vec::new(),
+        });
+        let (first, second) = items.split_at_mut(middle);
+        let left = self.build(first);
+        let right = self.build(second);
+        self.nodes[node] = Node::Branch {
+            bounds,
+            left,
+            right,
+        };
+        node
+    }
+
+    /// Positions, in increasing order, of the polygons whose bounding box passes `test`, looking
+    /// only into the nodes whose bounds pass it too (which they do if any box inside them does).
+    fn query<F: Fn(&BoundingBox) -> bool>(&self, test: F) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(node) = pending.pop() {
+            let node = &self.nodes[node];
+            if !test(node.bounds()) {
+                continue;
+            }
+            match node {
+                Node::Leaf { polygons, .. } => found.extend(
+                    polygons
+                        .iter()
+                        .filter(|(_, bounds)| test(bounds))
+                        .map(|(i, _)| *i),
+                ),
+                Node::Branch { left, right, .. } => pending.extend([*left, *right]),
+            }
+        }
+        // Keeping the car's order keeps the paint order
+        found.sort_unstable();
+        found
+    }
+
+    /// Polygons whose bounding box touches `rect`.
+    pub fn query_rect(&self, rect: &BoundingBox) -> Vec<usize> {
+        self.query(|bounds| bounds.intersects(rect))
+    }
+
+    /// Polygons whose bounding box contains `point`, the only ones that might contain it.
+    pub fn query_point(&self, point: Point<Universal>) -> Vec<usize> {
+        self.query(|bounds| bounds.contains(point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car;
+    use crate::shapes::fixtures::{point, rectangle};
+    use crate::shapes::Polygon;
+
+    /// Rectangles of all sizes scattered over a 1000 wide scene, on a few layers and including
+    /// some without any points, from a fixed seed.
+    fn scattered_car() -> Car {
+        let mut seed: u32 = 1;
+        let mut random = move |max: Universal| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 8) as Universal / (1 << 24) as Universal * max
+        };
+        (0..200)
+            .map(|i| {
+                let mut polygon = Polygon::new(i % 3, format!("polygon-{}", i));
+                if i % 17 != 0 {
+                    let (x, y) = (random(1000.0), random(1000.0));
+                    let (width, height) = (random(150.0), random(150.0));
+                    polygon.set_borders(vec![rectangle(x, y, x + width, y + height)]);
+                }
+                polygon
+            })
+            .collect()
+    }
+
+    fn brute_force<F: Fn(&BoundingBox) -> bool>(car: &Car, test: F) -> Vec<usize> {
+        car.iter()
+            .enumerate()
+            .filter(|(_, polygon)| {
+                polygon
+                    .bounding_box()
+                    .is_some_and(|b| test(&b.expand(CURVE_TOLERANCE)))
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    #[test]
+    fn rect_queries_match_brute_force() {
+        let car = scattered_car();
+        let index = SpatialIndex::new(&car);
+        for (x0, y0, x1, y1) in [
+            (0.0, 0.0, 1000.0, 1000.0),
+            (100.0, 200.0, 300.0, 250.0),
+            (480.0, 480.0, 520.0, 520.0),
+            (900.0, 0.0, 2000.0, 100.0),
+            (-50.0, -50.0, -10.0, -10.0),
+        ] {
+            let rect = BoundingBox::new(point(x0, y0), point(x1, y1));
+            assert_eq!(
+                index.query_rect(&rect),
+                brute_force(&car, |b| b.intersects(&rect)),
+                "{:?}",
+                rect
+            );
+        }
+    }
+
+    #[test]
+    fn point_queries_match_brute_force_and_keep_the_paint_order() {
+        let car = scattered_car();
+        let index = SpatialIndex::new(&car);
+        for x in (0..1000).step_by(37) {
+            for y in (0..1000).step_by(41) {
+                let point = point(x as Universal, y as Universal);
+                let candidates = index.query_point(point);
+                assert_eq!(candidates, brute_force(&car, |b| b.contains(point)));
+
+                let hits = car::polygons_at(candidates.iter().map(|i| &car[*i]), point);
+                let expected = car::polygons_at(&car, point);
+                assert_eq!(
+                    hits.iter().map(|p| p.id()).collect::<Vec<_>>(),
+                    expected.iter().map(|p| p.id()).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_car() {
+        let index = SpatialIndex::new(&Car::new());
+        assert!(index.query_point(point(0.0, 0.0)).is_empty());
+        let everything = BoundingBox::new(point(-1e6, -1e6), point(1e6, 1e6));
+        assert!(index.query_rect(&everything).is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+//! Recoloring of the car's parts, and themes (paint jobs) made out of recoloring rules that get
+//! loaded from a json file like this one:
+//!
+//! ```json
+//! [
+//!     {
+//!         "name": "Racing red",
+//!         "rules": [
+//!             { "id": "body_*", "fill": "#c0392b" },
+//!             { "color": "#000000", "stroke": "#2c0b0e" }
+//!         ]
+//!     }
+//! ]
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+
+use crate::shapes::Color;
+
+/// Changes the colors of the polygons it matches. Only paints the polygon already has get
+/// replaced, so a part without a fill doesn't get one.
+#[derive(Clone, Debug)]
+pub struct Recolor {
+    /// Polygons whose id matches this pattern, where `*` stands for any run of characters
+    id: Option<String>,
+    /// Only the paints (fill or stroke) that originally are this color
+    color: Option<Color>,
+    fill: Option<Color>,
+    stroke: Option<Color>,
+}
+
+/// Whether `id` matches `pattern`, where `*` matches any run of characters (even none).
+fn matches_pattern(pattern: &str, id: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == id,
+        Some((prefix, rest)) => match id.strip_prefix(prefix) {
+            None => false,
+            // Every place the rest of the pattern could start from gets tried
+            Some(id) => id
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(id.len()))
+                .any(|i| matches_pattern(rest, &id[i..])),
+        },
+    }
+}
+
+impl Recolor {
+    pub fn new(
+        id: Option<String>,
+        color: Option<Color>,
+        fill: Option<Color>,
+        stroke: Option<Color>,
+    ) -> Recolor {
+        Recolor {
+            id,
+            color,
+            fill,
+            stroke,
+        }
+    }
+
+    /// The fill and stroke that a polygon with `id` and the `original` fill and stroke ends up
+    /// with, starting from the `current` ones.
+    fn apply(
+        &self,
+        id: &str,
+        original: (Option<Color>, Option<Color>),
+        current: (Option<Color>, Option<Color>),
+    ) -> (Option<Color>, Option<Color>) {
+        if !self.id.as_deref().is_none_or(|p| matches_pattern(p, id)) {
+            return current;
+        }
+        let replace = |original: Option<Color>, current: Option<Color>, new: Option<Color>| {
+            let matches = original.is_some_and(|o| self.color.is_none_or(|c| c == o));
+            if matches && new.is_some() {
+                new
+            } else {
+                current
+            }
+        };
+        (
+            replace(original.0, current.0, self.fill),
+            replace(original.1, current.1, self.stroke),
+        )
+    }
+}
+
+/// A named set of recoloring rules, applied in order so later ones win.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    name: String,
+    rules: Vec<Recolor>,
+}
+
+impl Theme {
+    pub fn new(name: String, rules: Vec<Recolor>) -> Theme {
+        Theme { name, rules }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The fill and stroke of a polygon with `id` under this theme, rules being matched
+    /// against its `original` colors.
+    pub fn colors_for(
+        &self,
+        id: &str,
+        original: (Option<Color>, Option<Color>),
+    ) -> (Option<Color>, Option<Color>) {
+        self.rules
+            .iter()
+            .fold(original, |current, rule| rule.apply(id, original, current))
+    }
+}
+
+/// A rule as written in the themes file, with colors in hex.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    id: Option<String>,
+    color: Option<String>,
+    fill: Option<String>,
+    stroke: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    name: String,
+    rules: Vec<RuleFile>,
+}
+
+fn parse_hex(color: Option<String>) -> Result<Option<Color>> {
+    color.map(|hex| Color::from_hex(&hex)).transpose()
+}
+
+/// Reads the themes in the json file at `path`.
+pub fn load_themes(path: &str) -> Result<Vec<Theme>> {
+    let content = fs::read(path).with_context(|| format!("No se pudo leer '{}'", path))?;
+    let themes: Vec<ThemeFile> = serde_json::from_slice(&content)
+        .with_context(|| format!("Temas en '{}' mal formateados", path))?;
+
+    themes
+        .into_iter()
+        .map(|ThemeFile { name, rules }| {
+            let rules = rules
+                .into_iter()
+                .map(|rule| {
+                    if rule.fill.is_none() && rule.stroke.is_none() {
+                        return Err(anyhow!(
+                            "Regla del tema '{}' no trae 'fill' ni 'stroke'",
+                            name
+                        ));
+                    }
+                    Ok(Recolor::new(
+                        rule.id,
+                        parse_hex(rule.color)?,
+                        parse_hex(rule.fill)?,
+                        parse_hex(rule.stroke)?,
+                    ))
+                })
+                .collect::<Result<Vec<Recolor>>>()
+                .with_context(|| format!("Tema '{}' no se pudo leer", name))?;
+            Ok(Theme::new(name, rules))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(hex: &str) -> Option<Color> {
+        Some(Color::from_hex(hex).unwrap())
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(matches_pattern("chassis", "chassis"));
+        assert!(!matches_pattern("chassis", "chassis_2"));
+        assert!(matches_pattern("red_wheel_*", "red_wheel_front"));
+        assert!(matches_pattern("red_wheel_*", "red_wheel_"));
+        assert!(!matches_pattern("red_wheel_*", "blue_wheel_front"));
+        assert!(matches_pattern("*_top", "spoiler_top"));
+        assert!(matches_pattern("*wheel*rim", "red_wheel_front_rim"));
+        assert!(!matches_pattern("*wheel*rim", "red_wheel_front"));
+        assert!(matches_pattern("*", ""));
+    }
+
+    #[test]
+    fn rules_match_ids_and_original_colors() {
+        let theme = Theme::new(
+            "test".to_string(),
+            vec![
+                Recolor::new(Some("body_*".to_string()), None, hex("#ff0000"), None),
+                Recolor::new(None, hex("#000000"), None, hex("#555555")),
+            ],
+        );
+        let (white, black) = (hex("#ffffff"), hex("#000000"));
+        assert_eq!(
+            theme.colors_for("body_left", (white, black)),
+            (hex("#ff0000"), hex("#555555"))
+        );
+        // Only black paints get the second rule
+        assert_eq!(theme.colors_for("wheel", (white, white)), (white, white));
+        assert_eq!(theme.colors_for("wheel", (black, None)), (black, None));
+        // Parts without a fill don't get one
+        assert_eq!(theme.colors_for("body_left", (None, white)), (None, white));
+    }
+
+    #[test]
+    fn later_rules_win_and_match_the_original_colors() {
+        let theme = Theme::new(
+            "test".to_string(),
+            vec![
+                Recolor::new(None, hex("#ffffff"), hex("#000000"), None),
+                // Matches the original white, not the black the first rule painted
+                Recolor::new(None, hex("#ffffff"), hex("#00ff00"), None),
+                Recolor::new(None, hex("#000000"), hex("#0000ff"), None),
+            ],
+        );
+        assert_eq!(
+            theme.colors_for("part", (hex("#ffffff"), None)),
+            (hex("#00ff00"), None)
+        );
+    }
+
+    #[test]
+    fn loading_themes() {
+        let dir = std::env::temp_dir().join(format!("theme-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("themes.json");
+        let path = path.to_str().unwrap();
+
+        fs::write(
+            path,
+            r##"[{ "name": "Red", "rules": [{ "id": "body_*", "fill": "#c0392b" }] }]"##,
+        )
+        .unwrap();
+        let themes = load_themes(path).unwrap();
+        assert_eq!(themes.len(), 1);
+        assert_eq!(themes[0].name(), "Red");
+        assert_eq!(
+            themes[0].colors_for("body_1", (hex("#ffffff"), None)).0,
+            hex("#c0392b")
+        );
+
+        // Rules have to paint something, and colors have to be hex
+        fs::write(
+            path,
+            r##"[{ "name": "Empty", "rules": [{ "id": "body_*" }] }]"##,
+        )
+        .unwrap();
+        assert!(load_themes(path).is_err());
+        fs::write(
+            path,
+            r##"[{ "name": "Bad", "rules": [{ "fill": "red" }] }]"##,
+        )
+        .unwrap();
+        assert!(load_themes(path).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
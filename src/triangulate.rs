@@ -0,0 +1,352 @@
+//! Ear clipping triangulation of polygons with holes.
+//!
+//! Borders nested inside an even number of others are outer boundaries, and the rest are holes of
+//! the innermost outer boundary around them. Each hole gets joined to its boundary through a
+//! "bridge" (a pair of overlapping edges going there and back), which leaves a single ring per
+//! outer boundary that can be clipped ear by ear.
+
+use crate::shapes::{segments_intersect, LineMethods, Point, Polygon, Universal};
+
+fn cross(o: Point<Universal>, a: Point<Universal>, b: Point<Universal>) -> f64 {
+    (a.x() as f64 - o.x() as f64) * (b.y() as f64 - o.y() as f64)
+        - (a.y() as f64 - o.y() as f64) * (b.x() as f64 - o.x() as f64)
+}
+
+/// Twice the signed area of the ring, positive when it goes clockwise on screen.
+fn ring_area(vertices: &[Point<Universal>], ring: &[usize]) -> f64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(i, j)| {
+            let (a, b) = (vertices[*i], vertices[*j]);
+            a.x() as f64 * b.y() as f64 - b.x() as f64 * a.y() as f64
+        })
+        .sum()
+}
+
+/// Indices of the border's points, without the repeated closing point or repeated neighbours.
+fn border_ring(vertices: &[Point<Universal>], start: usize, len: usize) -> Vec<usize> {
+    let mut ring: Vec<usize> = (start..start + len).collect();
+    ring.dedup_by_key(|i| vertices[*i]);
+    while ring.len() > 1 && vertices[ring[0]] == vertices[ring[ring.len() - 1]] {
+        ring.pop();
+    }
+    ring
+}
+
+fn ring_edges<'a>(
+    vertices: &'a [Point<Universal>],
+    ring: &'a [usize],
+) -> impl Iterator<Item = (Point<Universal>, Point<Universal>)> + 'a {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(move |(i, j)| (vertices[*i], vertices[*j]))
+}
+
+/// Whether `m` is on the inner side of the corner `prev`, `p`, `next` of a clockwise ring.
+fn locally_inside(
+    prev: Point<Universal>,
+    p: Point<Universal>,
+    next: Point<Universal>,
+    m: Point<Universal>,
+) -> bool {
+    if cross(prev, p, next) >= 0.0 {
+        cross(prev, p, m) >= 0.0 && cross(p, next, m) >= 0.0
+    } else {
+        cross(prev, p, m) >= 0.0 || cross(p, next, m) >= 0.0
+    }
+}
+
+/// Joins `hole` to `ring` with a bridge from the hole's rightmost vertex to the closest vertex of
+/// the ring it can see without crossing any edge of the ring or of the holes in `others`.
+fn bridge_hole(
+    vertices: &[Point<Universal>],
+    ring: &[usize],
+    hole: &[usize],
+    others: &[Vec<usize>],
+) -> Vec<usize> {
+    let start = (0..hole.len())
+        .max_by(|i, j| vertices[hole[*i]].x().total_cmp(&vertices[hole[*j]].x()))
+        .expect("holes have at least 3 points");
+    let m = vertices[hole[start]];
+
+    let mut candidates: Vec<usize> = (0..ring.len()).collect();
+    let distance = |k: &usize| {
+        let p = vertices[ring[*k]];
+        (p.x() as f64 - m.x() as f64).powi(2) + (p.y() as f64 - m.y() as f64).powi(2)
+    };
+    candidates.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+    let visible = |k: &usize| {
+        let p = vertices[ring[*k]];
+        let prev = vertices[ring[(k + ring.len() - 1) % ring.len()]];
+        let next = vertices[ring[(k + 1) % ring.len()]];
+        locally_inside(prev, p, next, m)
+            && ring_edges(vertices, ring)
+                .chain(others.iter().flat_map(|o| ring_edges(vertices, o)))
+                .filter(|(a, b)| ![p, m].contains(a) && ![p, m].contains(b))
+                .all(|(a, b)| !segments_intersect(m, p, a, b))
+    };
+    let bridge = candidates
+        .iter()
+        .copied()
+        .find(visible)
+        .unwrap_or(candidates[0]);
+
+    let mut merged = Vec::with_capacity(ring.len() + hole.len() + 2);
+    merged.extend_from_slice(&ring[..=bridge]);
+    merged.extend(hole[start..].iter().chain(&hole[..start]));
+    merged.push(hole[start]);
+    merged.extend_from_slice(&ring[bridge..]);
+    merged
+}
+
+/// Whether no vertex of the ring (other than copies of the corners) is in triangle `a`, `b`, `c`.
+fn is_ear(
+    vertices: &[Point<Universal>],
+    ring: &[usize],
+    next: &[usize],
+    (a, b, c): (usize, usize, usize),
+) -> bool {
+    let corners = [vertices[ring[a]], vertices[ring[b]], vertices[ring[c]]];
+    let mut k = next[c];
+    while k != a {
+        let p = vertices[ring[k]];
+        if !corners.contains(&p)
+            && cross(corners[0], corners[1], p) >= 0.0
+            && cross(corners[1], corners[2], p) >= 0.0
+            && cross(corners[2], corners[0], p) >= 0.0
+        {
+            return false;
+        }
+        k = next[k];
+    }
+    true
+}
+
+/// Clips the ears of a clockwise ring, pushing the triangles into `triangles`.
+fn clip_ears(vertices: &[Point<Universal>], ring: &[usize], triangles: &mut Vec<[usize; 3]>) {
+    let n = ring.len();
+    let mut prev: Vec<usize> = (0..n).map(|i| (i + n - 1) % n).collect();
+    let mut next: Vec<usize> = (0..n).map(|i| (i + 1) % n).collect();
+    let mut remaining = n;
+    let mut current = 0;
+    // Ears that were looked at since the last one got clipped
+    let mut checked = 0;
+
+    while remaining > 3 {
+        let (p, q) = (prev[current], next[current]);
+        let turn = cross(
+            vertices[ring[p]],
+            vertices[ring[current]],
+            vertices[ring[q]],
+        );
+        // With no ear left (which only happens if the borders cross each other) the first convex
+        // corner goes anyway, so that the loop always ends
+        let stuck = checked >= remaining;
+        let clip = turn > 0.0 && (stuck || is_ear(vertices, ring, &next, (p, current, q)));
+
+        if clip || turn == 0.0 || (stuck && checked >= 2 * remaining) {
+            // Corners without area are dropped without a triangle
+            if turn > 0.0 {
+                triangles.push([ring[p], ring[current], ring[q]]);
+            }
+            next[p] = q;
+            prev[q] = p;
+            remaining -= 1;
+            checked = 0;
+            current = q;
+        } else {
+            checked += 1;
+            current = q;
+        }
+    }
+
+    let (p, q) = (prev[current], next[current]);
+    if cross(
+        vertices[ring[p]],
+        vertices[ring[current]],
+        vertices[ring[q]],
+    ) > 0.0
+    {
+        triangles.push([ring[p], ring[current], ring[q]]);
+    }
+}
+
+impl Polygon<Universal> {
+    /// Splits the polygon into triangles, treating borders nested inside an odd number of others
+    /// as holes. Each triangle is made of indices into the polygon's vertices as they come out of
+    /// `get_borders().iter().flatten()`, and goes clockwise on screen.
+    pub fn triangulate(&self) -> Vec<[usize; 3]> {
+        let vertices: Vec<Point<Universal>> =
+            self.get_borders().iter().flatten().copied().collect();
+        let mut rings = Vec::new();
+        let mut start = 0;
+        for border in self.get_borders() {
+            rings.push(border_ring(&vertices, start, border.len()));
+            start += border.len();
+        }
+
+        // Nesting depth of each border, and for holes the outer border right around them
+        let borders = self.get_borders();
+        let enclosing = |i: usize| -> Vec<usize> {
+            (0..borders.len())
+                .filter(|j| *j != i && !borders[i].is_empty())
+                .filter(|j| borders[*j].encloses(borders[i][0]))
+                .collect()
+        };
+        let depths: Vec<usize> = (0..borders.len()).map(|i| enclosing(i).len()).collect();
+
+        let mut triangles = Vec::new();
+        for outer in 0..rings.len() {
+            if depths[outer] % 2 == 1 || rings[outer].len() < 3 {
+                continue;
+            }
+            let mut ring = rings[outer].clone();
+            if ring_area(&vertices, &ring) < 0.0 {
+                ring.reverse();
+            }
+
+            let mut holes: Vec<Vec<usize>> = (0..rings.len())
+                .filter(|h| depths[*h] == depths[outer] + 1 && rings[*h].len() >= 3)
+                .filter(|h| enclosing(*h).contains(&outer))
+                .map(|h| {
+                    let mut hole = rings[h].clone();
+                    if ring_area(&vertices, &hole) > 0.0 {
+                        hole.reverse();
+                    }
+                    hole
+                })
+                .collect();
+            // Bridging from right to left keeps the bridges from having to go around each other
+            let max_x = |hole: &Vec<usize>| {
+                hole.iter()
+                    .map(|i| vertices[*i].x())
+                    .fold(Universal::MIN, Universal::max)
+            };
+            holes.sort_by(|a, b| max_x(b).total_cmp(&max_x(a)));
+
+            for (k, hole) in holes.iter().enumerate() {
+                ring = bridge_hole(&vertices, &ring, hole, &holes[k..]);
+            }
+            clip_ears(&vertices, &ring, &mut triangles);
+        }
+        triangles
+    }
+
+    /// The polygon's triangles as points, see [`Polygon::triangulate`].
+    pub fn triangles(&self) -> Vec<[Point<Universal>; 3]> {
+        let vertices: Vec<Point<Universal>> =
+            self.get_borders().iter().flatten().copied().collect();
+        self.triangulate()
+            .iter()
+            .map(|t| [vertices[t[0]], vertices[t[1]], vertices[t[2]]])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::{line, point, polygon, square};
+    use crate::shapes::FillRule;
+
+    /// Total area of the triangles, checking that every one of them goes clockwise.
+    fn triangles_area(polygon: &Polygon<Universal>) -> f64 {
+        polygon
+            .triangles()
+            .iter()
+            .map(|[a, b, c]| {
+                let area = cross(*a, *b, *c) / 2.0;
+                assert!(area > 0.0, "{:?} isn't clockwise", [a, b, c]);
+                area
+            })
+            .sum()
+    }
+
+    fn assert_area(polygon: &Polygon<Universal>, expected: f64) {
+        let area = triangles_area(polygon);
+        assert!((area - expected).abs() < 1e-6, "{} != {}", area, expected);
+    }
+
+    #[test]
+    fn convex_polygon() {
+        let square = polygon(vec![square(0.0, 0.0, 10.0)], FillRule::NonZero);
+        assert_eq!(square.triangulate().len(), 2);
+        assert_area(&square, 100.0);
+    }
+
+    #[test]
+    fn counterclockwise_and_closed_borders() {
+        let mut closed = square(0.0, 0.0, 10.0);
+        closed.reverse();
+        closed.push(closed[0]);
+        assert_area(&polygon(vec![closed], FillRule::NonZero), 100.0);
+    }
+
+    #[test]
+    fn concave_polygon() {
+        let l_shape = polygon(
+            vec![line(&[
+                (0.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 8.0),
+                (6.0, 8.0),
+                (6.0, 10.0),
+                (0.0, 10.0),
+            ])],
+            FillRule::NonZero,
+        );
+        assert_eq!(l_shape.triangulate().len(), 4);
+        assert_area(&l_shape, 28.0);
+    }
+
+    #[test]
+    fn holes() {
+        let donut = polygon(
+            vec![square(0.0, 0.0, 10.0), square(2.0, 2.0, 2.0)],
+            FillRule::NonZero,
+        );
+        assert_area(&donut, 96.0);
+        // No triangle covers the hole
+        for [a, b, c] in donut.triangles() {
+            let center = point((a.x() + b.x() + c.x()) / 3.0, (a.y() + b.y() + c.y()) / 3.0);
+            assert!(!square(2.0, 2.0, 2.0).encloses(center), "{:?}", [a, b, c]);
+        }
+
+        let holes = polygon(
+            vec![
+                square(0.0, 0.0, 10.0),
+                square(1.0, 1.0, 2.0),
+                square(6.0, 1.0, 2.0),
+                square(3.0, 6.0, 3.0),
+            ],
+            FillRule::NonZero,
+        );
+        assert_area(&holes, 100.0 - 4.0 - 4.0 - 9.0);
+    }
+
+    #[test]
+    fn island_inside_a_hole() {
+        let target = polygon(
+            vec![
+                square(0.0, 0.0, 10.0),
+                square(2.0, 2.0, 6.0),
+                square(4.0, 4.0, 2.0),
+            ],
+            FillRule::NonZero,
+        );
+        assert_area(&target, 100.0 - 36.0 + 4.0);
+    }
+
+    #[test]
+    fn indices_point_into_the_flattened_borders() {
+        let pair = polygon(
+            vec![square(0.0, 0.0, 1.0), square(5.0, 0.0, 1.0)],
+            FillRule::NonZero,
+        );
+        let triangles = pair.triangulate();
+        assert_eq!(triangles.len(), 4);
+        assert!(triangles.iter().flatten().all(|i| *i < 8));
+        assert_area(&pair, 2.0);
+    }
+}
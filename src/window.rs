@@ -1,22 +1,113 @@
 //! Contains the Window class, which represents the window in the computer graphics
-//! sense. It wraps the sdl_wrapper ScreenContextManager and implements all the drawing methods.
+//! sense. It wraps the sdl_wrapper ScreenContextManager (through a [`Canvas`]) and implements all
+//! the drawing methods.
 use anyhow::{anyhow, Context, Result};
-use sdl_wrapper::{EventPollIterator, ScreenContextManager};
+use sdl_wrapper::EventPollIterator;
 
+use crate::canvas::{AlphaMask, Canvas, PixelTarget};
 use crate::car::{self, Car};
-use crate::constants::{BACKGROUND_COLOR, PAN_PERCENT, SCENE_SIZE, WINDOW_HEIGHT, WINDOW_WIDTH};
-use crate::shapes::{Color, Framebuffer, Line, LineClip, Point, Polygon, Segment, Universal};
+use crate::constants::{
+    BACKGROUND_COLOR, COARSEST_DETAIL_BAND, CURVE_PIXEL_TOLERANCE, FINEST_DETAIL_BAND, PAN_PERCENT,
+    SCENE_CENTER, SCENE_SIZE, SIMPLIFY_TOLERANCE, WINDOW_HEIGHT, WINDOW_WIDTH,
+};
+use crate::export::{self, ExportState};
+use crate::shapes::{
+    BoundingBox, Color, Framebuffer, Line, LineClip, LineMethods, Mask, ModelTransform, Point,
+    Polygon, Segment, Subpixel, Universal, WorldBounds, SUBPIXEL_ONE,
+};
+use crate::simplify::{LineSimplify, Simplification};
+use crate::spatial::SpatialIndex;
+use crate::theme::Theme;
 
 use std::cmp::Ordering;
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub enum DisplayMode {
     NoColor,
     ColorFill,
+    /// Outlines of the triangles the polygons get split into
+    Triangles,
     TextureFill,
     CarTextureFill,
 }
 
+/// An angle in either unit. Positive angles turn clockwise on screen.
+#[derive(Debug, Clone, Copy)]
+pub enum Angle {
+    Degrees(Universal),
+    // The keys only rotate by degrees, but callers working in radians needn't convert
+    #[allow(dead_code)]
+    Radians(Universal),
+}
+
+impl Angle {
+    pub fn radians(&self) -> Universal {
+        match self {
+            Angle::Degrees(degrees) => degrees.to_radians(),
+            Angle::Radians(radians) => *radians,
+        }
+    }
+}
+
+/// Point that rotations turn the car around.
+#[derive(Debug, Clone, Copy)]
+pub enum Pivot {
+    SceneCenter,
+    /// Center of the part of the scene currently shown
+    WindowCenter,
+    CarCentroid,
+    /// Wherever the mouse was last seen over the window
+    Mouse,
+    // Left out of the key cycle since there's no way to type a point in yet
+    #[allow(dead_code)]
+    Point(Point<Universal>),
+}
+
+impl Pivot {
+    /// Cycles through the pivots that don't need a point to be given.
+    pub fn next(&self) -> Pivot {
+        match self {
+            Pivot::SceneCenter => Pivot::WindowCenter,
+            Pivot::WindowCenter => Pivot::CarCentroid,
+            Pivot::CarCentroid => Pivot::Mouse,
+            Pivot::Mouse | Pivot::Point(_) => Pivot::SceneCenter,
+        }
+    }
+}
+
+/// `target` (in radians) moved to the closest multiple of `snap`, if there's one.
+fn snap_angle(target: Universal, snap: Option<Angle>) -> Universal {
+    match snap.map(|snap| snap.radians()) {
+        Some(step) if step > 0.0 => (target / step).round() * step,
+        _ => target,
+    }
+}
+
+/// Point of the scene under the center of the pixel (x, y), when the window shows the part of
+/// the scene between `min_point` and `max_point`.
+fn pixel_center(
+    min_point: Point<Universal>,
+    max_point: Point<Universal>,
+    x: i32,
+    y: i32,
+) -> Point<Universal> {
+    Point::new_unchecked(
+        min_point.x()
+            + (x as Universal + 0.5) * (max_point.x() - min_point.x()) / WINDOW_WIDTH as Universal,
+        min_point.y()
+            + (y as Universal + 0.5) * (max_point.y() - min_point.y()) / WINDOW_HEIGHT as Universal,
+    )
+}
+
+/// What the view can be fitted to.
+#[derive(Debug, Clone)]
+pub enum Fit {
+    Car,
+    /// The polygon with this id
+    Polygon(String),
+}
+
 pub enum Pan {
     Up,
     Down,
@@ -31,7 +122,10 @@ pub struct Window {
     /// The bottom-right corner
     max_point: Point<Universal>,
 
-    screen: ScreenContextManager,
+    /// Limits of the scene, which the view never leaves
+    world: WorldBounds,
+
+    screen: Canvas,
 
     /// Store background color
     background_color: Color,
@@ -39,54 +133,92 @@ pub struct Window {
     /// Display mode for the car
     display_mode: DisplayMode,
 
-    /// Rotations carried out on the car
-    rotations: i32,
+    /// Where the car is placed in the scene. The car itself is never modified, this is applied
+    /// to it whenever it's drawn.
+    model: ModelTransform,
+
+    /// If set, the car's total rotation always lands on multiples of this angle
+    angle_snap: Option<Angle>,
+
+    /// Last known position of the mouse in the framebuffer
+    mouse: (i32, i32),
+
+    /// Whether the polygons hidden in the svg get drawn
+    show_hidden: bool,
+
+    /// Algorithm used to drop vertices that would fall on the same pixels, if any
+    simplification: Option<Simplification>,
+
+    /// The car's curves flattened for each zoom band
+    flattened: HashMap<i32, Car>,
+
+    /// Simplified versions of the flattened car, one per algorithm and zoom band
+    simplified: HashMap<(Simplification, i32), Car>,
+
+    /// Triangles of the car drawn at each zoom band (simplified or not), for
+    /// `DisplayMode::Triangles`
+    triangulated: HashMap<(Option<Simplification>, i32), Car>,
+
+    /// Fill and stroke of every polygon as parsed, which themes are matched against
+    original_colors: Vec<(Option<Color>, Option<Color>)>,
 
     car: Car,
+    /// Index over the car's polygons. The car is never modified (only the model transform
+    /// changes), so it's built once
+    index: SpatialIndex,
     min_layer: i32,
     max_layer: i32,
 }
 
 impl Window {
-    pub fn new(title: &str, width: u32, height: u32, car: Car) -> Result<Window> {
-        let screen = ScreenContextManager::new(title, WINDOW_WIDTH, WINDOW_HEIGHT)?;
+    pub fn new(
+        title: &str,
+        width: u32,
+        height: u32,
+        car: Car,
+        world: WorldBounds,
+    ) -> Result<Window> {
+        if let Some(point) = car
+            .iter()
+            .flat_map(|p| p.get_borders().iter().flatten())
+            .find(|p| !world.contains(**p))
+        {
+            return Err(anyhow!(
+                "El carro tiene puntos fuera del mundo {:?}, como {:?}",
+                world,
+                point
+            ));
+        }
+
+        let screen = Canvas::new(title)?;
         let background_color = Color::from_hex(BACKGROUND_COLOR)?;
         let display_mode = DisplayMode::NoColor;
+        let view = home_view(&world, width as Universal / height as Universal);
 
-        let window = Window {
-            min_point: Point::<Universal>::new(0.0, 0.0)?,
-            max_point: Point::<Universal>::new(SCENE_SIZE as f32, SCENE_SIZE as f32)?,
+        Ok(Window {
+            min_point: view.min(),
+            max_point: view.max(),
+            world,
             screen,
             background_color,
             display_mode,
-            rotations: 0,
+            model: ModelTransform::identity(),
+            angle_snap: None,
+            mouse: (WINDOW_WIDTH as i32 / 2, WINDOW_HEIGHT as i32 / 2),
+            show_hidden: false,
+            simplification: Some(Simplification::DouglasPeucker),
+            flattened: HashMap::new(),
+            simplified: HashMap::new(),
+            triangulated: HashMap::new(),
             min_layer: car::get_lowest_layer(&car),
             max_layer: car::get_top_layer(&car),
+            index: SpatialIndex::new(&car),
+            original_colors: car
+                .iter()
+                .map(|p| (p.get_fill_color(), p.get_stroke_color()))
+                .collect(),
             car,
-        };
-
-        // center window in the scene
-        let window = match height.cmp(&width) {
-            Ordering::Greater => {
-                let spacing = (SCENE_SIZE as f32 - width as f32) / 2.0;
-                Window {
-                    min_point: Point::<Universal>::new(spacing, 0.0)?,
-                    max_point: Point::<Universal>::new(spacing + width as f32, SCENE_SIZE as f32)?,
-                    ..window
-                }
-            }
-            Ordering::Less => {
-                let spacing = (SCENE_SIZE as f32 - width as f32) / 2.0;
-                Window {
-                    min_point: Point::<Universal>::new(0.0, spacing)?,
-                    max_point: Point::<Universal>::new(SCENE_SIZE as f32, spacing + height as f32)?,
-                    ..window
-                }
-            }
-            Ordering::Equal => window,
-        };
-
-        Ok(window)
+        })
     }
 
     pub fn get_events(&mut self) -> EventPollIterator {
@@ -96,34 +228,43 @@ impl Window {
     pub async fn update(&mut self) -> Result<()> {
         // First clear background
         match self.display_mode {
-            DisplayMode::NoColor => {
+            DisplayMode::NoColor | DisplayMode::Triangles => {
                 self.screen.clear(0.9);
             }
             _ => {
-                self.screen.clear_with_rgb(
-                    self.background_color.r(),
-                    self.background_color.g(),
-                    self.background_color.b(),
-                );
+                self.screen.clear_with_color(self.background_color);
             }
         }
 
         // Then paint car
-        let fb_polys: Vec<Polygon<Framebuffer>> =
-            self.map_to_framebuffer(&self.clip_car(&self.car))?;
+        let band = self.detail_level();
+        let car = match (&self.display_mode, self.simplification) {
+            (DisplayMode::Triangles, simplification) => &self.triangulated[&(simplification, band)],
+            (_, Some(algorithm)) => &self.simplified[&(algorithm, band)],
+            (_, None) => &self.flattened[&band],
+        };
+        let visible = self
+            .view_in_car()
+            .map(|view| self.index.query_rect(&view))
+            .unwrap_or_else(|| (0..car.len()).collect());
+        let visible: Vec<&Polygon<Universal>> = visible.into_iter().map(|i| &car[i]).collect();
 
         //println!("mode: {:?}", self.mode);
         match &self.display_mode {
-            DisplayMode::NoColor => self.no_color_draw(&fb_polys),
-            DisplayMode::ColorFill => self.color_draw(&fb_polys),
+            DisplayMode::NoColor | DisplayMode::Triangles => {
+                let strokes = self.map_to_framebuffer(&self.clip_strokes(visible))?;
+                self.no_color_draw(&strokes);
+            }
+            DisplayMode::ColorFill => {
+                let fills = self.map_to_framebuffer(&self.clip_car(visible.iter().copied()))?;
+                let strokes = self.map_to_framebuffer(&self.clip_strokes(visible))?;
+                self.color_draw(&fills, &strokes);
+            }
             _ => (),
         }
 
         // Finally present changes
-        self.screen
-            .present()
-            .await
-            .unwrap_or_else(|err| println!("Error while presenting screen: {}", err));
+        self.screen.present().await;
 
         Ok(())
     }
@@ -132,8 +273,106 @@ impl Window {
         self.display_mode = mode;
     }
 
-    fn no_color_draw(&mut self, fb_polys: &[Polygon<Framebuffer>]) {
-        self.screen.set_color(0.0, 0.0, 0.0);
+    pub fn set_simplification(&mut self, simplification: Option<Simplification>) {
+        self.simplification = simplification;
+    }
+
+    pub fn get_simplification(&self) -> Option<Simplification> {
+        self.simplification
+    }
+
+    /// Picks the zoom band the car is drawn at, building its cars the first time they're needed.
+    ///
+    /// Zoom bands are powers of two of the car units that fit in a pixel, and each band is made
+    /// with the tolerances of its most zoomed in end: the curves are flattened so that the lines
+    /// stray at most `CURVE_PIXEL_TOLERANCE` pixels from them, and then (if an algorithm is set)
+    /// simplified so that vertices never move by more than `SIMPLIFY_TOLERANCE` pixels. In
+    /// `DisplayMode::Triangles` that car also gets triangulated, once per band.
+    ///
+    /// Only the cars of the current band and the ones next to it are kept, since those are the
+    /// ones zooming can get back to soon.
+    fn detail_level(&mut self) -> i32 {
+        let units_per_pixel = (self.max_point.x() - self.min_point.x())
+            / WINDOW_WIDTH as Universal
+            / self.model.scale();
+        let band =
+            (units_per_pixel.log2().floor() as i32).clamp(FINEST_DETAIL_BAND, COARSEST_DETAIL_BAND);
+        let pixel = (2.0 as Universal).powi(band);
+
+        let near = |other: i32| (other - band).abs() <= 1;
+        self.flattened.retain(|b, _| near(*b));
+        self.simplified.retain(|(_, b), _| near(*b));
+        self.triangulated.retain(|(_, b), _| near(*b));
+
+        let car = &self.car;
+        let flattened = self.flattened.entry(band).or_insert_with(|| {
+            car.iter()
+                .map(|polygon| polygon.flattened(CURVE_PIXEL_TOLERANCE * pixel))
+                .collect()
+        });
+        if let Some(algorithm) = self.simplification {
+            self.simplified.entry((algorithm, band)).or_insert_with(|| {
+                flattened
+                    .iter()
+                    .map(|polygon| {
+                        polygon.new_copy_attributes(
+                            polygon
+                                .get_borders()
+                                .iter()
+                                .map(|border| {
+                                    border.simplify(algorithm, SIMPLIFY_TOLERANCE * pixel)
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect()
+            });
+        }
+        if let DisplayMode::Triangles = self.display_mode {
+            let key = (self.simplification, band);
+            if !self.triangulated.contains_key(&key) {
+                let drawn = match self.simplification {
+                    Some(algorithm) => &self.simplified[&(algorithm, band)],
+                    None => &self.flattened[&band],
+                };
+                let triangles = triangles_car(drawn);
+                self.triangulated.insert(key, triangles);
+            }
+        }
+        band
+    }
+
+    /// Paints the car with `theme`, or back with its original colors. The cars already built for
+    /// each zoom band get repainted too, since they line up polygon by polygon with the car.
+    pub fn set_theme(&mut self, theme: Option<&Theme>) {
+        let colors: Vec<(Option<Color>, Option<Color>)> = self
+            .car
+            .iter()
+            .zip(self.original_colors.iter())
+            .map(|(polygon, original)| match theme {
+                Some(theme) => theme.colors_for(polygon.id(), *original),
+                None => *original,
+            })
+            .collect();
+
+        let cars = std::iter::once(&mut self.car)
+            .chain(self.flattened.values_mut())
+            .chain(self.simplified.values_mut());
+        for car in cars {
+            for (polygon, (fill, stroke)) in car.iter_mut().zip(colors.iter()) {
+                polygon.set_fill_color(*fill);
+                polygon.set_stroke_color(*stroke);
+            }
+        }
+    }
+
+    /// Toggles drawing the polygons that are hidden in the svg.
+    pub fn toggle_hidden(&mut self) {
+        self.show_hidden = !self.show_hidden;
+    }
+
+    fn no_color_draw(&mut self, fb_polys: &[Polygon<Subpixel>]) {
+        self.screen.set_color(Color::BLACK);
         for poly in fb_polys {
             for line in poly.get_borders() {
                 for segment in line.windows(2) {
@@ -149,27 +388,32 @@ impl Window {
         }
     }
 
-    fn color_draw(&mut self, fb_polys: &[Polygon<Framebuffer>]) {
+    /// Paints the fill of each polygon in `fills` followed by its stroke from `strokes`, which
+    /// holds the same polygons clipped as open lines.
+    fn color_draw(&mut self, fills: &[Polygon<Subpixel>], strokes: &[Polygon<Subpixel>]) {
         for layer in self.min_layer..self.max_layer {
-            'polys: for poly in fb_polys {
+            'polys: for (poly, stroke) in fills.iter().zip(strokes) {
                 if poly.get_layer() != layer {
                     continue 'polys;
                 }
 
                 // fill shapes
                 if let Some(color) = poly.get_fill_color() {
-                    self.screen.set_color(color.r(), color.g(), color.b());
+                    self.screen.set_color(color);
+                    let alpha = poly.get_mask().map(|mask| self.rasterize_mask(mask));
+                    self.screen.set_alpha(alpha);
                     let sl_data = ScanlineData::new(poly);
                     if let Ok(data) = sl_data {
                         scanline(&mut self.screen, data)
                     };
+                    self.screen.set_alpha(None);
                 }
 
                 // draw strokes
-                if let Some(color) = poly.get_stroke_color() {
-                    self.screen.set_color(color.r(), color.g(), color.b());
+                if let Some(color) = stroke.get_stroke_color() {
+                    self.screen.set_color(color);
 
-                    for line in poly.get_borders() {
+                    for line in stroke.get_borders() {
                         for segment in line.windows(2) {
                             let segment = Segment {
                                 x0: segment[0].x(),
@@ -185,95 +429,196 @@ impl Window {
         }
     }
 
-    pub fn zoom(&mut self, zoom: Universal) -> Result<()> {
-        let x_c = (self.min_point.x() + self.max_point.x()) / 2.0;
-        let y_c = (self.min_point.y() + self.max_point.y()) / 2.0;
-
-        //println!(
-        //    "Before zoom '{}' old points are: {:?}\t{:?}",
-        //    zoom, self.max_point, self.min_point
-        //);
-        let min_x = (self.min_point.x() - x_c) * zoom + x_c;
-        let min_y = (self.min_point.y() - y_c) * zoom + y_c;
-        let max_x = (self.max_point.x() - x_c) * zoom + x_c;
-        let max_y = (self.max_point.y() - y_c) * zoom + y_c;
-
-        //println!(
-        //    "\nmin_x: {}\tmin_y: {}\nmax_x: {}\tmax_y: {}",
-        //    min_x, min_y, max_x, max_y
-        //);
-
-        let (min, max): (Point<Universal>, Point<Universal>) = match (
-            Point::<Universal>::new(min_x, min_y),
-            Point::<Universal>::new(max_x, max_y),
-        ) {
-            (Err(_), Ok(_)) if zoom > 1.0 => {
-                let x_displacement = (min_x - min_x.abs()) / 2.0;
-                let y_displacement = (min_y - min_y.abs()) / 2.0;
-                println!("x_disp: {}\t y_disp: {}", x_displacement, y_displacement);
-                (
-                    Point::<Universal>::new(min_x - x_displacement, min_y - y_displacement)
-                        .context("The other serious error")?,
-                    Point::<Universal>::new(
-                        (max_x - x_displacement).min(SCENE_SIZE as Universal),
-                        (max_y - y_displacement).min(SCENE_SIZE as Universal),
-                    )
-                    .context("The other serious error but for max")?,
-                )
+    /// Rasterizes the shapes of a mask, each one plotting the luminance of its fill color.
+    fn rasterize_mask(&self, mask: &Mask) -> AlphaMask {
+        let mut alpha = AlphaMask::transparent();
+        let fb_mask = self
+            .map_to_framebuffer(&self.clip_car(mask))
+            .unwrap_or_else(|err| {
+                println!("Mask couldn't be mapped to the framebuffer: {}", err);
+                Vec::new()
+            });
+        for poly in fb_mask.iter() {
+            if let Some(color) = poly.get_fill_color() {
+                alpha.set_value(color.luminance());
+                if let Ok(data) = ScanlineData::new(poly) {
+                    scanline(&mut alpha, data)
+                }
             }
-            (Ok(_), Err(_)) if zoom > 1.0 => {
-                let x_displacement = (max_x - SCENE_SIZE as f32).max(0.0);
-                let y_displacement = (max_y - SCENE_SIZE as f32).max(0.0);
-                println!("x_disp: {}\t y_disp: {}", x_displacement, y_displacement);
-                (
-                    Point::<Universal>::new(
-                        (min_x - x_displacement).max(0.0),
-                        (min_y - y_displacement).max(0.0),
-                    )
-                    .context("Actually a serious error but for min")?,
-                    Point::<Universal>::new(max_x - x_displacement, max_y - y_displacement)
-                        .context("Actually a serious error")?,
-                )
+        }
+        alpha
+    }
+
+    pub fn zoom(&mut self, zoom: Universal) -> Result<()> {
+        let center = (self.min_point + self.max_point) / 2.0;
+        let min = center + (self.min_point - center) * zoom;
+        let max = center + (self.max_point - center) * zoom;
+
+        let (min, max) = match self.world {
+            // Zooming out pushes the view back inside the world, and once it's as big as the world
+            // it stays like the starting view
+            WorldBounds::Bounded(world) if zoom > 1.0 => {
+                if max.x() - min.x() > world.width() || max.y() - min.y() > world.height() {
+                    let view = home_view(&self.world, self.aspect());
+                    (view.min(), view.max())
+                } else {
+                    let shift = Point::new_unchecked(
+                        (world.min().x() - min.x()).max(0.0) - (max.x() - world.max().x()).max(0.0),
+                        (world.min().y() - min.y()).max(0.0) - (max.y() - world.max().y()).max(0.0),
+                    );
+                    // Clamped so that rounding errors don't leave it barely outside
+                    (self.world.clamp(min + shift), self.world.clamp(max + shift))
+                }
             }
-            (Err(_), Err(_)) if zoom > 1.0 => (
-                Point::<Universal>::new(0.0, 0.0)?,
-                Point::<Universal>::new(SCENE_SIZE as f32, SCENE_SIZE as f32)?,
-            ),
-            (Err(err), _) | (_, Err(err)) if zoom <= 1.0 => {
-                return Err(anyhow!("Error zooming into picture: {}", err));
+            _ => (min, max),
+        };
+
+        self.min_point = Point::<Universal>::new_within(min.x(), min.y(), &self.world)
+            .context("Error zooming into picture")?;
+        self.max_point = Point::<Universal>::new_within(max.x(), max.y(), &self.world)
+            .context("Error zooming into picture")?;
+        Ok(())
+    }
+
+    fn aspect(&self) -> Universal {
+        WINDOW_WIDTH as Universal / WINDOW_HEIGHT as Universal
+    }
+
+    pub fn reset(&mut self) {
+        self.model = ModelTransform::identity();
+        let view = home_view(&self.world, self.aspect());
+        self.min_point = view.min();
+        self.max_point = view.max();
+    }
+
+    /// Saves the car as an svg to `path`, either as it was parsed or as it's currently shown.
+    pub fn export(&self, path: &str, state: ExportState) -> Result<()> {
+        match state {
+            ExportState::Transformed => {
+                let mut car = self.car.clone();
+                car::transform_car(&mut car, &self.model.to_matrix());
+                export::save_svg(path, &car, &self.world)
             }
+            ExportState::Pristine => export::save_svg(path, &self.car, &self.world),
+        }
+    }
 
-            (Ok(min), Ok(max)) => (min, max),
-            (Err(err), _) | (_, Err(err)) => {
-                return Err(anyhow!(
-                    "Extremely weird error in window.zoom() match arms: {}",
-                    err
-                ));
+    /// Moves and zooms the window so that `target` (as currently placed in the scene) fills it,
+    /// leaving `margin` (a fraction of the target's size) around it. The view never goes past the
+    /// world's boundaries.
+    pub fn fit(&mut self, target: &Fit, margin: Universal) -> Result<()> {
+        let matrix = self.model.to_matrix();
+        let bounds = match target {
+            Fit::Car => self
+                .car
+                .iter()
+                .filter_map(|p| p.transformed_bounding_box(&matrix))
+                .reduce(|a, b| a.union(&b)),
+            Fit::Polygon(id) => self
+                .car
+                .iter()
+                .find(|p| p.id() == id)
+                .ok_or_else(|| anyhow!("No existe polígono con id '{}'", id))?
+                .transformed_bounding_box(&matrix),
+        }
+        .ok_or_else(|| anyhow!("{:?} no tiene puntos", target))?;
+
+        // The window keeps its aspect ratio, so the box grows in the shorter direction
+        let aspect = self.aspect();
+        let height = (bounds.height() * (1.0 + 2.0 * margin))
+            .max(bounds.width() * (1.0 + 2.0 * margin) / aspect)
+            .max(Universal::EPSILON);
+        let width = height * aspect;
+
+        let (min_x, min_y, width, height) = match self.world {
+            WorldBounds::Bounded(world) => {
+                let scale = (world.width() / width)
+                    .min(world.height() / height)
+                    .min(1.0);
+                let (width, height) = (width * scale, height * scale);
+                let min_x = (bounds.center().x() - width / 2.0)
+                    .max(world.min().x())
+                    .min(world.max().x() - width);
+                let min_y = (bounds.center().y() - height / 2.0)
+                    .max(world.min().y())
+                    .min(world.max().y() - height);
+                (min_x, min_y, width, height)
             }
+            WorldBounds::Unbounded => (
+                bounds.center().x() - width / 2.0,
+                bounds.center().y() - height / 2.0,
+                width,
+                height,
+            ),
         };
+        self.min_point = self.world.clamp(Point::<Universal>::new(min_x, min_y)?);
+        self.max_point = self
+            .world
+            .clamp(Point::<Universal>::new(min_x + width, min_y + height)?);
+        Ok(())
+    }
 
-        self.min_point = min;
-        self.max_point = max;
+    /// Rotates the car by `angle` about `pivot`, snapping the total rotation if snapping is on.
+    pub fn rotate(&mut self, angle: Angle, pivot: Pivot) {
+        let target = snap_angle(self.model.rotation() + angle.radians(), self.angle_snap);
+        let theta = target - self.model.rotation();
+        if theta == 0.0 {
+            return;
+        }
 
-        //println!(
-        //    "After zoom '{}' new points are: {:?}\t{:?}",
-        //    zoom, self.max_point, self.min_point
-        //);
-        Ok(())
+        let pivot = self.pivot_point(pivot);
+        self.model.rotate_about(theta, pivot);
     }
 
-    pub fn reset(&mut self) {
-        if self.rotations != 0 {
-            self.rotate(-self.rotations)
+    pub fn set_angle_snap(&mut self, snap: Option<Angle>) {
+        self.angle_snap = snap;
+    }
+
+    pub fn get_angle_snap(&self) -> Option<Angle> {
+        self.angle_snap
+    }
+
+    /// Every polygon under the pixel (x, y) of the window, in paint order.
+    pub fn polygons_at(&self, x: i32, y: i32) -> Result<Vec<&Polygon<Universal>>> {
+        // The car is stored untransformed, so the point is taken back to the car's coordinates
+        let point = self
+            .model
+            .to_matrix()
+            .invert()?
+            .apply(self.framebuffer_to_universal(x, y));
+        let candidates = self.index.query_point(point);
+        Ok(car::polygons_at(
+            candidates.into_iter().map(|i| &self.car[i]),
+            point,
+        ))
+    }
+
+    /// Remembers where the mouse is, in framebuffer coordinates, for `Pivot::Mouse`.
+    pub fn set_mouse(&mut self, x: i32, y: i32) {
+        self.mouse = (x, y);
+    }
+
+    fn pivot_point(&self, pivot: Pivot) -> Point<Universal> {
+        match pivot {
+            Pivot::SceneCenter => self
+                .world
+                .as_box()
+                .map_or(Point::new_unchecked(SCENE_CENTER, SCENE_CENTER), |world| {
+                    world.center()
+                }),
+            Pivot::WindowCenter => Point::new_unchecked(
+                (self.min_point.x() + self.max_point.x()) / 2.0,
+                (self.min_point.y() + self.max_point.y()) / 2.0,
+            ),
+            Pivot::CarCentroid => self.model.apply(car::centroid(&self.car)),
+            Pivot::Mouse => self.framebuffer_to_universal(self.mouse.0, self.mouse.1),
+            Pivot::Point(point) => point,
         }
-        self.min_point = Point::<Universal>::new_unchecked(0.0, 0.0);
-        self.max_point =
-            Point::<Universal>::new_unchecked(SCENE_SIZE as Universal, SCENE_SIZE as Universal);
     }
 
-    pub fn rotate(&mut self, amount: i32) {
-        self.rotations += amount;
-        car::rotate_car(&mut self.car, amount);
+    /// Inverse of the mapping done in `map_to_framebuffer()`, for the center of the pixel (x, y).
+    fn framebuffer_to_universal(&self, x: i32, y: i32) -> Point<Universal> {
+        pixel_center(self.min_point, self.max_point, x, y)
     }
 
     pub fn pan(&mut self, pan: Pan) -> Result<()> {
@@ -284,28 +629,60 @@ impl Window {
 
         let (max, min) = match pan {
             Pan::Up => (
-                Point::<Universal>::new(self.max_point.x(), self.max_point.y() - distance)
-                    .context("Límite superior de la escena alcanzado")?,
-                Point::<Universal>::new(self.min_point.x(), self.min_point.y() - distance)
-                    .context("Límite superior de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.max_point.x(),
+                    self.max_point.y() - distance,
+                    &self.world,
+                )
+                .context("Límite superior de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.min_point.x(),
+                    self.min_point.y() - distance,
+                    &self.world,
+                )
+                .context("Límite superior de la escena alcanzado")?,
             ),
             Pan::Down => (
-                Point::<Universal>::new(self.max_point.x(), self.max_point.y() + distance)
-                    .context("Límite inferior de la escena alcanzado")?,
-                Point::<Universal>::new(self.min_point.x(), self.min_point.y() + distance)
-                    .context("Límite inferior de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.max_point.x(),
+                    self.max_point.y() + distance,
+                    &self.world,
+                )
+                .context("Límite inferior de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.min_point.x(),
+                    self.min_point.y() + distance,
+                    &self.world,
+                )
+                .context("Límite inferior de la escena alcanzado")?,
             ),
             Pan::Left => (
-                Point::<Universal>::new(self.max_point.x() - distance, self.max_point.y())
-                    .context("Límite izquierdo de la escena alcanzado")?,
-                Point::<Universal>::new(self.min_point.x() - distance, self.min_point.y())
-                    .context("Límite izquierdo de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.max_point.x() - distance,
+                    self.max_point.y(),
+                    &self.world,
+                )
+                .context("Límite izquierdo de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.min_point.x() - distance,
+                    self.min_point.y(),
+                    &self.world,
+                )
+                .context("Límite izquierdo de la escena alcanzado")?,
             ),
             Pan::Right => (
-                Point::<Universal>::new(self.max_point.x() + distance, self.max_point.y())
-                    .context("Límite derecho de la escena alcanzado")?,
-                Point::<Universal>::new(self.min_point.x() + distance, self.min_point.y())
-                    .context("Límite derecho de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.max_point.x() + distance,
+                    self.max_point.y(),
+                    &self.world,
+                )
+                .context("Límite derecho de la escena alcanzado")?,
+                Point::<Universal>::new_within(
+                    self.min_point.x() + distance,
+                    self.min_point.y(),
+                    &self.world,
+                )
+                .context("Límite derecho de la escena alcanzado")?,
             ),
         };
         self.min_point = min;
@@ -313,61 +690,133 @@ impl Window {
         Ok(())
     }
 
-    fn map_to_framebuffer(&self, clipped_car: &Car) -> Result<Vec<Polygon<Framebuffer>>> {
+    fn map_to_framebuffer(&self, clipped_car: &Car) -> Result<Vec<Polygon<Subpixel>>> {
         clipped_car
             .iter()
             .map(|polygon| {
                 let fb_borders = polygon
                     .get_borders()
                     .iter()
-                    .map(|border| -> Result<Line<Framebuffer>> {
+                    .map(|border| -> Result<Line<Subpixel>> {
                         border
                             .iter()
                             .map(|point| {
-                                Point::<Framebuffer>::new(
-                                    (WINDOW_WIDTH as Universal * (point.x() - self.min_point.x())
-                                        / (self.max_point.x() - self.min_point.x()))
-                                    .round() as Framebuffer,
-                                    (WINDOW_HEIGHT as Universal * (point.y() - self.min_point.y())
-                                        / (self.max_point.y() - self.min_point.y()))
-                                    .round() as Framebuffer,
+                                let relative = *point - self.min_point;
+                                let view = self.max_point - self.min_point;
+                                Point::new_unchecked(
+                                    WINDOW_WIDTH as Universal * relative.x() / view.x(),
+                                    WINDOW_HEIGHT as Universal * relative.y() / view.y(),
                                 )
+                                .to_subpixel()
                                 .context(format!(
                                     "Mapping of the point in universal coords '{:?}' to FB",
                                     point
                                 ))
                             })
-                            .collect::<Result<Line<Framebuffer>>>()
+                            .collect::<Result<Line<Subpixel>>>()
                     })
-                    .collect::<Result<Vec<Line<Framebuffer>>>>()
+                    .collect::<Result<Vec<Line<Subpixel>>>>()
                     .context("Wrong mapping from universal coordinates to framebuffer")?;
-                Ok(polygon.new_copy_attributes::<Framebuffer>(fb_borders))
+                Ok(polygon.new_copy_attributes::<Subpixel>(fb_borders))
             })
             .collect()
     }
 
-    fn clip_car(&self, car: &Car) -> Car {
-        let pre_max_ratio = 1.0 / WINDOW_WIDTH as Universal;
-        let pre_max_width = (self.max_point.x() - self.min_point.x()) * pre_max_ratio;
-        let pre_max_height = (self.max_point.y() - self.min_point.y()) * pre_max_ratio;
-        car.iter()
-            .fold(Vec::with_capacity(car.len()), |mut clipped_polys, poly| {
+    /// The part of the car's (untransformed) coordinates that ends up in the window, or `None` if
+    /// the model transform can't be undone.
+    fn view_in_car(&self) -> Option<BoundingBox> {
+        let inverse = self.model.to_matrix().invert().ok()?;
+        Some(BoundingBox::new(self.min_point, self.max_point).transformed(&inverse))
+    }
+
+    /// Area the car gets clipped to, borders included.
+    fn clip_bounds(&self) -> BoundingBox {
+        BoundingBox::new(self.min_point, self.max_point)
+    }
+
+    /// Places the car like `clip_car()`, but clips its borders as open strokes, so a border that
+    /// leaves the window and comes back turns into separate lines. Every polygon is kept, even
+    /// if nothing of it is left.
+    fn clip_strokes<'a>(&self, car: impl IntoIterator<Item = &'a Polygon<Universal>>) -> Car {
+        let model = self.model.to_matrix();
+        let bounds = self.clip_bounds();
+        car.into_iter()
+            .filter(|poly| self.show_hidden || !poly.is_hidden())
+            .map(|poly| {
+                let borders = poly
+                    .get_borders()
+                    .iter()
+                    .flat_map(|border| {
+                        let mut placed = border.clone();
+                        placed.transform(&model);
+                        placed.clip_stroke(&bounds)
+                    })
+                    .collect();
+                poly.new_copy_attributes(borders)
+            })
+            .collect()
+    }
+
+    /// Places the car (or mask) in the scene with the model transform and clips it to the window.
+    fn clip_car<'a>(&self, car: impl IntoIterator<Item = &'a Polygon<Universal>>) -> Car {
+        let model = (!self.model.is_identity()).then(|| self.model.to_matrix());
+        let bounds = self.clip_bounds();
+        car.into_iter()
+            .filter(|poly| self.show_hidden || !poly.is_hidden())
+            .fold(Vec::new(), |mut clipped_polys, poly| {
                 //println!("id: {}", poly.id());
+                if poly.get_stroke_color().is_some() {
+                    // The bridges Sutherland–Hodgman leaves along the window's edges show up next
+                    // to the stroke, so outlined polygons get traced instead
+                    let placed = match &model {
+                        Some(matrix) => poly.new_copy_attributes(
+                            poly.get_borders()
+                                .iter()
+                                .map(|border| {
+                                    let mut placed = border.clone();
+                                    placed.transform(matrix);
+                                    placed
+                                })
+                                .collect(),
+                        ),
+                        None => poly.clone(),
+                    };
+                    let borders = placed
+                        .clip_to_box(&bounds)
+                        .iter()
+                        .flat_map(|region| region.get_borders().clone())
+                        .collect();
+                    clipped_polys.push(poly.new_copy_attributes(borders));
+                    return clipped_polys;
+                }
+
                 let borders = poly.get_borders();
                 let borders = borders.iter().fold(
                     Vec::with_capacity(borders.len()),
                     |mut clipped_borders: Vec<Line<Universal>>,
                      border: &Line<Universal>|
                      -> Vec<Line<Universal>> {
+                        let placed;
+                        let border = match &model {
+                            Some(matrix) => {
+                                placed = {
+                                    let mut placed = border.clone();
+                                    placed.transform(matrix);
+                                    placed
+                                };
+                                &placed
+                            }
+                            None => border,
+                        };
                         let clipped_border = border
                             .clip_border(
-                                self.max_point.x() - pre_max_width,
+                                bounds.max().x(),
                                 self,
                                 intersection_vertical,
                                 Self::inside_max_x_edge,
                             )
                             .clip_border(
-                                self.max_point.y() - pre_max_height,
+                                bounds.max().y(),
                                 self,
                                 intersection_horizontal,
                                 Self::inside_max_y_edge,
@@ -391,10 +840,9 @@ impl Window {
                         clipped_borders
                     },
                 );
-                // This will remove the polygons whose borders are all fully out of frame
-                if !borders.is_empty() {
-                    clipped_polys.push(poly.new_copy_attributes(borders));
-                }
+                // Polygons fully out of frame are kept (without borders), so that the output lines
+                // up with `clip_strokes()`
+                clipped_polys.push(poly.new_copy_attributes(borders));
                 clipped_polys
             })
     }
@@ -413,10 +861,66 @@ impl Window {
         point.y() >= edge
     }
     fn inside_max_x_edge(&self, point: Point<Universal>, edge: Universal) -> bool {
-        point.x() < edge
+        point.x() <= edge
     }
     fn inside_max_y_edge(&self, point: Point<Universal>, edge: Universal) -> bool {
-        point.y() < edge
+        point.y() <= edge
+    }
+}
+
+/// The view the window starts at: the whole world, or the square the car was imported into if
+/// the world has no bounds, cut down to `aspect` (width over height) around its center.
+fn home_view(world: &WorldBounds, aspect: Universal) -> BoundingBox {
+    let bounds = world.as_box().unwrap_or_else(|| {
+        BoundingBox::new(
+            Point::new_unchecked(0.0, 0.0),
+            Point::new_unchecked(SCENE_SIZE as Universal, SCENE_SIZE as Universal),
+        )
+    });
+    let (width, height) = if bounds.width() > bounds.height() * aspect {
+        (bounds.height() * aspect, bounds.height())
+    } else {
+        (bounds.width(), bounds.width() / aspect)
+    };
+    let half = Point::new_unchecked(width / 2.0, height / 2.0);
+    BoundingBox::new(
+        world.clamp(bounds.center() - half),
+        world.clamp(bounds.center() + half),
+    )
+}
+
+/// Replaces each polygon with one closed border per triangle of its triangulation.
+fn triangles_car(car: &Car) -> Car {
+    car.iter()
+        .map(|polygon| {
+            polygon.new_copy_attributes(
+                polygon
+                    .triangles()
+                    .iter()
+                    .map(|[a, b, c]| vec![*a, *b, *c, *a])
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+/// Parameter t along the segment from `a` to `b` (coordinates along one axis) where it reaches
+/// `edge`, clamped to the segment. A segment (nearly) parallel to the edge, which the clipper
+/// only passes in when both ends are within rounding of it, gives the end closest to the edge.
+fn crossing_parameter(a: Universal, b: Universal, edge: Universal) -> Universal {
+    let d = b - a;
+    if d.abs() <= Universal::EPSILON * a.abs().max(b.abs()).max(1.0) {
+        return if (edge - a).abs() <= (edge - b).abs() {
+            0.0
+        } else {
+            1.0
+        };
+    }
+    let t = (edge - a) / d;
+    if t.is_nan() {
+        0.0
+    } else {
+        t.clamp(0.0, 1.0)
     }
 }
 
@@ -425,28 +929,8 @@ fn intersection_horizontal(
     p1: Point<Universal>,
     y_edge: f32,
 ) -> Point<Universal> {
-    let x = if p0.x() == p1.x() {
-        p0.x()
-    } else {
-        let m = (p1.y() - p0.y()) / (p1.x() - p0.x());
-        let b = p0.y() - m * p0.x();
-        (y_edge - b) / m
-        //let x = (y_edge - b) / m;
-        //if x > 1000.0 {
-        //    println!(
-        //        "-------\ny = mx + b\ny:{}\tm:{}\tx:{}\tb:{}\np0:{:?}\tp1:{:?}\n-------",
-        //        y_edge, m, x, b, p0, p1
-        //    );
-        //}
-        //if x.is_nan() {
-        //    println!(
-        //        "-------\ny = mx + b\ny:{}\tm:{}\tx:{}\tb:{}\np0:{:?}\tp1:{:?}\n-------",
-        //        y_edge, m, x, b, p0, p1
-        //    );
-        //}
-        //x
-    };
-    Point::<Universal>::new_unchecked(x, y_edge)
+    let t = crossing_parameter(p0.y(), p1.y(), y_edge);
+    Point::<Universal>::new_unchecked(p0.lerp(p1, t).x(), y_edge)
 }
 
 fn intersection_vertical(
@@ -454,16 +938,29 @@ fn intersection_vertical(
     p1: Point<Universal>,
     x_edge: f32,
 ) -> Point<Universal> {
-    let m = (p1.y() - p0.y()) / (p1.x() - p0.x());
-    let b = p0.y() - m * p0.x();
-    Point::<Universal>::new_unchecked(x_edge, m * x_edge + b)
+    let t = crossing_parameter(p0.x(), p1.x(), x_edge);
+    Point::<Universal>::new_unchecked(x_edge, p0.lerp(p1, t).y())
+}
+
+/// First pixel (along either axis) whose center is at or after `value`, given in subpixel units.
+fn first_center(value: f32) -> i32 {
+    (value / SUBPIXEL_ONE as f32 - 0.5).ceil() as i32
+}
+
+/// Plots pixel (x, y) moved inside the window, since lines clipped to the window's far edges
+/// run along no pixel of it.
+fn plot_clamped<S: PixelTarget>(screen: &mut S, x: i32, y: i32) {
+    screen.plot_pixel(
+        x.clamp(0, WINDOW_WIDTH as i32 - 1) as Framebuffer,
+        y.clamp(0, WINDOW_HEIGHT as i32 - 1) as Framebuffer,
+    );
 }
 
-/// Implementation of the bresenham method to draw lines
-fn bresenham_line(screen: &mut ScreenContextManager, segment: &Segment) {
+/// Implementation of the bresenham method to draw lines, sampling the segment at the center of
+/// every pixel column (or row, for steep segments) it spans.
+fn bresenham_line<S: PixelTarget>(screen: &mut S, segment: &Segment) {
     // Check for which type of octant we're on
-    if (segment.y1 as i32 - segment.y0 as i32).abs() < (segment.x1 as i32 - segment.x0 as i32).abs()
-    {
+    if (segment.y1 - segment.y0).abs() < (segment.x1 - segment.x0).abs() {
         // Octants 1, 4, 5, 8
         if segment.x1 > segment.x0 {
             // Octants 1, 8
@@ -484,50 +981,70 @@ fn bresenham_line(screen: &mut ScreenContextManager, segment: &Segment) {
         }
     }
 }
-fn bresenham_horizontal(screen: &mut ScreenContextManager, x0: u32, y0: u32, x1: u32, y1: u32) {
-    let dy = y1 as i32 - y0 as i32;
-    // Check for decreasing horizontal quadrants (5, 8)
-    let (yi, dy) = if dy < 0 { (-1, -dy) } else { (1, dy) };
-
-    let dx = (x1 - x0) as i32;
 
-    let delta_h = 2 * dy; // delta_h = horizontal movement
-    let delta_d = 2 * (dy - dx); // delta_d = diagonal movement
-
-    let mut y = y0 as i32;
-    let mut d = 2 * dy - dx;
-
-    for x in x0..x1 {
-        screen.plot_pixel(x, y as u32);
-        if d > 0 {
-            y += yi;
-            d += delta_d;
-        } else {
-            d += delta_h;
-        }
-    }
+fn bresenham_horizontal<S: PixelTarget>(
+    screen: &mut S,
+    x0: Subpixel,
+    y0: Subpixel,
+    x1: Subpixel,
+    y1: Subpixel,
+) {
+    bresenham_steps((x0, y0), (x1, y1), |x, y| plot_clamped(screen, x, y));
 }
 
-fn bresenham_vertical(screen: &mut ScreenContextManager, x0: u32, y0: u32, x1: u32, y1: u32) {
-    let dx = x1 as i32 - x0 as i32;
-    // Check for backwards vertical quadrants (3, 6)
-    let (xi, dx) = if dx < 0 { (-1, -dx) } else { (1, dx) };
-
-    let dy = (y1 - y0) as i32;
-
-    let delta_v = 2 * dx; // Vertical movement
-    let delta_d = 2 * (dx - dy); // Diagonal movement
+fn bresenham_vertical<S: PixelTarget>(
+    screen: &mut S,
+    x0: Subpixel,
+    y0: Subpixel,
+    x1: Subpixel,
+    y1: Subpixel,
+) {
+    bresenham_steps((y0, x0), (y1, x1), |y, x| plot_clamped(screen, x, y));
+}
 
-    let mut x = x0 as i32;
-    let mut d = 2 * dx - dy;
+/// Walks a segment from `(a0, b0)` to `(a1, b1)` along its major axis `a` (with `a0 <= a1`, and
+/// `b` moving no more than `a`), calling `plot` with the pixel the segment is in at every pixel
+/// center in [a0, a1). Like in the classic algorithm the minor axis is kept as a whole pixel and
+/// an error term, only measured from subpixel endpoints. A segment that doesn't reach any pixel
+/// center plots the pixel holding its midpoint instead, so that it doesn't vanish.
+fn bresenham_steps(
+    (a0, b0): (Subpixel, Subpixel),
+    (a1, b1): (Subpixel, Subpixel),
+    mut plot: impl FnMut(i32, i32),
+) {
+    let (da, db) = ((a1 - a0) as i64, (b1 - b0) as i64);
+    let one = SUBPIXEL_ONE as i64;
+    let first = first_center(a0 as f32);
+    let last = first_center(a1 as f32);
+    if first == last {
+        let pixel_of_midpoint =
+            |p: Subpixel, q: Subpixel| (p as i64 + q as i64).div_euclid(2 * one);
+        plot(
+            pixel_of_midpoint(a0, a1) as i32,
+            pixel_of_midpoint(b0, b1) as i32,
+        );
+        return;
+    }
 
-    for y in y0..y1 {
-        screen.plot_pixel(x as u32, y);
-        if d > 0 {
-            x += xi;
-            d += delta_d;
-        } else {
-            d += delta_v;
+    // b at the first center is b0 + (center - a0) * db / da, so in pixels it's this numerator
+    // over `denominator`
+    let center = first as i64 * one + one / 2;
+    let numerator = b0 as i64 * da + (center - a0 as i64) * db;
+    let denominator = da * one;
+    let mut b = numerator.div_euclid(denominator);
+    let mut error = numerator.rem_euclid(denominator);
+
+    // Moving one pixel along `a` moves `b` by db / da pixels, which is never more than one
+    let step = db * one;
+    for a in first..last {
+        plot(a, b as i32);
+        error += step;
+        if error >= denominator {
+            b += 1;
+            error -= denominator;
+        } else if error < 0 {
+            b -= 1;
+            error += denominator;
         }
     }
 }
@@ -552,16 +1069,28 @@ impl PartialEq for Segment {
 
 impl Eq for Segment {}
 
+/// Rows of pixel centers that a border crosses, as the lowest and highest. Borders cover the
+/// centers in [y_min, y_max), so where two of them meet the center is counted only once.
+fn border_rows(border: &Segment) -> (i32, i32) {
+    (
+        first_center(border.y_min() as f32),
+        first_center(border.y_max() as f32) - 1,
+    )
+}
+
 struct ScanlineData<'a> {
+    /// Borders that cross some row of pixel centers, sorted from the highest y_max down
     borders: Vec<Segment>,
+    /// How much each border's x changes from one row to the one above it, in subpixel units
     deltas: Vec<f32>,
     active_borders: Vec<usize>,
-    polygon: &'a Polygon<Framebuffer>,
+    polygon: &'a Polygon<Subpixel>,
+    /// x of each border at the center of the row being painted, in subpixel units
     next_intersects: Vec<f32>,
 }
 
 impl<'a> ScanlineData<'a> {
-    fn new(fb_polygon: &'a Polygon<Framebuffer>) -> Result<Self> {
+    fn new(fb_polygon: &'a Polygon<Subpixel>) -> Result<Self> {
         let mut borders: Vec<Segment> = fb_polygon
             .get_borders()
             .iter()
@@ -574,115 +1103,81 @@ impl<'a> ScanlineData<'a> {
                         y0: segment[0].y(),
                         y1: segment[1].y(),
                     })
-                    //filter out horizontal lines
-                    .filter(|segment| segment.y0 != segment.y1)
+                    // filter out the segments between two rows of centers (horizontal ones too)
+                    .filter(|segment| {
+                        let (bottom, top) = border_rows(segment);
+                        bottom <= top
+                    })
             })
             .collect::<Vec<Segment>>();
 
+        if borders.is_empty() {
+            return Err(anyhow!("Border segments was somehow empty"));
+        }
+
         borders.sort_unstable();
         borders.reverse();
 
         let deltas: Vec<f32> = borders
             .iter()
             .map(|border| {
-                (border.x1 as f32 - border.x0 as f32) / (border.y1 as f32 - border.y0 as f32)
+                SUBPIXEL_ONE as f32 * (border.x1 - border.x0) as f32
+                    / (border.y1 - border.y0) as f32
             })
             .collect();
 
-        //for delta in deltas.iter() {
-        //    if delta.is_infinite() || delta.is_nan() {
-        //        println!("The bad thing is happening: {}", delta);
-        //    }
-        //}
-
-        if borders.is_empty() {
-            return Err(anyhow!("Border segments was somehow empty"));
-        }
-
-        let mut active_borders = Vec::new();
-        let y_max = borders.first().expect("En método ScanlineData::new() de alguna manera el vector de bordes no tiene elementos").y_max();
-        for (i, possibly_active_border) in borders.iter().enumerate() {
-            if possibly_active_border.y_max() == y_max {
-                active_borders.push(i)
-            } else {
-                break;
-            }
-        }
         let next_intersects = borders
             .iter()
-            .map(|border| border.x_of_y_max() as f32)
+            .map(|border| {
+                let top = border_rows(border).1;
+                border.x_at((top as f32 + 0.5) * SUBPIXEL_ONE as f32)
+            })
             .collect();
 
         Ok(ScanlineData {
             borders,
             deltas,
-            active_borders,
+            active_borders: Vec::new(),
             polygon: fb_polygon,
             next_intersects,
         })
     }
 }
 
-fn scanline(screen: &mut ScreenContextManager, mut sl_data: ScanlineData) {
-    let mut scanline = sl_data
+/// Fills the polygon by painting, row by row, the pixels whose centers are inside it according
+/// to its fill rule.
+fn scanline<S: PixelTarget>(screen: &mut S, mut sl_data: ScanlineData) {
+    let rows: Vec<(i32, i32)> = sl_data.borders.iter().map(border_rows).collect();
+    // Borders going down the screen wind one way around the inside, and the ones going up the
+    // other way
+    let windings: Vec<i32> = sl_data
         .borders
+        .iter()
+        .map(|border| if border.y1 > border.y0 { 1 } else { -1 })
+        .collect();
+    let fill_rule = sl_data.polygon.get_fill_rule();
+
+    let mut scanline = rows
         .first()
         .expect("En método scanline() por alguna razón el vector de bordes está vacío")
-        .y_max() as i32;
-
-    // We initialize intersections as a mutable variable outside the loop so that only one
-    // allocation has to ever be done for it
-    let mut intersections: Vec<Framebuffer> = Vec::with_capacity(sl_data.borders.len());
-
-    let y_maxs: Vec<Framebuffer> = sl_data.borders.iter().map(|s| s.y_max()).collect();
-    let y_mins: Vec<Framebuffer> = sl_data.borders.iter().map(|s| s.y_min()).collect();
-
-    let y_min = y_mins
+        .1;
+    let y_min = rows
         .iter()
+        .map(|(bottom, _)| *bottom)
         .min()
-        .expect("El vecto de ymins tampoco debería estar vacío");
-
-    // Lugares donde dos segmentos se juntan y no son max/min
-    let count_as_one: Vec<Point<Framebuffer>> = sl_data
-        .borders
-        .windows(2)
-        .filter_map(|window| {
-            if window[0].x1 == window[1].x0 && window[0].y1 == window[1].y0 {
-                match (
-                    window[0].y0.cmp(&window[0].y1),
-                    window[1].y1.cmp(&window[1].y0),
-                ) {
-                    (Ordering::Less, Ordering::Less) => None,
-                    (Ordering::Greater, Ordering::Greater) => None,
-                    (Ordering::Less, Ordering::Greater) => Some(
-                        Point::<Framebuffer>::new(window[0].x1, window[0].y1).expect("why god why"),
-                    ),
-                    (Ordering::Greater, Ordering::Less) => Some(
-                        Point::<Framebuffer>::new(window[0].x1, window[0].y1).expect("please istg"),
-                    ),
-                    (_, _) => {
-                        println!("Weird bad thing!!");
-                        None
-                    }
-                }
-            } else {
-                None
-            }
-        })
-        .collect();
+        .expect("El vector de filas tampoco debería estar vacío");
 
-    // scanline es i32 para que pueda valer menos de 0, ya que a veces y_min=0
-    while scanline >= *y_min as i32 {
-        // Activar bordes
-        let start_i = *(sl_data.active_borders.last().unwrap_or(&0_usize));
-        sl_data.active_borders.extend(
-            y_maxs[start_i..]
-                .iter()
-                .zip(start_i..sl_data.borders.len())
-                .take_while(|(ymax, _)| **ymax >= scanline as Framebuffer)
-                .filter(|(ymax, _)| **ymax == scanline as Framebuffer)
-                .map(|(_, i)| i),
-        );
+    // We initialize intersections as a mutable variable outside the loop so that only one
+    // allocation has to ever be done for it
+    let mut intersections: Vec<(f32, i32)> = Vec::with_capacity(sl_data.borders.len());
+    let mut next_border = 0;
+
+    while scanline >= y_min {
+        // Activar bordes, que están ordenados de la fila más alta a la más baja
+        while next_border < rows.len() && rows[next_border].1 >= scanline {
+            sl_data.active_borders.push(next_border);
+            next_border += 1;
+        }
 
         // Calcular intersecciones
         intersections.clear();
@@ -690,66 +1185,162 @@ fn scanline(screen: &mut ScreenContextManager, mut sl_data: ScanlineData) {
             sl_data
                 .active_borders
                 .iter()
-                .map(|i| sl_data.next_intersects[*i].round() as Framebuffer),
+                .map(|i| (sl_data.next_intersects[*i], windings[*i])),
         );
 
         // Ordenamos las intersecciones
-        intersections.sort_unstable();
+        intersections.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
 
-        let mut paint = true;
-        // Pintamos de forma alternada
+        // Pintamos entre intersecciones donde la regla de relleno dice que estamos adentro
+        let mut winding = 0;
         for segment in intersections.windows(2) {
-            if paint {
-                paint_scanline(screen, scanline as Framebuffer, segment[0], segment[1]);
-            }
-            //paint = !paint;
-            if segment[0] == segment[1] {
-                // if it's not a "local min" or "local max" then we flip paint back
-                let x = segment[0];
-                if x >= 1000 || scanline as Framebuffer >= 1000 {
-                    println!("X: {}\t Y: {}", x, scanline);
-                }
-                //paint = !paint;
-
-                if count_as_one.contains(
-                    &Point::<Framebuffer>::new(x, scanline as Framebuffer).expect("uggo mistake"),
-                ) {
-                    //println!("X: {}\t Y: {}", x, scanline);
-                    //paint = !paint
-                }
+            winding += segment[0].1;
+            if fill_rule.is_inside(winding) {
+                paint_scanline(screen, scanline, segment[0].0, segment[1].0);
             }
         }
 
         // Desactivamos bordes
-        sl_data
-            .active_borders
-            .retain(|i| y_mins[*i] < scanline as Framebuffer);
+        sl_data.active_borders.retain(|i| rows[*i].0 < scanline);
 
         // Calculamos siguientes intersecciones
         for i in sl_data.active_borders.iter() {
-            let new_x =
-                (sl_data.next_intersects[*i] - sl_data.deltas[*i]).min((WINDOW_WIDTH - 1) as f32);
-            sl_data.next_intersects[*i] = new_x;
+            sl_data.next_intersects[*i] -= sl_data.deltas[*i];
         }
 
         scanline -= 1;
     }
 }
 
-enum SharedVerticeType {
-    Max,
-    Min,
-    Other,
-    Init,
+/// Paints the pixels of row `y` whose centers are in [x0, x1), given in subpixel units.
+fn paint_scanline<S: PixelTarget>(screen: &mut S, y: i32, x0: f32, x1: f32) {
+    let from = first_center(x0).max(0);
+    let to = first_center(x1).min(WINDOW_WIDTH as i32);
+    for x in from..to {
+        screen.plot_pixel(x as Framebuffer, y as Framebuffer);
+    }
 }
 
-fn paint_scanline(
-    screen: &mut ScreenContextManager,
-    y: Framebuffer,
-    x0: Framebuffer,
-    x1: Framebuffer,
-) {
-    for x in x0..x1 {
-        screen.plot_pixel(x, y);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::fixtures::point;
+
+    #[test]
+    fn snapping_rounds_to_the_closest_step() {
+        let snap = Some(Angle::Degrees(15.0));
+        let degrees = |target: Universal| snap_angle(target.to_radians(), snap).to_degrees();
+        assert!((degrees(20.0) - 15.0).abs() < 1e-4);
+        assert!((degrees(23.0) - 30.0).abs() < 1e-4);
+        assert!((degrees(-8.0) + 15.0).abs() < 1e-4);
+        assert!(degrees(7.0).abs() < 1e-4);
+        // Without snapping, or with a step that can't be rounded to, the angle is kept
+        assert_eq!(snap_angle(0.3, None), 0.3);
+        assert_eq!(snap_angle(0.3, Some(Angle::Radians(0.0))), 0.3);
+    }
+
+    #[test]
+    fn mouse_pivots_are_at_the_pixel_center() {
+        let (width, height) = (WINDOW_WIDTH as Universal, WINDOW_HEIGHT as Universal);
+        // Two units of the scene per pixel
+        let (min, max) = (
+            point(100.0, 200.0),
+            point(100.0 + 2.0 * width, 200.0 + 2.0 * height),
+        );
+        assert_eq!(pixel_center(min, max, 0, 0), point(101.0, 201.0));
+        assert_eq!(
+            pixel_center(min, max, WINDOW_WIDTH as i32 - 1, WINDOW_HEIGHT as i32 - 1),
+            point(99.0 + 2.0 * width, 199.0 + 2.0 * height)
+        );
+    }
+
+    #[test]
+    fn pivots_cycle_through_the_ones_without_a_point() {
+        let mut pivot = Pivot::Point(point(1.0, 2.0));
+        let mut seen = Vec::new();
+        for _ in 0..5 {
+            pivot = pivot.next();
+            seen.push(format!("{:?}", pivot));
+        }
+        assert_eq!(
+            seen,
+            [
+                "SceneCenter",
+                "WindowCenter",
+                "CarCentroid",
+                "Mouse",
+                "SceneCenter"
+            ]
+        );
+    }
+
+    #[test]
+    fn crossings_land_on_the_edge() {
+        let crossing = intersection_vertical(point(-10.0, 0.0), point(10.0, 20.0), 0.0);
+        assert_eq!(crossing, point(0.0, 10.0));
+        let crossing = intersection_horizontal(point(1.0, 1000.5), point(3.0, 999.5), 1000.0);
+        assert_eq!(crossing, point(2.0, 1000.0));
+    }
+
+    #[test]
+    fn crossings_stay_within_the_segment() {
+        // Rounding can put an end a hair past the edge, which mustn't extrapolate the segment
+        assert_eq!(crossing_parameter(0.0, 10.0, -1e-6), 0.0);
+        assert_eq!(crossing_parameter(0.0, 10.0, 10.000001), 1.0);
+        let crossing = intersection_vertical(point(5.0, 0.0), point(5.0 + 1e-7, 100.0), 5.0);
+        assert!(crossing.is_finite());
+        assert!(
+            crossing.y() == 0.0 || crossing.y() == 100.0,
+            "{:?}",
+            crossing
+        );
+    }
+
+    #[test]
+    fn parallel_segments_give_the_closest_end() {
+        assert_eq!(crossing_parameter(3.0, 3.0, 3.0), 0.0);
+        assert_eq!(crossing_parameter(3.0, 3.0, 5.0), 0.0);
+        // One ulp apart
+        assert_eq!(crossing_parameter(1e6, 1e6 + 0.0625, 1e6 + 0.0625), 1.0);
+        assert_eq!(crossing_parameter(Universal::NAN, 1.0, 0.5), 0.0);
+    }
+
+    fn steps(a: (Subpixel, Subpixel), b: (Subpixel, Subpixel)) -> Vec<(i32, i32)> {
+        let mut plotted = Vec::new();
+        bresenham_steps(a, b, |a, b| plotted.push((a, b)));
+        plotted
+    }
+
+    #[test]
+    fn steps_plot_the_pixel_at_each_center() {
+        let one = SUBPIXEL_ONE;
+        assert_eq!(
+            steps((0, 0), (4 * one, 2 * one)),
+            vec![(0, 0), (1, 0), (2, 1), (3, 1)]
+        );
+        // Going up along the minor axis
+        assert_eq!(
+            steps((0, 2 * one), (4 * one, 0)),
+            vec![(0, 1), (1, 1), (2, 0), (3, 0)]
+        );
+        // Centers only count up to the end, which is left for the next segment
+        assert_eq!(steps((one / 2, 0), (one * 5 / 2, 0)), vec![(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn segments_between_centers_plot_their_midpoint() {
+        let one = SUBPIXEL_ONE;
+        assert_eq!(steps((10, 10), (100, 50)), vec![(0, 0)]);
+        // Between the centers of pixels 1 and 2, with its midpoint in 2
+        assert_eq!(
+            steps((one + 200, 3 * one), (2 * one + 100, 3 * one + 10)),
+            vec![(2, 3)]
+        );
+        // Points too
+        assert_eq!(
+            steps((5 * one + 1, 7 * one), (5 * one + 1, 7 * one)),
+            vec![(5, 7)]
+        );
+        assert_eq!(steps((-10, -10), (-10, -10)), vec![(-1, -1)]);
     }
 }